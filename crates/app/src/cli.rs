@@ -1,9 +1,46 @@
+use std::{fs, path::PathBuf};
+
+use adrastos_core::{
+    config::Config,
+    db::postgres::{DatabaseType, Databases},
+    entities::{Project, SigningKey, System},
+    error::Error,
+    migrations::Migrations,
+};
 use clap::{Parser, Subcommand};
+use deadpool_postgres::tokio_postgres;
+use tracing::{error, info};
+use tracing_unwrap::ResultExt;
 
 #[derive(Subcommand, PartialEq)]
 pub enum Command {
     /// Apply all pending migrations
     Migrate,
+    /// Manage the keys that sign tokens
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+}
+
+#[derive(Subcommand, PartialEq)]
+pub enum KeysCommand {
+    /// List the signing keys
+    List,
+    /// Add a signing key, it signs every new token
+    Generate {
+        /// Import an RSA key from a PKCS#8 PEM file instead of generating an Ed25519 key
+        #[arg(long)]
+        rsa_pem: Option<PathBuf>,
+    },
+    /// Add a signing key and retire every key except the one it replaces
+    Rotate {
+        /// Import an RSA key from a PKCS#8 PEM file instead of generating an Ed25519 key
+        #[arg(long)]
+        rsa_pem: Option<PathBuf>,
+    },
+    /// Retire a key, tokens signed with it stop being accepted
+    Retire { kid: String },
 }
 
 #[derive(Parser)]
@@ -12,3 +49,125 @@ pub struct Cli {
     #[command(subcommand)]
     pub command: Option<Command>,
 }
+
+fn new_key(rsa_pem: Option<PathBuf>) -> Result<SigningKey, Error> {
+    match rsa_pem {
+        Some(path) => SigningKey::import_rsa(
+            &fs::read_to_string(path)
+                .map_err(|err| Error::BadRequest(format!("Unable to read the PEM file: {err}")))?,
+        ),
+        None => SigningKey::generate(),
+    }
+}
+
+async fn run_queries(
+    db: &deadpool_postgres::Pool,
+    queries: &[String],
+    version: &str,
+) -> Result<(), Error> {
+    let mut conn = db.get().await.unwrap_or_log();
+    let migration_error = |err: tokio_postgres::Error| {
+        error!(error = ?err);
+        Error::InternalServerError(format!("Failed to apply migration {version}"))
+    };
+
+    let transaction = conn.transaction().await.map_err(migration_error)?;
+    for query in queries {
+        info!("Query: {query}");
+        transaction
+            .execute(query.as_str(), &[])
+            .await
+            .map_err(migration_error)?;
+    }
+    transaction.commit().await.map_err(migration_error)?;
+
+    Ok(())
+}
+
+/// Applies the migrations newer than the version the system database was migrated to, to it
+/// and every project database
+pub async fn migrate(
+    databases: &Databases,
+    config: &Config,
+    db: &deadpool_postgres::Pool,
+) -> Result<(), Error> {
+    let conn = db.get().await.unwrap_or_log();
+    let previous_version = conn
+        .query_one(&System::get_version(), &[])
+        .await
+        .ok()
+        .and_then(|row| row.get::<_, Option<String>>(0))
+        .unwrap_or(env!("CARGO_PKG_VERSION").into());
+
+    let migrations = Migrations::all_from(&previous_version);
+    let Some(latest) = migrations.last() else {
+        info!("No pending migrations");
+        return Ok(());
+    };
+
+    let projects = Project::find().all(db).await?;
+    for migration in &migrations {
+        let version = migration.version.to_string();
+        info!("Migration: {version}");
+
+        run_queries(db, &migration.system, &version).await?;
+        for project in &projects {
+            let project_db = databases
+                .get(&DatabaseType::Project(project.id.clone()), config)
+                .await;
+            run_queries(&project_db, &migration.project, &version).await?;
+        }
+    }
+
+    conn.execute(&System::set_version(&latest.version.to_string()), &[])
+        .await
+        .map_err(|err| {
+            error!(error = ?err);
+            Error::InternalServerError("Failed to save the migrated version".into())
+        })?;
+
+    Ok(())
+}
+
+pub async fn keys(command: KeysCommand, db: &deadpool_postgres::Pool) -> Result<(), Error> {
+    match command {
+        KeysCommand::List => {
+            for key in SigningKey::find().all(db).await? {
+                let status = match key.retired_at {
+                    Some(retired_at) => format!("retired at {retired_at}"),
+                    None => "active".into(),
+                };
+                info!(
+                    "{} ({}) created at {}, {status}",
+                    key.id, key.algorithm, key.created_at
+                );
+            }
+        }
+        KeysCommand::Generate { rsa_pem } => {
+            let key = new_key(rsa_pem)?;
+            key.create(db).await?;
+            info!("Generated signing key {}", key.id);
+        }
+        KeysCommand::Rotate { rsa_pem } => {
+            let key = new_key(rsa_pem)?;
+            key.create(db).await?;
+            info!("Generated signing key {}", key.id);
+
+            // The previous key keeps verifying the tokens it already signed
+            for old in SigningKey::active(db).await?.into_iter().skip(2) {
+                old.retire(db).await?;
+                info!("Retired signing key {}", old.id);
+            }
+        }
+        KeysCommand::Retire { kid } => {
+            SigningKey::find_by_id(&kid)
+                .one(db)
+                .await?
+                .retire(db)
+                .await?;
+            info!("Retired signing key {kid}");
+        }
+    }
+
+    Ok(())
+}
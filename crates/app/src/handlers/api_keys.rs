@@ -0,0 +1,136 @@
+use adrastos_core::{
+    entities::{ApiKey, ApiKeyScope, TeamRole, UpdateApiKey},
+    error::Error,
+    id::Id,
+};
+use axum::{
+    extract::Path,
+    response::IntoResponse,
+    routing::{delete, get, patch, post},
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+
+use crate::{
+    middleware::extractors::{Member, ProjectDatabase},
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateBody {
+    name: String,
+    scopes: Vec<ApiKeyScope>,
+    #[serde(default)]
+    bypass_permissions: bool,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateBody {
+    name: Option<String>,
+    scopes: Option<Vec<ApiKeyScope>>,
+    bypass_permissions: Option<bool>,
+    #[serde(default, deserialize_with = "nullable")]
+    expires_at: Option<Option<DateTime<Utc>>>,
+}
+
+/// Tells an explicit `null`, which removes the expiry, apart from a missing key
+fn nullable<'de, D>(deserializer: D) -> Result<Option<Option<DateTime<Utc>>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list))
+        .route("/create", post(create))
+        .route("/update/:id", patch(update))
+        .route("/delete/:id", delete(remove))
+}
+
+pub async fn list(
+    Member(member): Member,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    let keys = ApiKey::find().all(&db).await?;
+    Ok(Json(keys))
+}
+
+/// The plaintext key is only ever returned here
+pub async fn create(
+    Member(member): Member,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<CreateBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    if body
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(Error::BadRequest("The expiry must be in the future".into()));
+    }
+
+    let key = ApiKey::generate_key();
+    let api_key = ApiKey {
+        id: Id::new().to_string(),
+        name: body.name,
+        key_hash: ApiKey::hash(&key),
+        prefix: key[..ApiKey::PREFIX.len() + 6].to_string(),
+        scopes: body.scopes,
+        bypass_permissions: body.bypass_permissions,
+        expires_at: body.expires_at,
+        last_used_at: None,
+        created_at: Utc::now(),
+        updated_at: None,
+    };
+
+    api_key.create(&db).await?;
+    Ok(Json(json!({ "apiKey": api_key, "key": key })))
+}
+
+pub async fn update(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<UpdateBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    ApiKey::find_by_id(&id)
+        .one(&db)
+        .await?
+        .update(
+            &db,
+            UpdateApiKey {
+                name: body.name,
+                scopes: body.scopes,
+                bypass_permissions: body.bypass_permissions,
+                expires_at: body.expires_at,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    let api_key = ApiKey::find_by_id(&id).one(&db).await?;
+    Ok(Json(api_key))
+}
+
+pub async fn remove(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    ApiKey::find_by_id(&id).one(&db).await?.delete(&db).await?;
+    Ok(Json(Value::Null))
+}
@@ -21,11 +21,16 @@ use serde_json::{json, Value};
 use tower_sessions::Session;
 
 use crate::{
-    middleware::extractors::{AnyUser, Config, Database, Project},
+    middleware::{
+        extractors::{AnyUser, Config, Database, Device, Project},
+        rate_limit::RateLimit,
+    },
     session::SessionKey,
     state::AppState,
 };
 
+use super::limit_attempts;
+
 #[derive(Deserialize)]
 pub struct CVDRBody {
     code: String,
@@ -35,7 +40,14 @@ pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/enable", get(enable))
         .route("/confirm", post(confirm))
-        .route("/verify", post(verify))
+        .route(
+            "/verify",
+            post(verify).layer(
+                RateLimit::new("mfa-verify", 5, Duration::minutes(1))
+                    .lockout(3, Duration::minutes(5))
+                    .layer(),
+            ),
+        )
         .route("/disable", post(disable))
         .route("/codes/regenerate", post(regenerate))
 }
@@ -129,10 +141,12 @@ pub async fn confirm(
 
 pub async fn verify(
     jar: CookieJar,
+    Device(device): Device,
     session: Session,
     project: Option<Project>,
     Config(config): Config,
     Database(db): Database,
+    State(AppState { redis_pool, .. }): State<AppState>,
     Json(body): Json<CVDRBody>,
 ) -> Result<impl IntoResponse, Error> {
     let Ok(Some(retries)) = session.get::<u8>(&SessionKey::MfaRetries.to_string()).await else {
@@ -164,6 +178,16 @@ pub async fn verify(
         ));
     };
 
+    // Starting a new login resets the session's retries, so codes are also limited per user
+    limit_attempts(
+        &redis_pool,
+        &config,
+        format!("mfa:{user_id}"),
+        5,
+        Duration::minutes(15),
+    )
+    .await?;
+
     let user = entities::UserType::from(&db)
         .find_by_id(&user_id)
         .one()
@@ -187,10 +211,17 @@ pub async fn verify(
         )
         .await?
     {
+        session
+            .insert(&SessionKey::MfaRetries.to_string(), retries - 1)
+            .await
+            .map_err(|_| {
+                Error::InternalServerError("An error occurred while setting the session".into())
+            })?;
+
         return Err(Error::BadRequest("Invalid MFA code".into()));
     }
 
-    let (_, jar) = auth::authenticate(&db, &config.clone(), &user, jar).await?;
+    let (_, jar) = auth::authenticate(&db, &config.clone(), &user, device, jar).await?;
     Ok((jar, Json(user)))
 }
 
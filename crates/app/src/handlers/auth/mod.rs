@@ -1,22 +1,28 @@
+use std::collections::HashMap;
+
 use adrastos_core::{
     auth::{self, TokenType},
-    db::redis,
+    config,
+    db::{postgres::DatabaseType, redis},
     entities::{self, UserType},
     error::Error,
     id::Id,
     util,
+    webhooks::{self, WebhookEvent},
 };
 use axum::{
     extract::{Query, State},
-    response::IntoResponse,
+    http::StatusCode,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
 use axum_extra::extract::CookieJar;
 use chrono::{Duration, Utc};
 use fred::{
+    clients::RedisPool,
     interfaces::{EventInterface, KeysInterface, PubsubInterface},
-    types::Expiration,
+    types::{Expiration, SetOptions},
 };
 use lettre::{message::header::ContentType, AsyncTransport, Message};
 use serde::Deserialize;
@@ -26,7 +32,11 @@ use tower_sessions::Session;
 use tracing::{error, warn};
 
 use crate::{
-    middleware::extractors::{AnyUser, Config, Database, Mailer, ProjectDatabase, User},
+    handlers::teams,
+    middleware::{
+        extractors::{AnyUser, Config, Database, Device, Mailer, ProjectDatabase, User},
+        rate_limit::RateLimit,
+    },
     session::SessionKey,
     state::AppState,
 };
@@ -34,6 +44,9 @@ use crate::{
 pub mod mfa;
 pub mod oauth2;
 pub mod passkeys;
+pub mod password;
+pub mod passwordless;
+pub mod sessions;
 pub mod token;
 
 #[derive(Deserialize)]
@@ -44,6 +57,8 @@ pub struct RegisterBody {
     email: String,
     username: String,
     password: String,
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
 }
 
 #[derive(Deserialize)]
@@ -57,15 +72,125 @@ pub struct VerifyParams {
     token: String,
 }
 
+/// Sends an HTML email from the configured sender
+pub async fn send_email(
+    Mailer(mailer): &Mailer,
+    config: &config::Config,
+    to: &str,
+    subject: &str,
+    html: String,
+) -> Result<(), Error> {
+    let invalid = |_| Error::InternalServerError("Invalid email address".into());
+    let message = Message::builder()
+        .from(
+            format!(
+                "{} <{}>",
+                config.smtp_sender_name.clone().unwrap_or_default(),
+                config.smtp_sender_email.clone().unwrap_or_default()
+            )
+            .parse()
+            .map_err(invalid)?,
+        )
+        .to(format!("<{to}>").parse().map_err(invalid)?)
+        .subject(subject)
+        .header(ContentType::TEXT_HTML)
+        .body(html)
+        .map_err(|_| Error::InternalServerError("Unable to build email".into()))?;
+
+    mailer.send(message).await.map_err(|e| {
+        error!(error = ?e);
+        Error::InternalServerError("An error occurred while sending the email".into())
+    })?;
+
+    Ok(())
+}
+
+/// Counts an attempt under `key`, failing once more than `max` were made within `window`
+async fn limit_attempts(
+    redis_pool: &RedisPool,
+    config: &config::Config,
+    key: String,
+    max: i64,
+    window: Duration,
+) -> Result<(), Error> {
+    let key = redis::build_key(config, format!("attempts:{key}"));
+    let redis_error =
+        |_| Error::InternalServerError("An error occurred while counting attempts".into());
+
+    // Only starts the window on the first attempt
+    redis_pool
+        .set::<(), _, _>(
+            key.clone(),
+            0,
+            Some(Expiration::EX(window.num_seconds())),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await
+        .map_err(redis_error)?;
+    let attempts: i64 = redis_pool.incr(key).await.map_err(redis_error)?;
+
+    if attempts > max {
+        return Err(Error::Custom(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many attempts, try again later".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Leaves the login pending until the user passes MFA verification
+async fn require_mfa(session: &Session, user_id: String) -> Result<Response, Error> {
+    session
+        .insert(&SessionKey::LoginUserId.to_string(), user_id)
+        .await
+        .map_err(|_| {
+            Error::InternalServerError("An error occurred while setting the session".into())
+        })?;
+    session
+        .insert(&SessionKey::MfaRetries.to_string(), 3)
+        .await
+        .map_err(|_| {
+            Error::InternalServerError("An error occurred while setting the session".into())
+        })?;
+
+    Ok(Json(json!({
+        "success": true,
+        "message": "MFA is required for this user, continue to MFA verification",
+    }))
+    .into_response())
+}
+
 pub fn routes() -> Router<AppState> {
     Router::new()
-        .route("/register", post(register))
-        .route("/login", post(login))
+        .route(
+            "/register",
+            post(register).layer(RateLimit::new("register", 5, Duration::hours(1)).layer()),
+        )
+        .route(
+            "/login",
+            post(login).layer(
+                RateLimit::new("login", 10, Duration::minutes(1))
+                    .identifier("email")
+                    .layer(),
+            ),
+        )
         .route("/logout", get(logout))
-        .route("/verify", get(verify))
-        .route("/resend-verification", post(resend_verification))
+        .route(
+            "/verify",
+            get(verify).layer(RateLimit::new("verify", 10, Duration::minutes(1)).layer()),
+        )
+        .route(
+            "/resend-verification",
+            post(resend_verification)
+                .layer(RateLimit::new("resend-verification", 3, Duration::hours(1)).layer()),
+        )
         .nest("/mfa", mfa::routes())
+        .nest("/password", password::routes())
+        .nest("/passwordless", passwordless::routes())
         .nest("/token", token::routes())
+        .nest("/sessions", sessions::routes())
         .nest("/passkeys", passkeys::routes())
         .nest("/oauth2", oauth2::routes())
 }
@@ -105,6 +230,13 @@ pub async fn register(
         return Err(Error::BadRequest("Username already in use".into()));
     }
 
+    let user_schema = match db.1 {
+        DatabaseType::Project(_) => config.system().as_ref().and_then(|s| s.user_schema.clone()),
+        DatabaseType::System => None,
+    }
+    .unwrap_or_default();
+    let extra_values = user_schema.values(&body.extra, false)?;
+
     let user = entities::AnyUser {
         id: Id::new().to_string(),
         first_name: body.first_name.clone(),
@@ -117,6 +249,11 @@ pub async fn register(
     };
 
     UserType::from(&db).create(user.clone()).await?;
+    user_schema.save(&db, &user.id, extra_values).await?;
+
+    if let DatabaseType::System = db.1 {
+        teams::create_personal(&db, &user).await?;
+    }
 
     if let UserType::Normal(_) = UserType::from(&db) {
         if let Some(Mailer(mailer)) = mailer {
@@ -193,11 +330,16 @@ pub async fn register(
         }
     }
 
+    if let DatabaseType::Project(_) = db.1 {
+        webhooks::dispatch(&db, WebhookEvent::AuthRegister, json!(user));
+    }
+
     Ok(Json(user).into_response())
 }
 
 pub async fn login(
     jar: CookieJar,
+    Device(device): Device,
     session: Session,
     Config(config): Config,
     Database(db): Database,
@@ -216,29 +358,18 @@ pub async fn login(
             "No user was found with this email/password combo".into(),
         ));
     }
+    user.ensure_not_banned()?;
 
     if user.mfa_secret.is_some() {
-        session
-            .insert(&SessionKey::LoginUserId.to_string(), user.id)
-            .await
-            .map_err(|_| {
-                Error::InternalServerError("An error occurred while setting the session".into())
-            })?;
-        session
-            .insert(&SessionKey::MfaRetries.to_string(), 3)
-            .await
-            .map_err(|_| {
-                Error::InternalServerError("An error occurred while setting the session".into())
-            })?;
+        return require_mfa(&session, user.id).await;
+    }
+
+    let (_, jar) = auth::authenticate(&db, &config.clone(), &user, device, jar).await?;
 
-        return Ok(Json(json!({
-            "success": true,
-            "message": "MFA is required for this user, continue to MFA verification",
-        }))
-        .into_response());
+    if let DatabaseType::Project(_) = db.1 {
+        webhooks::dispatch(&db, WebhookEvent::AuthLogin, json!(user));
     }
 
-    let (_, jar) = auth::authenticate(&db, &config.clone(), &user, jar).await?;
     Ok((jar, Json(user).into_response()).into_response())
 }
 
@@ -249,8 +380,11 @@ pub async fn logout(
     Database(db): Database,
 ) -> Result<impl IntoResponse, Error> {
     let cookies = util::get_auth_cookies(&jar)?;
-    let refresh_token =
-        auth::TokenType::verify(&config.clone(), cookies.refresh_token.value().into())?;
+    let refresh_token = auth::TokenType::verify(
+        &config,
+        &[db.1.clone()],
+        cookies.refresh_token.value().into(),
+    )?;
     if refresh_token.token_type != TokenType::Refresh {
         return Err(Error::Unauthorized);
     }
@@ -277,7 +411,8 @@ pub async fn verify(
 ) -> Result<impl IntoResponse, Error> {
     // TODO(@Xenfo): make this middleware
     let refresh_token = auth::TokenType::verify(
-        &config.clone(),
+        &config,
+        &[db.1.clone()],
         util::get_auth_cookies(&jar)?.refresh_token.value().into(),
     )?;
     if refresh_token.token_type != TokenType::Refresh {
@@ -21,7 +21,7 @@ use tower_sessions::Session;
 use tracing::error;
 
 use crate::{
-    middleware::extractors::{AnyUser, Config, Database, OAuth2},
+    middleware::extractors::{AnyUser, Config, Database, Device, OAuth2},
     session::SessionKey,
     state::AppState,
 };
@@ -96,6 +96,7 @@ pub async fn login(
 
 pub async fn callback(
     jar: CookieJar,
+    Device(device): Device,
     session: Session,
     Config(config): Config,
     OAuth2(oauth2): OAuth2,
@@ -170,6 +171,7 @@ pub async fn callback(
             }
         }
     }?;
+    user.ensure_not_banned()?;
 
     if user.mfa_secret.is_some() {
         session
@@ -197,6 +199,6 @@ pub async fn callback(
         .map(|url| format!("{}{}", client_url, url))
         .unwrap_or(format!("{}/dashboard", client_url));
 
-    let (_, jar) = auth::authenticate(&db, &config, &user, jar).await?;
+    let (_, jar) = auth::authenticate(&db, &config, &user, device, jar).await?;
     Ok((StatusCode::FOUND, [(header::LOCATION, redirect_url)], jar).into_response())
 }
@@ -12,7 +12,7 @@ use axum::{
     Json, Router,
 };
 use axum_extra::extract::CookieJar;
-use chrono::Utc;
+use chrono::{Duration, Utc};
 use serde::Deserialize;
 use serde_json::Value;
 use tower_sessions::Session;
@@ -22,7 +22,10 @@ use webauthn_rs::prelude::{
 };
 
 use crate::{
-    middleware::extractors::{AnyUser, Config, Database, Project},
+    middleware::{
+        extractors::{AnyUser, Config, Database, Device, Project},
+        rate_limit::RateLimit,
+    },
     session::SessionKey,
     state::AppState,
 };
@@ -50,8 +53,19 @@ pub fn routes() -> Router<AppState> {
         .route("/delete/:id", axum::routing::delete(delete))
         .route("/register/start", post(register_start))
         .route("/register/finish", post(register_finish))
-        .route("/login/start", post(login_start))
-        .route("/login/finish", post(login_finish))
+        .route(
+            "/login/start",
+            post(login_start).layer(
+                RateLimit::new("passkey-login", 10, Duration::minutes(1))
+                    .identifier("id")
+                    .layer(),
+            ),
+        )
+        .route(
+            "/login/finish",
+            post(login_finish)
+                .layer(RateLimit::new("passkey-login-finish", 10, Duration::minutes(1)).layer()),
+        )
 }
 
 pub async fn list(AnyUser(user, _): AnyUser) -> Result<impl IntoResponse, Error> {
@@ -254,6 +268,7 @@ pub async fn login_start(
 
 pub async fn login_finish(
     jar: CookieJar,
+    Device(device): Device,
     session: Session,
     headers: HeaderMap,
     project: Option<Project>,
@@ -296,6 +311,7 @@ pub async fn login_finish(
             (user.clone(), passkey)
         }
     };
+    user.ensure_not_banned()?;
 
     let mut authentication = session
         .remove::<PasskeyAuthentication>(&SessionKey::PasskeyAuthentication.to_string())
@@ -330,6 +346,6 @@ pub async fn login_finish(
         .await
         .map_err(|_| Error::InternalServerError("Unable to update passkey".to_string()))?;
 
-    let (_, jar) = auth::authenticate(&db, &config.clone(), &user, jar).await?;
+    let (_, jar) = auth::authenticate(&db, &config.clone(), &user, device, jar).await?;
     Ok((jar, Json(user)).into_response())
 }
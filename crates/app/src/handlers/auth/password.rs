@@ -0,0 +1,162 @@
+use adrastos_core::{
+    db::redis,
+    entities::{self, RefreshTokenTree, UserType},
+    error::Error,
+    id::Id,
+};
+use axum::{extract::State, response::IntoResponse, routing::post, Json, Router};
+use chrono::Duration;
+use fred::{interfaces::KeysInterface, types::Expiration};
+use serde::Deserialize;
+use serde_json::Value;
+use validator::Validate;
+
+use crate::{
+    middleware::{
+        extractors::{Config, Database, Mailer},
+        rate_limit::RateLimit,
+    },
+    state::AppState,
+};
+
+use super::{limit_attempts, send_email};
+
+#[derive(Deserialize)]
+pub struct RequestResetBody {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmResetBody {
+    token: String,
+    password: String,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/reset/request", post(request_reset))
+        .route(
+            "/reset/confirm",
+            post(confirm_reset)
+                .layer(RateLimit::new("password-reset", 10, Duration::minutes(15)).layer()),
+        )
+}
+
+fn reset_email(url: &str) -> String {
+    format!(
+        r#"<p>A password reset was requested for your account. Click the link below to choose a new password, it expires in one hour.</p><p><a href="{url}">{url}</a></p><p>If you didn't request this, you can ignore this email.</p>"#
+    )
+}
+
+/// Always succeeds so the endpoint can't be used to find out which emails have accounts
+pub async fn request_reset(
+    mailer: Mailer,
+    Config(config): Config,
+    Database(db): Database,
+    State(AppState { redis_pool, .. }): State<AppState>,
+    Json(body): Json<RequestResetBody>,
+) -> Result<impl IntoResponse, Error> {
+    limit_attempts(
+        &redis_pool,
+        &config,
+        format!("password-reset:{}", body.email),
+        5,
+        Duration::hours(1),
+    )
+    .await?;
+
+    let Ok(user) = UserType::from(&db)
+        .find()
+        .by_email(body.email.clone())
+        .one()
+        .await
+    else {
+        return Ok(Json(Value::Null));
+    };
+
+    let token = Id::new().to_string();
+    redis_pool
+        .set(
+            redis::build_key(&config, format!("password-reset:{token}")),
+            user.id.clone(),
+            Some(Expiration::EX(Duration::hours(1).num_seconds())),
+            None,
+            false,
+        )
+        .await
+        .map_err(|_| {
+            Error::InternalServerError(
+                "An error ocurred while saving password reset token to Redis".into(),
+            )
+        })?;
+
+    let url = format!("{}/auth/reset-password?token={token}", config.client_url);
+    send_email(
+        &mailer,
+        &config,
+        &user.email,
+        "Reset Your Password",
+        reset_email(&url),
+    )
+    .await?;
+
+    Ok(Json(Value::Null))
+}
+
+pub async fn confirm_reset(
+    Config(config): Config,
+    Database(db): Database,
+    State(AppState { redis_pool, .. }): State<AppState>,
+    Json(body): Json<ConfirmResetBody>,
+) -> Result<impl IntoResponse, Error> {
+    // Checked before the token is consumed so a rejected password doesn't use it up
+    entities::UpdateUser {
+        password: Some(body.password.clone()),
+        ..Default::default()
+    }
+    .validate()
+    .map_err(|e| Error::ValidationErrors {
+        errors: e,
+        message: "Invalid password".into(),
+    })?;
+
+    // Reading the token also consumes it, so it can only be used once
+    let user_id: Option<String> = redis_pool
+        .getdel(redis::build_key(
+            &config,
+            format!("password-reset:{}", body.token),
+        ))
+        .await
+        .map_err(|_| {
+            Error::InternalServerError(
+                "An error ocurred while getting password reset token from Redis".into(),
+            )
+        })?;
+    let user_id =
+        user_id.ok_or_else(|| Error::BadRequest("Invalid password reset token".into()))?;
+
+    let user = UserType::from(&db).find_by_id(&user_id).one().await?;
+    limit_attempts(
+        &redis_pool,
+        &config,
+        format!("password-reset:confirm:{}", user.email),
+        5,
+        Duration::hours(1),
+    )
+    .await?;
+
+    UserType::from(&db)
+        .update(
+            user.clone(),
+            entities::UpdateAnyUser {
+                password: Some(body.password),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    // Sessions started with the old password shouldn't outlive it
+    RefreshTokenTree::delete_all(&db, &user.id).await?;
+
+    Ok(Json(Value::Null))
+}
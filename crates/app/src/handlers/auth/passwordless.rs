@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+
+use adrastos_core::{
+    auth::{
+        self,
+        passwordless::{self, Method},
+    },
+    config,
+    db::{
+        postgres::{self, DatabaseType},
+        redis,
+    },
+    entities::{self, UserType},
+    error::Error,
+    id::Id,
+    webhooks::{self, WebhookEvent},
+};
+use axum::{extract::State, response::IntoResponse, routing::post, Json, Router};
+use axum_extra::extract::CookieJar;
+use chrono::{Duration, Utc};
+use fred::{interfaces::KeysInterface, types::Expiration};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tower_sessions::Session;
+
+use crate::{
+    middleware::{
+        extractors::{Config, Database, Device, Mailer},
+        rate_limit::RateLimit,
+    },
+    state::AppState,
+};
+
+use super::{limit_attempts, require_mfa, send_email};
+
+#[derive(Deserialize)]
+pub struct RequestBody {
+    email: String,
+    method: Method,
+}
+
+/// Profile fields are only used when the login creates the user
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum VerifyBody {
+    Link {
+        token: String,
+        #[serde(flatten)]
+        extra: HashMap<String, Value>,
+    },
+    Code {
+        email: String,
+        code: String,
+        #[serde(flatten)]
+        extra: HashMap<String, Value>,
+    },
+}
+
+/// The Redis entry backing a login link or code and the email delivering it
+struct LoginEmail {
+    key: String,
+    value: String,
+    subject: &'static str,
+    html: String,
+}
+
+impl LoginEmail {
+    fn new(method: Method, email: &str, client_url: &str) -> Self {
+        match method {
+            Method::Link => {
+                let token = Id::new().to_string();
+                let url = format!("{client_url}/auth/passwordless?token={token}");
+
+                Self {
+                    key: format!("magic-link:{token}"),
+                    value: email.to_string(),
+                    subject: "Your Login Link",
+                    html: format!(
+                        r#"<p>Click the link below to log in, it expires in 15 minutes.</p><p><a href="{url}">{url}</a></p><p>If you didn't request this, you can ignore this email.</p>"#
+                    ),
+                }
+            }
+            Method::Code => {
+                let code = passwordless::generate_code();
+
+                Self {
+                    key: format!("otp:{email}"),
+                    value: code.clone(),
+                    subject: "Your Login Code",
+                    html: format!(
+                        "<p>Your login code is <strong>{code}</strong>, it expires in 15 minutes.</p><p>If you didn't request this, you can ignore this email.</p>"
+                    ),
+                }
+            }
+        }
+    }
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/request", post(request)).route(
+        "/verify",
+        post(verify).layer(
+            RateLimit::new("passwordless-verify", 10, Duration::minutes(15))
+                .identifier("email")
+                .layer(),
+        ),
+    )
+}
+
+/// Sends a login link or code. Always succeeds so the endpoint can't be used to find out which
+/// emails have accounts.
+pub async fn request(
+    mailer: Mailer,
+    Config(config): Config,
+    Database(db): Database,
+    State(AppState { redis_pool, .. }): State<AppState>,
+    Json(body): Json<RequestBody>,
+) -> Result<impl IntoResponse, Error> {
+    limit_attempts(
+        &redis_pool,
+        &config,
+        format!("passwordless:{}", body.email),
+        5,
+        Duration::hours(1),
+    )
+    .await?;
+
+    let user_exists = UserType::from(&db)
+        .find()
+        .by_email(body.email.clone())
+        .one()
+        .await
+        .is_ok();
+    if !user_exists && !can_create_users(&config, &db) {
+        return Ok(Json(Value::Null));
+    }
+
+    let LoginEmail {
+        key,
+        value,
+        subject,
+        html,
+    } = LoginEmail::new(body.method, &body.email, &config.client_url);
+
+    redis_pool
+        .set(
+            redis::build_key(&config, key),
+            value,
+            Some(Expiration::EX(Duration::minutes(15).num_seconds())),
+            None,
+            false,
+        )
+        .await
+        .map_err(|_| {
+            Error::InternalServerError("An error ocurred while saving login token to Redis".into())
+        })?;
+
+    send_email(&mailer, &config, &body.email, subject, html).await?;
+
+    Ok(Json(Value::Null))
+}
+
+pub async fn verify(
+    jar: CookieJar,
+    Device(device): Device,
+    session: Session,
+    Config(config): Config,
+    Database(db): Database,
+    State(AppState { redis_pool, .. }): State<AppState>,
+    Json(body): Json<VerifyBody>,
+) -> Result<impl IntoResponse, Error> {
+    let redis_error = |_| {
+        Error::InternalServerError("An error ocurred while getting login token from Redis".into())
+    };
+
+    let (email, extra) = match body {
+        VerifyBody::Link { token, extra } => {
+            // Reading the token also consumes it, so it can only be used once
+            let email: Option<String> = redis_pool
+                .getdel(redis::build_key(&config, format!("magic-link:{token}")))
+                .await
+                .map_err(redis_error)?;
+
+            let email = email.ok_or_else(|| Error::BadRequest("Invalid login link".into()))?;
+            (email, extra)
+        }
+        VerifyBody::Code { email, code, extra } => {
+            limit_attempts(
+                &redis_pool,
+                &config,
+                format!("otp:{email}"),
+                5,
+                Duration::minutes(15),
+            )
+            .await?;
+
+            // Consumed by any attempt so two requests can't both use it, a wrong guess needs a
+            // new code
+            let expected: Option<String> = redis_pool
+                .getdel(redis::build_key(&config, format!("otp:{email}")))
+                .await
+                .map_err(redis_error)?;
+            if expected.as_deref() != Some(code.as_str()) {
+                return Err(Error::BadRequest("Invalid login code".into()));
+            }
+
+            (email, extra)
+        }
+    };
+
+    let user = match UserType::from(&db)
+        .find()
+        .by_email(email.clone())
+        .one()
+        .await
+    {
+        Ok(user) => user,
+        Err(_) if can_create_users(&config, &db) => {
+            create_user(&db, &config, email, &extra).await?
+        }
+        Err(_) => {
+            return Err(Error::BadRequest(
+                "No user was found with this email".into(),
+            ))
+        }
+    };
+
+    if user.mfa_secret.is_some() {
+        return require_mfa(&session, user.id).await;
+    }
+
+    let (_, jar) = auth::authenticate(&db, &config, &user, device, jar).await?;
+
+    if let DatabaseType::Project(_) = db.1 {
+        webhooks::dispatch(&db, WebhookEvent::AuthLogin, json!(user));
+    }
+
+    Ok((jar, Json(user)).into_response())
+}
+
+/// Only projects that opted in create accounts on the first login, never the system
+fn can_create_users(config: &config::Config, db: &postgres::Database) -> bool {
+    match db.1 {
+        DatabaseType::Project(_) => config
+            .system()
+            .as_ref()
+            .and_then(|s| s.passwordless_config.as_ref())
+            .is_some_and(|c| c.create_users),
+        DatabaseType::System => false,
+    }
+}
+
+async fn create_user(
+    db: &postgres::Database,
+    config: &config::Config,
+    email: String,
+    extra: &HashMap<String, Value>,
+) -> Result<entities::AnyUser, Error> {
+    if !mailchecker::is_valid(&email) {
+        return Err(Error::BadRequest("Invalid email".into()));
+    }
+
+    let user_schema = config
+        .system()
+        .as_ref()
+        .and_then(|s| s.user_schema.clone())
+        .unwrap_or_default();
+    let extra_values = user_schema.values(extra, false)?;
+
+    // The password is never shared, a password reset is needed to start using one. Receiving
+    // the login email proves the address.
+    let user = entities::AnyUser {
+        id: Id::new().to_string(),
+        username: format!("user_{}", Id::new()),
+        email,
+        password: Id::new().to_string(),
+        verified: true,
+        created_at: Utc::now(),
+        ..Default::default()
+    };
+    UserType::from(db).create(user.clone()).await?;
+    user_schema.save(db, &user.id, extra_values).await?;
+
+    webhooks::dispatch(db, WebhookEvent::AuthRegister, json!(user));
+
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use lettre::{AsyncSmtpTransport, Tokio1Executor};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Accepts a single email, returning its headers and body
+    async fn smtp_server(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut message = String::new();
+
+        write.write_all(b"220 localhost\r\n").await.unwrap();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if line != "DATA" {
+                write.write_all(b"250 OK\r\n").await.unwrap();
+                continue;
+            }
+
+            write.write_all(b"354 Go ahead\r\n").await.unwrap();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                if line == "." {
+                    break;
+                }
+                message.push_str(&line);
+                message.push('\n');
+            }
+            write.write_all(b"250 Queued\r\n").await.unwrap();
+            break;
+        }
+
+        message
+    }
+
+    async fn send(method: Method) -> (LoginEmail, String) {
+        for (key, value) in [
+            ("POSTGRES_URL", "postgres://localhost"),
+            ("REDIS_URL", "redis://localhost"),
+            ("S3_BUCKET", "bucket"),
+            ("S3_REGION", "region"),
+            ("S3_ENDPOINT", "http://localhost"),
+            ("S3_ACCESS_KEY", "key"),
+            ("S3_SECRET_KEY", "secret"),
+        ] {
+            env::set_var(key, value);
+        }
+        let mut config = config::Config::new();
+        config.client_url = "https://app.example.com".into();
+        config.smtp_sender_name = Some("Adrastos".into());
+        config.smtp_sender_email = Some("noreply@example.com".into());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(smtp_server(listener));
+
+        let mailer = Mailer(
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+                .port(port)
+                .build(),
+        );
+        let email = LoginEmail::new(method, "user@example.com", &config.client_url);
+        send_email(
+            &mailer,
+            &config,
+            "user@example.com",
+            email.subject,
+            email.html.clone(),
+        )
+        .await
+        .unwrap();
+
+        (email, server.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn emails_login_links() {
+        let (email, message) = send(Method::Link).await;
+        let token = email.key.strip_prefix("magic-link:").unwrap();
+
+        assert_eq!(email.value, "user@example.com");
+        assert!(message.contains("Subject: Your Login Link"));
+        assert!(message.contains(&format!(
+            "https://app.example.com/auth/passwordless?token={token}"
+        )));
+    }
+
+    #[tokio::test]
+    async fn emails_login_codes() {
+        let (email, message) = send(Method::Code).await;
+
+        assert_eq!(email.key, "otp:user@example.com");
+        assert!(message.contains("Subject: Your Login Code"));
+        assert!(message.contains(&format!("<strong>{}</strong>", email.value)));
+    }
+}
@@ -0,0 +1,105 @@
+use adrastos_core::{
+    auth::{self, TokenType},
+    config,
+    db::postgres,
+    entities::{self, RefreshTokenTree},
+    error::Error,
+    util,
+};
+use axum::{
+    extract::Path,
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use axum_extra::extract::CookieJar;
+use serde_json::{json, Value};
+
+use crate::{
+    middleware::extractors::{AnyUser, Config, Database},
+    state::AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list))
+        .route("/revoke/:id", delete(revoke))
+        .route("/revoke-others", post(revoke_others))
+}
+
+/// The session the request's refresh token belongs to
+fn current_session(
+    jar: &CookieJar,
+    config: &config::Config,
+    db: &postgres::Database,
+    user: &entities::AnyUser,
+) -> Option<RefreshTokenTree> {
+    let cookies = util::get_auth_cookies(jar).ok()?;
+    let refresh_token = auth::TokenType::verify(
+        config,
+        &[db.1.clone()],
+        cookies.refresh_token.value().into(),
+    )
+    .ok()
+    .filter(|token| token.token_type == TokenType::Refresh)?;
+
+    user.refresh_token_trees
+        .as_ref()?
+        .iter()
+        .find(|tree| tree.tokens.contains(&refresh_token.claims.jti))
+        .cloned()
+}
+
+pub async fn list(
+    jar: CookieJar,
+    Config(config): Config,
+    Database(db): Database,
+    AnyUser(user, _): AnyUser,
+) -> Result<impl IntoResponse, Error> {
+    let current = current_session(&jar, &config, &db, &user).map(|tree| tree.id);
+
+    let sessions = user
+        .refresh_token_trees
+        .clone()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|tree| {
+            let is_current = current.as_ref() == Some(&tree.id);
+            let mut session = json!(tree);
+            session["current"] = json!(is_current);
+
+            session
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Json(sessions))
+}
+
+pub async fn revoke(
+    Path(id): Path<String>,
+    Database(db): Database,
+    AnyUser(user, _): AnyUser,
+) -> Result<impl IntoResponse, Error> {
+    user.refresh_token_trees
+        .unwrap_or_default()
+        .into_iter()
+        .find(|tree| tree.id == id)
+        .ok_or(Error::NotFound)?
+        .delete(&db)
+        .await?;
+
+    Ok(Json(Value::Null))
+}
+
+/// Logs out of every session except the one making the request
+pub async fn revoke_others(
+    jar: CookieJar,
+    Config(config): Config,
+    Database(db): Database,
+    AnyUser(user, _): AnyUser,
+) -> Result<impl IntoResponse, Error> {
+    let current = current_session(&jar, &config, &db, &user).ok_or(Error::Unauthorized)?;
+    RefreshTokenTree::delete_others(&db, &user.id, &current.id).await?;
+
+    Ok(Json(Value::Null))
+}
@@ -1,30 +1,43 @@
 use adrastos_core::{
     auth::{self, TokenType},
+    config,
+    db::{postgres, redis},
     entities,
     error::Error,
     util,
 };
-use axum::{response::IntoResponse, routing::get, Json, Router};
+use axum::{extract::State, response::IntoResponse, routing::get, Json, Router};
 use axum_extra::extract::CookieJar;
 use chrono::Utc;
+use fred::{
+    clients::RedisPool,
+    interfaces::KeysInterface,
+    types::{Expiration, SetOptions},
+};
 
 use crate::{
     middleware::extractors::{Config, Database},
     state::AppState,
 };
 
+/// How many seconds a replaced refresh token can still be used, so tabs refreshing at the same
+/// time all end up with the same new token instead of being treated as a stolen token
+const ROTATION_GRACE_SECONDS: i64 = 30;
+
 pub fn routes() -> Router<AppState> {
     Router::new().route("/refresh", get(refresh))
 }
 
-#[tracing::instrument(skip(config, jar, db))]
+#[tracing::instrument(skip(config, jar, db, redis_pool))]
 pub async fn refresh(
     jar: CookieJar,
     Config(config): Config,
     Database(db): Database,
+    State(AppState { redis_pool, .. }): State<AppState>,
 ) -> Result<impl IntoResponse, Error> {
     let refresh_token = auth::TokenType::verify(
-        &config.clone(),
+        &config,
+        &[db.1.clone()],
         util::get_auth_cookies(&jar)?.refresh_token.value().into(),
     )?;
     if refresh_token.token_type != TokenType::Refresh {
@@ -36,6 +49,7 @@ pub async fn refresh(
         .join(entities::AnyUserJoin::RefreshTokenTrees)
         .one()
         .await?;
+    user.ensure_not_banned()?;
 
     let refresh_token_tree = &user
         .refresh_token_trees
@@ -50,23 +64,89 @@ pub async fn refresh(
         .last()
         .ok_or_else(|| Error::Forbidden("Refresh token tree is invalid".into()))?;
 
+    let rotation_key = redis::build_key(
+        &config,
+        format!("refresh-rotation:{}", refresh_token.claims.jti),
+    );
     if refresh_token_tree.inactive_at < Utc::now() || refresh_token_tree.expires_at < Utc::now() {
         return Err(Error::Forbidden("Refresh token tree has expired".into()));
     } else if refresh_token.claims.jti.clone().as_str() != last_token.as_str() {
-        refresh_token_tree.delete(&db).await?;
+        // Reusing an old token outside of the grace period means it was probably stolen
+        let Some(replacement) = rotated_token(&redis_pool, rotation_key).await? else {
+            refresh_token_tree.delete(&db).await?;
+
+            return Err(Error::Forbidden("Refresh token is invalid".into()));
+        };
 
-        return Err(Error::Forbidden("Refresh token is invalid".into()));
+        return reuse(&config, &db, &user, replacement, jar);
     }
 
-    let access_token = TokenType::Access.sign(&config.clone(), &user)?;
-    let refresh_token = TokenType::Refresh.sign(&config.clone(), &user)?;
+    let access_token = TokenType::Access.sign(&config, &db.1, &user)?;
+    let refresh_token = TokenType::Refresh.sign(&config, &db.1, &user)?;
+
+    // Only the first of concurrent refreshes rotates the token, the others reuse its token
+    let claimed: Option<String> = redis_pool
+        .set(
+            rotation_key.clone(),
+            refresh_token.token.clone(),
+            Some(Expiration::EX(ROTATION_GRACE_SECONDS)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await
+        .map_err(|_| {
+            Error::InternalServerError(
+                "An error ocurred while saving the rotated token to Redis".into(),
+            )
+        })?;
+    if claimed.is_none() {
+        let replacement = rotated_token(&redis_pool, rotation_key)
+            .await?
+            .ok_or_else(|| Error::Forbidden("Refresh token is invalid".into()))?;
+
+        return reuse(&config, &db, &user, replacement, jar);
+    }
 
     let mut tokens = refresh_token_tree.tokens.clone();
     tokens.push(refresh_token.claims.jti.clone());
-    refresh_token_tree.update(&db, tokens).await?;
+    if let Err(err) = refresh_token_tree.update(&db, tokens).await {
+        // Lets the next refresh claim the rotation again
+        redis_pool.del::<(), _>(rotation_key).await.ok();
+
+        return Err(err);
+    }
 
     Ok((
         auth::create_auth_cookies(refresh_token, jar)?,
         Json(access_token.clone().token),
     ))
 }
+
+/// The token that replaced a rotated refresh token, while it's within the grace period
+async fn rotated_token(
+    redis_pool: &RedisPool,
+    rotation_key: String,
+) -> Result<Option<String>, Error> {
+    redis_pool.get(rotation_key).await.map_err(|_| {
+        Error::InternalServerError(
+            "An error ocurred while getting the rotated token from Redis".into(),
+        )
+    })
+}
+
+/// Hands out the token another refresh already rotated to instead of rotating again
+fn reuse(
+    config: &config::Config,
+    db: &postgres::Database,
+    user: &entities::AnyUser,
+    replacement: String,
+    jar: CookieJar,
+) -> Result<(CookieJar, Json<String>), Error> {
+    let refresh_token = auth::TokenType::verify(config, &[db.1.clone()], replacement)?;
+    let access_token = TokenType::Access.sign(config, &db.1, user)?;
+
+    Ok((
+        auth::create_auth_cookies(refresh_token, jar)?,
+        Json(access_token.token),
+    ))
+}
@@ -2,7 +2,10 @@ use std::collections::HashMap;
 
 use adrastos_core::{
     auth::oauth2::providers::OAuth2Provider,
-    entities::{OAuth2Config, SmtpConfig},
+    entities::{
+        custom_table::fields::Field, OAuth2Config, PasswordlessConfig, SmtpConfig, TeamRole,
+        UserSchema, VerificationConfig,
+    },
     error::Error,
 };
 use axum::{
@@ -10,11 +13,14 @@ use axum::{
     routing::{get, post},
     Json, Router,
 };
+use deadpool_postgres::tokio_postgres;
+use heck::ToSnakeCase;
 use serde::Deserialize;
 use serde_json::json;
+use tracing::error;
 
 use crate::{
-    middleware::extractors::{Config, ProjectDatabase, SystemUser},
+    middleware::extractors::{Config, Member, ProjectDatabase},
     state::AppState,
 };
 
@@ -41,9 +47,17 @@ pub fn routes() -> Router<AppState> {
         .route("/details", get(details))
         .route("/smtp", post(smtp))
         .route("/oauth2", post(oauth2))
+        .route("/user-fields", post(user_fields))
+        .route("/passwordless", post(passwordless))
+        .route("/verification", post(verification))
 }
 
-pub async fn details(_: SystemUser, Config(config): Config) -> Result<impl IntoResponse, Error> {
+pub async fn details(
+    Member(member): Member,
+    Config(config): Config,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
     let system = config.system();
     let Some(system) = system else {
         return Err(Error::InternalServerError(
@@ -65,16 +79,21 @@ pub async fn details(_: SystemUser, Config(config): Config) -> Result<impl IntoR
             "github": system.github_config,
             "twitter": system.twitter_config,
             "discord": system.discord_config,
-        }
+        },
+        "userFields": system.user_schema.as_ref().map(|s| &s.fields),
+        "passwordlessConfig": system.passwordless_config,
+        "verificationConfig": system.verification_config,
     })))
 }
 
 pub async fn smtp(
-    _: SystemUser,
+    Member(member): Member,
     Config(config): Config,
     ProjectDatabase(db): ProjectDatabase,
     body: Option<Json<SmtpBody>>,
 ) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
     let system = config.system();
     let Some(mut system) = system.clone() else {
         return Err(Error::InternalServerError(
@@ -130,11 +149,13 @@ pub async fn smtp(
 }
 
 pub async fn oauth2(
-    _: SystemUser,
+    Member(member): Member,
     Config(config): Config,
     ProjectDatabase(db): ProjectDatabase,
     body: Json<HashMap<OAuth2Provider, Option<Oauth2Body>>>,
 ) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
     let system = config.system();
     let Some(mut system) = system.clone() else {
         return Err(Error::InternalServerError(
@@ -172,3 +193,109 @@ pub async fn oauth2(
         "discord": system.discord_config,
     })))
 }
+
+pub async fn user_fields(
+    Member(member): Member,
+    Config(config): Config,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(fields): Json<Vec<Field>>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    let system = config.system();
+    let Some(mut system) = system.clone() else {
+        return Err(Error::InternalServerError(
+            "Something went wrong getting the system.".into(),
+        ));
+    };
+
+    let user_schema = UserSchema {
+        fields: fields
+            .into_iter()
+            .map(|f| Field {
+                name: f.name.to_snake_case(),
+                info: f.info,
+            })
+            .collect(),
+    };
+    user_schema.validate()?;
+
+    let queries = system
+        .user_schema
+        .clone()
+        .unwrap_or_default()
+        .migrate(&user_schema)?;
+
+    system.user_schema = Some(user_schema);
+
+    // The columns and the schema describing them change together or not at all
+    let mut conn = db.get().await.unwrap();
+    let update_error = |e: tokio_postgres::Error| {
+        error!(error = ?e);
+        Error::InternalServerError("Failed to update the user fields".into())
+    };
+    let transaction = conn.transaction().await.map_err(update_error)?;
+    for query in queries.iter().chain([&system.set()]) {
+        transaction
+            .execute(query, &[])
+            .await
+            .map_err(update_error)?;
+    }
+    transaction.commit().await.map_err(update_error)?;
+
+    Ok(Json(system.user_schema.map(|s| s.fields)))
+}
+
+pub async fn passwordless(
+    Member(member): Member,
+    Config(config): Config,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<PasswordlessConfig>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    let system = config.system();
+    let Some(mut system) = system.clone() else {
+        return Err(Error::InternalServerError(
+            "Something went wrong getting the system.".into(),
+        ));
+    };
+
+    system.passwordless_config = Some(body);
+
+    db.get()
+        .await
+        .unwrap()
+        .execute(&system.set(), &[])
+        .await
+        .unwrap();
+
+    Ok(Json(system.passwordless_config))
+}
+
+pub async fn verification(
+    Member(member): Member,
+    Config(config): Config,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<VerificationConfig>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    let system = config.system();
+    let Some(mut system) = system.clone() else {
+        return Err(Error::InternalServerError(
+            "Something went wrong getting the system.".into(),
+        ));
+    };
+
+    system.verification_config = Some(body);
+
+    db.get()
+        .await
+        .unwrap()
+        .execute(&system.set(), &[])
+        .await
+        .unwrap();
+
+    Ok(Json(system.verification_config))
+}
@@ -0,0 +1,204 @@
+use adrastos_core::{
+    config,
+    entities::{
+        custom_table::{
+            fields::{FieldInfo, RelationTarget},
+            schema::CustomTableSchema,
+        },
+        Job, JobAction, JobRun, TeamRole, UpdateJob,
+    },
+    error::Error,
+    id::Id,
+    jobs, webhooks,
+};
+use axum::{
+    extract::Path,
+    response::IntoResponse,
+    routing::{delete, get, patch, post},
+    Json, Router,
+};
+use chrono::Utc;
+use heck::ToSnakeCase;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    middleware::extractors::{Config, Member, ProjectDatabase},
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+pub struct CreateBody {
+    name: String,
+    schedule: String,
+    action: JobAction,
+    enabled: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateBody {
+    name: Option<String>,
+    schedule: Option<String>,
+    action: Option<JobAction>,
+    enabled: Option<bool>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list))
+        .route("/create", post(create))
+        .route("/update/:id", patch(update))
+        .route("/delete/:id", delete(remove))
+        .route("/:id/runs", get(runs))
+}
+
+async fn validate_action(
+    action: &JobAction,
+    db: &deadpool_postgres::Pool,
+    config: &config::Config,
+) -> Result<(), Error> {
+    match action {
+        JobAction::DeleteRows {
+            table,
+            filter,
+            older_than,
+        } => {
+            if filter.is_empty() && older_than.is_none() {
+                return Err(Error::BadRequest(
+                    "Deleting rows needs a filter or an age".into(),
+                ));
+            }
+            if let Some(older_than) = older_than {
+                jobs::cutoff(*older_than)?;
+            }
+
+            let schema = CustomTableSchema::find()
+                .by_name(table.clone())
+                .one(db)
+                .await
+                .map_err(|_| Error::BadRequest(format!("No table named '{table}' exists")))?;
+
+            for key in filter.keys() {
+                let column = key.to_snake_case();
+                let is_column = ["id", "created_at", "updated_at"].contains(&column.as_str())
+                    || schema.fields.iter().any(|field| {
+                        field.name == column
+                            && !matches!(
+                                field.info,
+                                FieldInfo::Relation {
+                                    target: RelationTarget::Many,
+                                    ..
+                                }
+                            )
+                    });
+                if !is_column {
+                    return Err(Error::BadRequest(format!(
+                        "Table '{table}' has no field named '{key}'"
+                    )));
+                }
+            }
+        }
+        JobAction::PurgeUploads { older_than } => {
+            jobs::cutoff(*older_than)?;
+        }
+        JobAction::CallWebhook { url, .. } => {
+            webhooks::validate_url(url, config.allow_private_webhooks).await?;
+        }
+        JobAction::PurgeRefreshTokens => {}
+    }
+
+    Ok(())
+}
+
+pub async fn list(
+    Member(member): Member,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    let jobs = Job::find().all(&db).await?;
+    Ok(Json(jobs))
+}
+
+pub async fn create(
+    Member(member): Member,
+    Config(config): Config,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<CreateBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    Job::parse_schedule(&body.schedule)?;
+    validate_action(&body.action, &db, &config).await?;
+
+    let job = Job {
+        id: Id::new().to_string(),
+        name: body.name,
+        schedule: body.schedule,
+        action: body.action,
+        enabled: body.enabled.unwrap_or(true),
+        last_run_at: None,
+        created_at: Utc::now(),
+        updated_at: None,
+    };
+
+    job.create(&db).await?;
+    Ok(Json(job))
+}
+
+pub async fn update(
+    Member(member): Member,
+    Path(id): Path<String>,
+    Config(config): Config,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<UpdateBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    if let Some(schedule) = &body.schedule {
+        Job::parse_schedule(schedule)?;
+    }
+    if let Some(action) = &body.action {
+        validate_action(action, &db, &config).await?;
+    }
+
+    Job::find_by_id(&id)
+        .one(&db)
+        .await?
+        .update(
+            &db,
+            UpdateJob {
+                name: body.name,
+                schedule: body.schedule,
+                action: body.action,
+                enabled: body.enabled,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    let job = Job::find_by_id(&id).one(&db).await?;
+    Ok(Json(job))
+}
+
+pub async fn remove(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    Job::find_by_id(&id).one(&db).await?.delete(&db).await?;
+    Ok(Json(Value::Null))
+}
+
+pub async fn runs(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    let runs = JobRun::find().by_job_id(id).all(&db).await?;
+    Ok(Json(runs))
+}
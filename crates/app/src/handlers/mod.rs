@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+
 use adrastos_core::{
+    config,
     db::postgres::DatabaseType,
-    entities::{SystemUser, User},
+    entities::{SystemUser, UpdateUser, User},
     error::Error,
 };
 use axum::{
@@ -8,17 +11,34 @@ use axum::{
     response::IntoResponse,
     Json,
 };
+use serde::Deserialize;
+use serde_json::{json, Value};
 
 use crate::{
     assets::handle_embedded_file,
-    middleware::extractors::{AnyUser, Config, Database},
+    middleware::extractors::{self, AnyUser, Config, Database, ProjectDatabase},
 };
 
+pub mod api_keys;
 pub mod auth;
 pub mod config;
+pub mod jobs;
+pub mod roles;
 pub mod storage;
 pub mod tables;
 pub mod teams;
+pub mod users;
+pub mod webhooks;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateMeBody {
+    first_name: Option<String>,
+    last_name: Option<String>,
+    username: Option<String>,
+    #[serde(flatten)]
+    extra: HashMap<String, Value>,
+}
 
 pub async fn root(uri: Uri) -> impl IntoResponse {
     handle_embedded_file(uri.path())
@@ -34,7 +54,41 @@ pub async fn api(Config(config): Config) -> impl IntoResponse {
     )
 }
 
+/// The public keys that verify our tokens, retired keys are left out
+pub async fn jwks(Config(config): Config) -> impl IntoResponse {
+    let keys = config
+        .signing_keys
+        .into_iter()
+        .map(|key| key.public_key)
+        .collect::<Vec<_>>();
+
+    Json(json!({ "keys": keys }))
+}
+
+async fn user_with_fields(
+    config: &config::Config,
+    db: &deadpool_postgres::Pool,
+    user: &User,
+) -> Result<Value, Error> {
+    let mut data = serde_json::to_value(user).map_err(|_| {
+        Error::InternalServerError("An error occurred while serializing the user".into())
+    })?;
+
+    if let Some(user_schema) = config
+        .system()
+        .as_ref()
+        .and_then(|s| s.user_schema.as_ref())
+        && let (Some(data), Value::Object(fields)) =
+            (data.as_object_mut(), user_schema.find(db, &user.id).await?)
+    {
+        data.extend(fields);
+    }
+
+    Ok(data)
+}
+
 pub async fn me(
+    Config(config): Config,
     Database(db): Database,
     AnyUser(user, _): AnyUser,
 ) -> Result<impl IntoResponse, Error> {
@@ -45,7 +99,47 @@ pub async fn me(
         }
         DatabaseType::Project(_) => {
             let user = User::find_by_id(&user.id).one(&db).await?;
-            Ok(Json(user).into_response())
+            Ok(Json(user_with_fields(&config, &db, &user).await?).into_response())
         }
     }
 }
+
+pub async fn update_me(
+    Config(config): Config,
+    ProjectDatabase(db): ProjectDatabase,
+    extractors::User(user): extractors::User,
+    Json(body): Json<UpdateMeBody>,
+) -> Result<impl IntoResponse, Error> {
+    if let Some(username) = &body.username
+        && username != &user.username
+        && User::find()
+            .by_username(username.clone())
+            .one(&db)
+            .await
+            .is_ok()
+    {
+        return Err(Error::BadRequest("Username already in use".into()));
+    }
+
+    let user_schema = config
+        .system()
+        .as_ref()
+        .and_then(|s| s.user_schema.clone())
+        .unwrap_or_default();
+    let extra_values = user_schema.values(&body.extra, true)?;
+
+    user.update(
+        &db,
+        UpdateUser {
+            first_name: body.first_name,
+            last_name: body.last_name,
+            username: body.username,
+            ..Default::default()
+        },
+    )
+    .await?;
+    user_schema.save(&db, &user.id, extra_values).await?;
+
+    let user = User::find_by_id(&user.id).one(&db).await?;
+    Ok(Json(user_with_fields(&config, &db, &user).await?))
+}
@@ -0,0 +1,200 @@
+use adrastos_core::{
+    entities::{Role, TeamRole, UpdateRole, User, UserRole},
+    error::Error,
+    id::Id,
+};
+use axum::{
+    extract::Path,
+    response::IntoResponse,
+    routing::{delete, get, patch, post},
+    Json, Router,
+};
+use chrono::Utc;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::{
+    middleware::extractors::{Member, ProjectDatabase},
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateBody {
+    name: String,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateBody {
+    name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    description: Option<Option<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssignmentBody {
+    user_id: String,
+    role_id: String,
+}
+
+/// Tells an explicit `null`, which removes the description, apart from a missing key
+fn nullable<'de, D>(deserializer: D) -> Result<Option<Option<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+fn validate_name(name: &str) -> Result<(), Error> {
+    if !Role::is_valid_name(name) {
+        return Err(Error::BadRequest(
+            "Role names can only contain letters, numbers, '_' and '-'".into(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list))
+        .route("/create", post(create))
+        .route("/update/:id", patch(update))
+        .route("/delete/:id", delete(remove))
+        .route("/user/:id", get(user_roles))
+        .route("/assign", post(assign))
+        .route("/unassign", post(unassign))
+}
+
+pub async fn list(
+    _: Member,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    let roles = Role::find().all(&db).await?;
+    Ok(Json(roles))
+}
+
+pub async fn create(
+    Member(member): Member,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<CreateBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    validate_name(&body.name)?;
+
+    let role = Role {
+        id: Id::new().to_string(),
+        name: body.name,
+        description: body.description,
+        created_at: Utc::now(),
+        updated_at: None,
+    };
+
+    role.create(&db).await?;
+    Ok(Json(role))
+}
+
+pub async fn update(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<UpdateBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    if let Some(name) = &body.name {
+        validate_name(name)?;
+    }
+
+    Role::find_by_id(&id)
+        .one(&db)
+        .await?
+        .update(
+            &db,
+            UpdateRole {
+                name: body.name,
+                description: body.description,
+            },
+        )
+        .await?;
+
+    let role = Role::find_by_id(&id).one(&db).await?;
+    Ok(Json(role))
+}
+
+/// Also takes the role away from everyone it was granted to
+pub async fn remove(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    Role::find_by_id(&id).one(&db).await?.delete(&db).await?;
+    Ok(Json(Value::Null))
+}
+
+pub async fn user_roles(
+    _: Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    let user = User::find_by_id(&id).one(&db).await?;
+    let roles = Role::for_user(&db, &user.id).await?;
+
+    Ok(Json(roles))
+}
+
+pub async fn assign(
+    Member(member): Member,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<AssignmentBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    let user = User::find_by_id(&body.user_id).one(&db).await?;
+    let role = Role::find_by_id(&body.role_id).one(&db).await?;
+
+    if UserRole::find()
+        .by_user_id(user.id.clone())
+        .by_role_id(role.id.clone())
+        .one(&db)
+        .await
+        .is_ok()
+    {
+        return Err(Error::BadRequest("The user already has this role".into()));
+    }
+
+    let user_role = UserRole {
+        id: Id::new().to_string(),
+        user_id: user.id,
+        role_id: role.id,
+        created_at: Utc::now(),
+        updated_at: None,
+    };
+
+    user_role.create(&db).await?;
+    Ok(Json(user_role))
+}
+
+pub async fn unassign(
+    Member(member): Member,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<AssignmentBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    UserRole::find()
+        .by_user_id(body.user_id)
+        .by_role_id(body.role_id)
+        .one(&db)
+        .await?
+        .delete(&db)
+        .await?;
+
+    Ok(Json(Value::Null))
+}
@@ -4,6 +4,7 @@ use adrastos_core::{
     entities::{SizeUnit, UploadMetadata},
     error::Error,
     id::Id,
+    webhooks::{self, WebhookEvent},
 };
 use axum::{
     body::Body,
@@ -47,6 +48,7 @@ pub fn routes() -> Router<AppState> {
             ),
         )
         .route("/delete/:id", delete(remove))
+        .layer(axum::middleware::from_fn(middleware::verified::run))
 }
 
 pub async fn list(
@@ -151,14 +153,15 @@ pub async fn upload(
         )
         .await?;
 
-        UploadMetadata {
+        let metadata = UploadMetadata {
             id,
             user_id: user.id.clone(),
             name: file.metadata.file_name.clone().unwrap(),
             created_at: Utc::now(),
-        }
-        .create(&db)
-        .await?
+        };
+        metadata.create(&db).await?;
+
+        webhooks::dispatch(&db, WebhookEvent::StorageUpload, json!(metadata));
     }
 
     Ok(Json(Value::Null))
@@ -174,11 +177,10 @@ pub async fn remove(
     s3.delete(format!("{}/{}/{}", project.id, user.id, path))
         .await?;
 
-    UploadMetadata::find_by_id(&path)
-        .one(&db)
-        .await?
-        .delete(&db)
-        .await?;
+    let metadata = UploadMetadata::find_by_id(&path).one(&db).await?;
+    metadata.delete(&db).await?;
+
+    webhooks::dispatch(&db, WebhookEvent::StorageDelete, json!(metadata));
 
     Ok(Json(Value::Null))
 }
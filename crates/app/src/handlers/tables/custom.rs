@@ -3,49 +3,513 @@ use std::collections::HashMap;
 use adrastos_core::{
     db::postgres,
     entities::{
+        self,
         custom_table::{
-            fields::{FieldInfo, RelationTarget},
+            fields::{Field, FieldInfo, RelationTarget},
+            geo,
+            hooks::HookEvent,
             mm_relation::ManyToManyRelationTable,
             permissions::Permission,
+            reverse::{CountOperator, ReverseRelation},
+            rules,
             schema::CustomTableSchema,
             CustomTableSelectBuilder,
         },
-        AlternateUserType,
+        AlternateUserType, Operation,
     },
     error::Error,
     id::Id,
+    webhooks::{self, WebhookEvent},
 };
 use axum::{
     extract::{Path, Query},
+    http::HeaderMap,
     response::IntoResponse,
-    routing::{delete, get, patch, post},
+    routing::{delete, get, patch, post, put},
     Json, Router,
 };
 use chrono::{DateTime, Utc};
+use deadpool_postgres::tokio_postgres;
 use heck::{ToLowerCamelCase, ToSnakeCase};
 use regex::Regex;
-use sea_query::{Alias, Expr, PostgresQueryBuilder, SimpleExpr, Value};
+use sea_query::{Alias, Expr, Func, Order, PostgresQueryBuilder, SimpleExpr};
 use serde_json::json;
-use tracing_unwrap::ResultExt;
-use validator::ValidationErrors;
+use tracing::error;
+use validator::{ValidationError, ValidationErrors};
 
 use crate::{
-    middleware::extractors::{AnyUser, ProjectDatabase},
+    middleware::extractors::{Caller, ProjectDatabase},
     state::AppState,
     util,
 };
 
+use super::{check_version, conflict, etag, pool_error};
+
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/rows", get(rows))
         .route("/row", get(row))
         .route("/create", post(create))
-        .route("/update", patch(update))
+        .route("/update", patch(update).put(replace))
         .route("/delete", delete(remove))
 }
 
+/// Builds equality filters from the remaining query keys, rejecting unknown fields and values
+/// that don't fit them
+fn filters(
+    custom_table: &CustomTableSchema,
+    query: &HashMap<String, String>,
+) -> Result<Vec<SimpleExpr>, Error> {
+    query
+        .iter()
+        .map(|(key, value)| {
+            let name = key.to_snake_case();
+            let expr = match name.as_str() {
+                "id" => Some(value.as_str().into()),
+                "created_at" | "updated_at" => DateTime::parse_from_rfc3339(value)
+                    .ok()
+                    .map(|v| v.with_timezone(&Utc).into()),
+                _ => custom_table
+                    .fields
+                    .iter()
+                    .find(|f| f.name == name)
+                    .ok_or_else(|| Error::BadRequest(format!("Unknown field '{key}'")))?
+                    .filter_value(value),
+            };
+            let expr =
+                expr.ok_or_else(|| Error::BadRequest(format!("Invalid value for '{key}'")))?;
+
+            Ok(Expr::col(Alias::new(name)).eq(expr))
+        })
+        .collect()
+}
+
+/// A row's version is when it was last written
+fn row_version(row: &serde_json::Value) -> Option<DateTime<Utc>> {
+    ["updatedAt", "createdAt"].into_iter().find_map(|key| {
+        let version = row.get(key)?.as_str()?;
+        DateTime::parse_from_rfc3339(version)
+            .ok()
+            .map(|v| v.with_timezone(&Utc))
+    })
+}
+
+/// Matches rows still at `version`
+fn version_filter(version: DateTime<Utc>) -> SimpleExpr {
+    Expr::expr(Func::coalesce([
+        Expr::col(Alias::new("updated_at")).into(),
+        Expr::col(Alias::new("created_at")).into(),
+    ]))
+    .eq(version)
+}
+
+/// The body value of `field`, sent in camelCase or snake_case
+fn body_value<'a>(
+    body: &'a HashMap<String, serde_json::Value>,
+    field: &Field,
+) -> Option<&'a serde_json::Value> {
+    body.get(&field.name.to_lower_camel_case())
+        .or_else(|| body.get(&field.name))
+}
+
+/// Changes to a many relation. A plain array replaces every relation, while an object can
+/// `set`, `add` and `remove` ids.
+struct RelationChanges {
+    set: Option<Vec<String>>,
+    add: Vec<String>,
+    remove: Vec<String>,
+}
+
+impl RelationChanges {
+    fn parse(
+        field: &Field,
+        value: Option<&serde_json::Value>,
+    ) -> Result<Self, Vec<ValidationError>> {
+        let ids = |value: Option<&serde_json::Value>| match value {
+            None => Ok(vec![]),
+            Some(value) => value
+                .as_array()
+                .and_then(|ids| {
+                    ids.iter()
+                        .map(|id| id.as_str().map(str::to_string))
+                        .collect::<Option<Vec<_>>>()
+                })
+                .ok_or_else(|| {
+                    vec![util::create_validation_error(
+                        "type",
+                        Some("Expected an array of ids".into()),
+                    )]
+                }),
+        };
+
+        match value.filter(|v| !v.is_null()) {
+            Some(serde_json::Value::Object(changes)) => {
+                let set = match changes.get("set") {
+                    Some(set) => {
+                        field.validate(Some(set))?;
+                        Some(ids(Some(set))?)
+                    }
+                    None => None,
+                };
+
+                Ok(Self {
+                    set,
+                    add: ids(changes.get("add"))?,
+                    remove: ids(changes.get("remove"))?,
+                })
+            }
+            value => {
+                if let Err(errors) = field.validate(value)
+                    && !errors.is_empty()
+                {
+                    return Err(errors);
+                }
+
+                Ok(Self {
+                    set: Some(ids(value)?),
+                    add: vec![],
+                    remove: vec![],
+                })
+            }
+        }
+    }
+
+    fn queries(self, custom_table: &CustomTableSchema, field: &Field, row_id: &str) -> Vec<String> {
+        let mut queries = vec![];
+        let mut insert = |relations: Vec<String>| {
+            for query in
+                ManyToManyRelationTable::insert_query(custom_table, field, row_id.into(), relations)
+            {
+                queries.push(query.to_string(PostgresQueryBuilder));
+            }
+        };
+        let delete = |relations: Option<Vec<String>>| {
+            ManyToManyRelationTable::delete_query(custom_table, field, row_id.into(), relations)
+                .to_string(PostgresQueryBuilder)
+        };
+
+        let mut deletes = vec![];
+        if let Some(set) = self.set {
+            deletes.push(delete(None));
+            insert(set);
+        }
+        if !self.remove.is_empty() {
+            deletes.push(delete(Some(self.remove)));
+        }
+        if !self.add.is_empty() {
+            // Re-adding an existing relation shouldn't duplicate it
+            deletes.push(delete(Some(self.add.clone())));
+            insert(self.add);
+        }
+
+        deletes.into_iter().chain(queries).collect()
+    }
+}
+
+/// The tables whose permissions apply to the rows joined for `user`, none when unrestricted
+async fn related_tables(
+    user: Option<&entities::AnyUser>,
+    db: &deadpool_postgres::Pool,
+) -> Result<Vec<CustomTableSchema>, Error> {
+    match user {
+        Some(_) => CustomTableSchema::find().all(db).await,
+        None => Ok(vec![]),
+    }
+}
+
+/// The joined row with `id`, hiding the fields `user` can't view
+async fn find_row(
+    custom_table: &CustomTableSchema,
+    id: &str,
+    user: Option<&entities::AnyUser>,
+    db: &deadpool_postgres::Pool,
+) -> Result<serde_json::Value, Error> {
+    let mut builder = CustomTableSelectBuilder::from(custom_table);
+    builder
+        .and_where(vec![Expr::col(Alias::new("id")).eq(id)])
+        .join(&related_tables(user, db).await?, user)?;
+    if let Some(user) = user {
+        apply_field_permissions(&mut builder, custom_table, &[], user)?;
+    }
+
+    builder
+        .finish(db)
+        .await?
+        .as_array()
+        .and_then(|rows| rows.first().cloned())
+        .ok_or(Error::NotFound)
+}
+
+/// Keeps API keys to the tables and operations they were scoped to
+fn check_scope(
+    user_type: &AlternateUserType,
+    custom_table: &CustomTableSchema,
+    operation: Operation,
+) -> Result<(), Error> {
+    match user_type {
+        AlternateUserType::Service(key) if !key.allows(&custom_table.name, operation) => {
+            Err(Error::Forbidden(format!(
+                "This API key can't {operation} rows of '{}'",
+                custom_table.name
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Adds an `unknown` error for every body key that isn't a field of the table
+fn reject_unknown_keys(
+    custom_table: &CustomTableSchema,
+    body: &HashMap<String, serde_json::Value>,
+    allowed: &[&str],
+    errors: &mut ValidationErrors,
+) {
+    for key in body.keys() {
+        let name = key.to_snake_case();
+        if allowed.contains(&key.as_str()) || custom_table.fields.iter().any(|f| f.name == name) {
+            continue;
+        }
+
+        errors.add(
+            util::string_to_static_str(key.clone()),
+            ValidationError::new("unknown"),
+        );
+    }
+}
+
+fn parse_page_param(query: &mut HashMap<String, String>, key: &str) -> Result<Option<u64>, Error> {
+    query
+        .remove(key)
+        .map(|value| {
+            value
+                .parse::<u64>()
+                .ok()
+                .filter(|v| *v > 0)
+                .ok_or_else(|| Error::BadRequest(format!("'{key}' must be a positive integer")))
+        })
+        .transpose()
+}
+
+/// Turns database errors caused by the submitted row into 400s
+fn write_error(error: tokio_postgres::Error) -> Error {
+    let Some(db_error) = error.as_db_error() else {
+        return Error::InternalServerError("Unable to convert error".to_string());
+    };
+
+    if let Some(routine) = db_error.routine()
+        && let Ok(postgres::Error::UniqueKeyViolation) = postgres::Error::try_from(routine)
+    {
+        let pre = Regex::new(r"\(.+\)=\('.+'\)").unwrap();
+
+        let Some(detail) = db_error.detail() else {
+            return Error::InternalServerError("Unable to get error info".to_string());
+        };
+        let Some(matched) = pre.find(detail) else {
+            return Error::InternalServerError("Invalid error details".to_string());
+        };
+
+        let mut details = matched.as_str().split('=').collect::<Vec<_>>().into_iter();
+
+        let Some(key) = details.next() else {
+            return Error::InternalServerError("Invalid error details".to_string());
+        };
+        let Some(value) = details.next() else {
+            return Error::InternalServerError("Invalid error details".to_string());
+        };
+
+        return Error::BadRequest(format!(
+            "Key '{}' already exists with value '{}'",
+            key.replace(['(', ')'], ""),
+            value.replace("('", "").replace("')", "")
+        ));
+    }
+
+    // Class 22 is invalid data and class 23 broken constraints, both caused by the request
+    let code = db_error.code().code();
+    if code.starts_with("22") || code.starts_with("23") {
+        return Error::BadRequest(db_error.message().to_string());
+    }
+
+    error!(error = ?error);
+    Error::InternalServerError("An error occurred while saving the row".to_string())
+}
+
+/// Applies reverse relation expansions (`expand=posts`, `expand.posts.limit=5`,
+/// `expand.posts.sort=-created_at`) and count filters (`posts.count=>5`), removing
+/// their keys from the query. Restricted callers only see the related rows they can view.
+async fn apply_reverse_relations(
+    builder: &mut CustomTableSelectBuilder,
+    custom_table: &CustomTableSchema,
+    query: &mut HashMap<String, String>,
+    user: Option<&entities::AnyUser>,
+    db: &deadpool_postgres::Pool,
+) -> Result<(), Error> {
+    let count_keys = query
+        .keys()
+        .filter(|key| key.ends_with(".count"))
+        .cloned()
+        .collect::<Vec<_>>();
+    if !query.contains_key("expand") && count_keys.is_empty() {
+        return Ok(());
+    }
+
+    let tables = CustomTableSchema::find().all(db).await?;
+    let relations = ReverseRelation::all(custom_table, &tables);
+
+    if let Some(expand) = query.remove("expand") {
+        for name in expand.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            let relation = ReverseRelation::find(&relations, name)?;
+
+            let limit = query
+                .remove(&format!("expand.{name}.limit"))
+                .map(|limit| {
+                    limit
+                        .parse::<u64>()
+                        .map_err(|_| Error::BadRequest(format!("Invalid limit for '{name}'")))
+                })
+                .transpose()?;
+            let sort = query.remove(&format!("expand.{name}.sort")).map(|sort| {
+                match sort.strip_prefix('-') {
+                    Some(column) => (column.to_snake_case(), Order::Desc),
+                    None => (sort.to_snake_case(), Order::Asc),
+                }
+            });
+
+            builder.expand(relation, limit, sort, user)?;
+        }
+    }
+    query.retain(|key, _| !key.starts_with("expand."));
+
+    for key in count_keys {
+        let Some(value) = query.remove(&key) else {
+            continue;
+        };
+
+        let relation = ReverseRelation::find(&relations, key.trim_end_matches(".count"))?;
+        let (operator, count) = CountOperator::parse(&value)?;
+        builder.count_where(relation, operator, count, user)?;
+    }
+
+    Ok(())
+}
+
+/// Applies geo point filters (`location.near=lat,lng,meters`,
+/// `location.within=south,west,north,east`) and distance ordering (`location.sort=asc`, which
+/// needs a `near` point), removing their keys from the query
+fn apply_geo_filters(
+    builder: &mut CustomTableSelectBuilder,
+    custom_table: &CustomTableSchema,
+    query: &mut HashMap<String, String>,
+) -> Result<(), Error> {
+    for field in &custom_table.fields {
+        if !matches!(field.info, FieldInfo::GeoPoint { .. }) {
+            continue;
+        }
+
+        let key = field.name.to_lower_camel_case();
+        let mut take = |suffix: &str| {
+            query
+                .remove(&format!("{key}.{suffix}"))
+                .or_else(|| query.remove(&format!("{}.{suffix}", field.name)))
+        };
+
+        let near = take("near");
+        let within = take("within");
+        let sort = take("sort");
+
+        if let Some(within) = within {
+            builder.within(&field.name, geo::parse_within(&within, &key)?);
+        }
+
+        let order = sort
+            .map(|sort| match sort.as_str() {
+                "asc" => Ok(Order::Asc),
+                "desc" => Ok(Order::Desc),
+                _ => Err(Error::BadRequest(format!("Invalid sort for '{key}'"))),
+            })
+            .transpose()?;
+        match near {
+            Some(near) => {
+                let (point, meters) = geo::parse_near(&near, &key)?;
+                builder.near(&field.name, point, meters, order);
+            }
+            None if order.is_some() => {
+                return Err(Error::BadRequest(format!(
+                    "Sorting '{key}' by distance needs a 'near' point"
+                )));
+            }
+            None => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Applies range filters (`starts_at.gte=2024-01-01`, `duration.lt=3600`) on comparable
+/// fields, removing their keys from the query
+fn apply_range_filters(
+    builder: &mut CustomTableSelectBuilder,
+    custom_table: &CustomTableSchema,
+    query: &mut HashMap<String, String>,
+) -> Result<(), Error> {
+    for field in &custom_table.fields {
+        for operator in ["gt", "gte", "lt", "lte"] {
+            let key = format!("{}.{operator}", field.name.to_lower_camel_case());
+            let Some(value) = query
+                .remove(&key)
+                .or_else(|| query.remove(&format!("{}.{operator}", field.name)))
+            else {
+                continue;
+            };
+
+            let value = field
+                .range_value(&value)
+                .ok_or_else(|| Error::BadRequest(format!("Invalid value for '{key}'")))?;
+            let column = Expr::col(Alias::new(&field.name));
+            builder.and_where(vec![match operator {
+                "gt" => column.gt(value),
+                "gte" => column.gte(value),
+                "lt" => column.lt(value),
+                _ => column.lte(value),
+            }]);
+        }
+    }
+
+    Ok(())
+}
+
+/// The fields a query filters or sorts on, from keys like `status`, `startsAt.gte` or
+/// `location.near`. Taken before the filters consume their keys.
+fn queried_fields(query: &HashMap<String, String>) -> Vec<String> {
+    query
+        .keys()
+        .map(|key| key.split('.').next().unwrap_or(key).to_snake_case())
+        .collect()
+}
+
+/// Hides fields whose view rule fails for `user`. Filters on those fields are limited to
+/// rows where they're visible so their values can't be probed.
+fn apply_field_permissions(
+    builder: &mut CustomTableSelectBuilder,
+    custom_table: &CustomTableSchema,
+    queried: &[String],
+    user: &entities::AnyUser,
+) -> Result<(), Error> {
+    for (name, condition) in custom_table
+        .permissions
+        .field_view_conds(custom_table, user)?
+    {
+        if queried.contains(&name) {
+            builder.cond_where(condition.clone());
+        }
+        builder.restrict(&name, condition);
+    }
+
+    Ok(())
+}
+
 pub async fn rows(
-    AnyUser(user, user_type): AnyUser,
+    Caller(user, user_type): Caller,
     Path(path): Path<String>,
     ProjectDatabase(db): ProjectDatabase,
     Query(mut query): Query<HashMap<String, String>>,
@@ -54,27 +518,27 @@ pub async fn rows(
         .by_name(path.clone())
         .one(&db)
         .await?;
+    check_scope(&user_type, &custom_table, Operation::View)?;
 
-    let page = query.get("page").map(|s| s.parse::<u64>().unwrap());
-    let limit = query.get("limit").map(|s| s.parse::<u64>().unwrap());
-    query.remove("page");
-    query.remove("limit");
+    let page = parse_page_param(&mut query, "page")?;
+    let limit = parse_page_param(&mut query, "limit")?;
+    let queried = queried_fields(&query);
 
+    let viewer = user_type.is_restricted().then_some(&user);
     let mut builder = CustomTableSelectBuilder::from(&custom_table);
+    apply_reverse_relations(&mut builder, &custom_table, &mut query, viewer, &db).await?;
+    apply_geo_filters(&mut builder, &custom_table, &mut query)?;
+    apply_range_filters(&mut builder, &custom_table, &mut query)?;
     builder
-        .and_where(
-            query
-                .iter()
-                .map(|(field, equals)| Expr::col(Alias::new(field)).eq(equals))
-                .collect(),
-        )
+        .and_where(filters(&custom_table, &query)?)
         .paginate(page, limit)
-        .join();
+        .join(&related_tables(viewer, &db).await?, viewer)?;
 
-    if matches!(user_type, AlternateUserType::Normal) {
+    if user_type.is_restricted() {
         if let Some(permission) = custom_table.permissions.view.clone() {
             builder.cond_where(Permission::parse(&custom_table, permission)?.to_sql_cond(&user));
         }
+        apply_field_permissions(&mut builder, &custom_table, &queried, &user)?;
     }
 
     let rows = builder.finish(&db).await?;
@@ -95,74 +559,80 @@ pub async fn rows(
 }
 
 pub async fn row(
-    AnyUser(user, user_type): AnyUser,
+    Caller(user, user_type): Caller,
     Path(path): Path<String>,
     ProjectDatabase(db): ProjectDatabase,
-    Query(query): Query<HashMap<String, String>>,
+    Query(mut query): Query<HashMap<String, String>>,
 ) -> Result<impl IntoResponse, Error> {
     let custom_table = CustomTableSchema::find()
         .by_name(path.clone())
         .one(&db)
         .await?;
+    check_scope(&user_type, &custom_table, Operation::View)?;
 
+    let queried = queried_fields(&query);
+    let viewer = user_type.is_restricted().then_some(&user);
     let mut builder = CustomTableSelectBuilder::from(&custom_table);
+    apply_reverse_relations(&mut builder, &custom_table, &mut query, viewer, &db).await?;
+    apply_geo_filters(&mut builder, &custom_table, &mut query)?;
+    apply_range_filters(&mut builder, &custom_table, &mut query)?;
     builder
-        .and_where(
-            query
-                .iter()
-                .map(|(field, equals)| Expr::col(Alias::new(field)).eq(equals))
-                .collect(),
-        )
-        .join();
+        .and_where(filters(&custom_table, &query)?)
+        .join(&related_tables(viewer, &db).await?, viewer)?;
 
-    if matches!(user_type, AlternateUserType::Normal) {
+    if user_type.is_restricted() {
         if let Some(permission) = custom_table.permissions.view.clone() {
             builder.cond_where(Permission::parse(&custom_table, permission)?.to_sql_cond(&user));
         }
+        apply_field_permissions(&mut builder, &custom_table, &queried, &user)?;
     }
 
-    let row = builder.finish(&db).await?;
-    Ok(Json(row.as_array().unwrap().first().cloned()))
+    let row = builder
+        .finish(&db)
+        .await?
+        .as_array()
+        .and_then(|rows| rows.first().cloned());
+    let headers = row
+        .as_ref()
+        .and_then(row_version)
+        .map(etag)
+        .unwrap_or_default();
+
+    Ok((headers, Json(row)))
 }
 
 pub async fn create(
-    _: AnyUser,
+    Caller(user, user_type): Caller,
     Path(path): Path<String>,
     ProjectDatabase(db): ProjectDatabase,
-    Json(body): Json<HashMap<String, serde_json::Value>>,
+    Json(mut body): Json<HashMap<String, serde_json::Value>>,
 ) -> Result<impl IntoResponse, Error> {
     let custom_table = CustomTableSchema::find()
         .by_name(path.to_snake_case())
         .one(&db)
         .await?;
+    check_scope(&user_type, &custom_table, Operation::Create)?;
 
-    let id = Id::new().to_string();
     let mut errors = ValidationErrors::new();
+    reject_unknown_keys(&custom_table, &body, &["id"], &mut errors);
+
+    custom_table
+        .hooks
+        .run_before(&HookEvent::Create, &mut body)?;
+
+    let id = body
+        .get("id")
+        .and_then(|id| id.as_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| Id::new().to_string());
     let mut table_values: Vec<(_, SimpleExpr)> = vec![
         ("id", id.clone().into()),
         ("created_at", Utc::now().into()),
         ("updated_at", None::<DateTime<Utc>>.into()),
     ];
 
-    table_values = table_values
-        .clone()
-        .into_iter()
-        .map(|(key, value)| {
-            if key != "id" {
-                return (key, value);
-            }
-
-            let Some(Some(id)) = body.get("id").map(|f| f.as_str()) else {
-                return (key, value);
-            };
-
-            (key, id.into())
-        })
-        .collect::<Vec<_>>();
-
     custom_table.fields.iter().for_each(|field| {
-        let validation_results =
-            field.validate(body.get(&field.name.clone().to_lower_camel_case()));
+        let validation_results = field.validate(body_value(&body, field));
 
         match validation_results {
             Ok(value) => {
@@ -184,6 +654,7 @@ pub async fn create(
             }
         }
     });
+    rules::check(&custom_table.rules, &body, &mut errors);
 
     let insert_queries = custom_table
         .fields
@@ -195,13 +666,11 @@ pub async fn create(
 
             match target {
                 RelationTarget::Many => {
-                    let values = body
-                        .get(&field.name.clone().to_lower_camel_case())
-                        .unwrap()
-                        .as_array()
-                        .unwrap()
+                    // Already checked to be an array of ids by `Field::validate`
+                    let values = body_value(&body, field)?
+                        .as_array()?
                         .iter()
-                        .map(|v| v.as_str().unwrap().to_string())
+                        .filter_map(|v| v.as_str().map(str::to_string))
                         .collect::<Vec<_>>();
 
                     Some(ManyToManyRelationTable::insert_query(
@@ -224,9 +693,10 @@ pub async fn create(
         });
     }
 
-    db.get()
-        .await
-        .unwrap()
+    // Unknown relation ids break the junction's foreign keys, rolling back the row with them
+    let mut conn = db.get().await.map_err(pool_error)?;
+    let transaction = conn.transaction().await.map_err(write_error)?;
+    transaction
         .execute(
             sea_query::Query::insert()
                 .into_table(Alias::new(&custom_table.name))
@@ -237,131 +707,154 @@ pub async fn create(
             &[],
         )
         .await
-        .map_err(|error| {
-            let Some(db_error) = error.as_db_error() else {
-                return Error::InternalServerError("Unable to convert error".to_string());
-            };
-            let Some(routine) = db_error.routine() else {
-                return Error::InternalServerError("Unable to get error info".to_string());
-            };
-            let Some(error) = postgres::Error::try_from(routine).ok() else {
-                return Error::InternalServerError("Unsupported database error code".to_string());
-            };
-
-            match error {
-                postgres::Error::UniqueKeyViolation => {
-                    let pre = Regex::new(r"\(.+\)=\('.+'\)").unwrap();
-
-                    let Some(detail) = db_error.detail() else {
-                        return Error::InternalServerError("Unable to get error info".to_string());
-                    };
-                    let Some(matched) = pre.find(detail) else {
-                        return Error::InternalServerError("Invalid error details".to_string());
-                    };
-
-                    let mut details = matched.as_str().split('=').collect::<Vec<_>>().into_iter();
-
-                    let Some(key) = details.next() else {
-                        return Error::InternalServerError("Invalid error details".to_string());
-                    };
-                    let Some(value) = details.next() else {
-                        return Error::InternalServerError("Invalid error details".to_string());
-                    };
-
-                    Error::BadRequest(format!(
-                        "Key '{}' already exists with value '{}'",
-                        key.replace(['(', ')'], ""),
-                        value.replace("('", "").replace("')", "")
-                    ))
-                }
-                _ => todo!(),
-            }
-        })?;
+        .map_err(write_error)?;
 
     for query in insert_queries {
-        db.get()
-            .await
-            .unwrap()
+        transaction
             .execute(query.to_string(PostgresQueryBuilder).as_str(), &[])
             .await
-            .unwrap_or_log();
+            .map_err(write_error)?;
+    }
+    transaction.commit().await.map_err(write_error)?;
+
+    // Read back so computed columns and relations are included
+    let data = find_row(&custom_table, &id, None, &db).await?;
+
+    custom_table
+        .hooks
+        .run_after(&HookEvent::Create, &custom_table, &data, &db)
+        .await;
+
+    webhooks::dispatch(
+        &db,
+        WebhookEvent::RowCreate,
+        json!({ "table": custom_table.name, "row": data }),
+    );
+
+    let headers = row_version(&data).map(etag).unwrap_or_default();
+    match user_type.is_restricted() {
+        true => Ok((
+            headers,
+            Json(find_row(&custom_table, &id, Some(&user), &db).await?),
+        )),
+        false => Ok((headers, Json(data))),
     }
-
-    let mut data = json!({});
-
-    table_values
-        .into_iter()
-        .filter_map(|(name, value)| {
-            let camel_case_name = heck::AsLowerCamelCase(name).to_string();
-            let camel_case_name = camel_case_name.as_str();
-
-            match value {
-                SimpleExpr::Value(value) => match value {
-                    Value::String(value) => Some(json!({ camel_case_name: value })),
-                    Value::BigInt(value) => Some(json!({ camel_case_name: value })),
-                    Value::Bool(value) => Some(json!({ camel_case_name: value })),
-                    Value::ChronoDateTimeUtc(value) => Some(json!({ camel_case_name: value })),
-                    Value::Array(_, Some(value)) => {
-                        let value = value
-                            .iter()
-                            .filter_map(|value| match value {
-                                Value::String(value) => Some(value),
-                                _ => None,
-                            })
-                            .collect::<Vec<_>>();
-
-                        Some(json!({ camel_case_name: value }))
-                    }
-                    _ => None,
-                },
-                _ => None,
-            }
-        })
-        .for_each(|patch| json_patch::merge(&mut data, &patch));
-
-    Ok(Json(data))
 }
 
 pub async fn update(
-    _: AnyUser,
+    Caller(user, user_type): Caller,
     Path(path): Path<String>,
     ProjectDatabase(db): ProjectDatabase,
     Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
     Json(body): Json<HashMap<String, serde_json::Value>>,
 ) -> Result<impl IntoResponse, Error> {
+    update_row(user, user_type, path, db, query, headers, body, true).await
+}
+
+pub async fn replace(
+    Caller(user, user_type): Caller,
+    Path(path): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+    Json(body): Json<HashMap<String, serde_json::Value>>,
+) -> Result<impl IntoResponse, Error> {
+    update_row(user, user_type, path, db, query, headers, body, false).await
+}
+
+/// Updates the row matching `query`. A `partial` update only touches the supplied fields, a full
+/// one clears every field missing from `body`.
+#[allow(clippy::too_many_arguments)]
+async fn update_row(
+    user: entities::AnyUser,
+    user_type: AlternateUserType,
+    path: String,
+    db: deadpool_postgres::Pool,
+    query: HashMap<String, String>,
+    headers: HeaderMap,
+    mut body: HashMap<String, serde_json::Value>,
+    partial: bool,
+) -> Result<(HeaderMap, Json<serde_json::Value>), Error> {
     let custom_table = CustomTableSchema::find()
         .by_name(path.clone())
         .one(&db)
         .await?;
+    check_scope(&user_type, &custom_table, Operation::Update)?;
 
-    let mut db_query = sea_query::Query::update();
-    // TODO(@Xenfo): Add support for multiple rows
-    db_query.limit(1);
+    let expected_version = body.remove("expectedVersion");
 
-    query.iter().for_each(|(field, equals)| {
-        db_query.and_where(Expr::col(Alias::new(field.to_snake_case())).eq(equals));
-    });
+    custom_table
+        .hooks
+        .run_before(&HookEvent::Update, &mut body)?;
+
+    // TODO(@Xenfo): Add support for multiple rows
+    let mut builder = CustomTableSelectBuilder::from(&custom_table);
+    let existing = builder
+        .and_where(filters(&custom_table, &query)?)
+        .finish(&db)
+        .await?
+        .as_array()
+        .and_then(|rows| rows.first().cloned())
+        .ok_or(Error::NotFound)?;
+    let id = existing
+        .get("id")
+        .and_then(|id| id.as_str())
+        .map(str::to_string)
+        .ok_or(Error::NotFound)?;
+    let version = row_version(&existing).ok_or(Error::NotFound)?;
+    check_version(
+        &headers,
+        expected_version.as_ref().and_then(|v| v.as_str()),
+        version,
+    )?;
 
     let mut errors = ValidationErrors::new();
+    reject_unknown_keys(&custom_table, &body, &[], &mut errors);
     let mut table_values: Vec<(_, SimpleExpr)> = vec![("updated_at", Utc::now().into())];
+    let mut relation_queries = vec![];
+
+    for field in &custom_table.fields {
+        let value = body_value(&body, field);
+        let result = match &field.info {
+            FieldInfo::Relation {
+                target: RelationTarget::Many,
+                ..
+            } => {
+                if partial && value.is_none() {
+                    continue;
+                }
 
-    custom_table.fields.iter().for_each(|field| {
-        let validation_results = field.validate(body.get(&field.name.clone().to_snake_case()));
-
-        match validation_results {
-            Ok(value) => {
-                table_values.push((&field.name, value));
-            }
-            Err(validation_errors) => {
-                validation_errors.iter().for_each(|error| {
-                    errors.add(
-                        util::string_to_static_str(field.name.clone().to_snake_case()),
-                        error.clone(),
-                    );
-                });
+                RelationChanges::parse(field, value).map(|changes| {
+                    relation_queries.extend(changes.queries(&custom_table, field, &id));
+                })
             }
+            _ => field
+                .validate_update(value, partial)
+                .map(|value| table_values.extend(value.map(|value| (field.name.as_str(), value)))),
+        };
+
+        if let Err(validation_errors) = result {
+            validation_errors.into_iter().for_each(|error| {
+                errors.add(
+                    util::string_to_static_str(field.name.to_lower_camel_case()),
+                    error,
+                );
+            });
         }
-    });
+    }
+
+    // Rules see the row as it will be after the update
+    let mut payload = match partial {
+        true => serde_json::from_value::<HashMap<String, serde_json::Value>>(existing)
+            .unwrap_or_default(),
+        false => HashMap::new(),
+    };
+    payload.extend(
+        body.iter()
+            .map(|(key, value)| (key.to_lower_camel_case(), value.clone())),
+    );
+    rules::check(&custom_table.rules, &payload, &mut errors);
 
     if !errors.is_empty() {
         return Err(Error::ValidationErrors {
@@ -370,80 +863,131 @@ pub async fn update(
         });
     }
 
-    db.get()
-        .await
-        .unwrap()
-        .execute(
-            db_query
-                .table(Alias::new(&custom_table.name))
-                .values(
-                    table_values
-                        .clone()
-                        .into_iter()
-                        .map(|(f, v)| (Alias::new(f), v.clone())),
-                )
-                .to_string(PostgresQueryBuilder)
-                .as_str(),
-            &[],
-        )
-        .await
-        .unwrap();
-
-    let mut data = json!({});
-
-    table_values
-        .into_iter()
-        .filter_map(|(name, value)| {
-            let camel_case_name = heck::AsLowerCamelCase(name).to_string();
-            let camel_case_name = camel_case_name.as_str();
-
-            match value {
-                SimpleExpr::Value(value) => match value {
-                    Value::String(value) => Some(json!({ camel_case_name: value })),
-                    Value::BigInt(value) => Some(json!({ camel_case_name: value })),
-                    Value::Bool(value) => Some(json!({ camel_case_name: value })),
-                    Value::ChronoDateTimeUtc(value) => Some(json!({ camel_case_name: value })),
-                    Value::Array(_, Some(value)) => {
-                        let value = value
-                            .iter()
-                            .filter_map(|value| match value {
-                                Value::String(value) => Some(value),
-                                _ => None,
-                            })
-                            .collect::<Vec<_>>();
-
-                        Some(json!({ camel_case_name: value }))
-                    }
-                    _ => None,
-                },
-                _ => None,
+    if user_type.is_restricted() {
+        for (name, rules) in &custom_table.permissions.fields {
+            let Some(rule) = rules.update.clone() else {
+                continue;
+            };
+            if partial && !body.keys().any(|key| key.to_snake_case() == *name) {
+                continue;
             }
-        })
-        .for_each(|patch| json_patch::merge(&mut data, &patch));
 
-    Ok(Json(data))
+            let mut builder = CustomTableSelectBuilder::from(&custom_table);
+            builder
+                .and_where(vec![Expr::col(Alias::new("id")).eq(id.as_str())])
+                .cond_where(Permission::parse(&custom_table, rule)?.to_sql_cond(&user));
+
+            let count = builder
+                .count()
+                .finish(&db)
+                .await?
+                .as_i64()
+                .unwrap_or_default();
+            if count == 0 {
+                return Err(Error::Forbidden(format!(
+                    "You can't update '{}'",
+                    name.to_lower_camel_case()
+                )));
+            }
+        }
+    }
+
+    let update_query = sea_query::Query::update()
+        .table(Alias::new(&custom_table.name))
+        .values(table_values.into_iter().map(|(f, v)| (Alias::new(f), v)))
+        .and_where(Expr::col(Alias::new("id")).eq(id.as_str()))
+        .and_where(version_filter(version))
+        .to_string(PostgresQueryBuilder);
+
+    // Failing relation changes roll back the update with them
+    let mut conn = db.get().await.map_err(pool_error)?;
+    let transaction = conn.transaction().await.map_err(write_error)?;
+    let updated = transaction
+        .execute(update_query.as_str(), &[])
+        .await
+        .map_err(write_error)?;
+    // Someone else wrote the row since it was read
+    if updated == 0 {
+        return Err(conflict());
+    }
+
+    for query in relation_queries {
+        transaction
+            .execute(query.as_str(), &[])
+            .await
+            .map_err(write_error)?;
+    }
+    transaction.commit().await.map_err(write_error)?;
+
+    let data = find_row(&custom_table, &id, None, &db).await?;
+
+    custom_table
+        .hooks
+        .run_after(&HookEvent::Update, &custom_table, &data, &db)
+        .await;
+
+    webhooks::dispatch(
+        &db,
+        WebhookEvent::RowUpdate,
+        json!({ "table": custom_table.name, "row": data }),
+    );
+
+    let headers = row_version(&data).map(etag).unwrap_or_default();
+    match user_type.is_restricted() {
+        true => Ok((
+            headers,
+            Json(find_row(&custom_table, &id, Some(&user), &db).await?),
+        )),
+        false => Ok((headers, Json(data))),
+    }
 }
 
 pub async fn remove(
-    _: AnyUser,
+    Caller(_, user_type): Caller,
     Path(path): Path<String>,
     ProjectDatabase(db): ProjectDatabase,
-    Query(query): Query<HashMap<String, String>>,
+    Query(mut query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse, Error> {
+    let expected_version = query.remove("expectedVersion");
+
     let custom_table = CustomTableSchema::find()
         .by_name(path.clone())
         .one(&db)
         .await?;
+    check_scope(&user_type, &custom_table, Operation::Delete)?;
+
+    let mut builder = CustomTableSelectBuilder::from(&custom_table);
+    builder
+        .and_where(filters(&custom_table, &query)?)
+        .join(&[], None)?;
+
+    let row = builder
+        .finish(&db)
+        .await?
+        .as_array()
+        .and_then(|rows| rows.first().cloned())
+        .ok_or(Error::NotFound)?;
+    let version = row_version(&row).ok_or(Error::NotFound)?;
+    check_version(&headers, expected_version.as_deref(), version)?;
+
+    let mut payload = serde_json::from_value::<HashMap<String, serde_json::Value>>(row.clone())
+        .unwrap_or_default();
+    custom_table
+        .hooks
+        .run_before(&HookEvent::Delete, &mut payload)?;
 
     let mut db_query = sea_query::Query::delete();
     // TODO(@Xenfo): Add support for multiple rows
     db_query.limit(1);
 
-    query.iter().for_each(|(field, equals)| {
-        db_query.and_where(Expr::col(Alias::new(field.to_snake_case())).eq(equals));
-    });
+    for filter in filters(&custom_table, &query)? {
+        db_query.and_where(filter);
+    }
+    db_query.and_where(version_filter(version));
 
-    db.get()
+    let deleted = db
+        .get()
         .await
         .unwrap()
         .execute(
@@ -454,7 +998,21 @@ pub async fn remove(
             &[],
         )
         .await
-        .unwrap();
+        .map_err(write_error)?;
+    if deleted == 0 {
+        return Err(conflict());
+    }
+
+    custom_table
+        .hooks
+        .run_after(&HookEvent::Delete, &custom_table, &row, &db)
+        .await;
+
+    webhooks::dispatch(
+        &db,
+        WebhookEvent::RowDelete,
+        json!({ "table": custom_table.name, "row": row }),
+    );
 
     Ok(Json(serde_json::Value::Null))
 }
@@ -1,33 +1,48 @@
+use std::collections::HashMap;
+
 use adrastos_core::{
     db::postgres,
-    entities::custom_table::{
-        fields::{Field, FieldInfo, RelationTarget},
-        mm_relation::ManyToManyRelationTable,
-        permissions::Permissions,
-        schema::{CustomTableSchema, UpdateCustomTableSchema},
+    entities::{
+        custom_table::{
+            computed,
+            fields::{Field, FieldInfo, RelationTarget},
+            hooks::Hooks,
+            mm_relation::ManyToManyRelationTable,
+            permissions::Permissions,
+            rules::{self, Rule},
+            schema::{CustomTableSchema, UpdateCustomTableSchema},
+            select,
+        },
+        ApiKey, Connection, Job, JobRun, Passkey, RefreshTokenTree, Role, System, TeamRole,
+        UploadMetadata, User, UserRole, Webhook, WebhookDelivery,
     },
     error::Error,
     id::Id,
 };
 use axum::{
     extract::Path,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
     routing::{delete, get, patch, post},
     Json, Router,
 };
-use chrono::Utc;
+use chrono::{DateTime, SecondsFormat, Utc};
+use deadpool_postgres::tokio_postgres::{self, error::SqlState};
 use heck::ToSnakeCase;
 use regex::Regex;
 use sea_query::{
-    Alias, Expr, ForeignKeyAction, Order, PostgresQueryBuilder, Table, TableCreateStatement,
+    Alias, Expr, ForeignKeyAction, Iden, Order, PostgresQueryBuilder, Table, TableCreateStatement,
     TableForeignKey,
 };
 use serde::Deserialize;
 use serde_json::Value;
-use tracing_unwrap::ResultExt;
+use tracing::error;
 
 use crate::{
-    middleware::extractors::{AnyUser, ProjectDatabase},
+    middleware::{
+        self,
+        extractors::{AnyUser, Member, ProjectDatabase},
+    },
     state::AppState,
 };
 
@@ -39,13 +54,24 @@ pub struct CreateBody {
     name: String,
     fields: Vec<Field>,
     permissions: Permissions,
+    #[serde(default)]
+    hooks: Hooks,
+    #[serde(default)]
+    rules: Vec<Rule>,
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase", tag = "action")]
 enum Action {
-    Create { field: Field },
-    Update { field: Field },
+    Create {
+        field: Field,
+    },
+    Update {
+        field: Field,
+        /// Old select option values mapped to the new ones
+        #[serde(default, rename = "optionRenames")]
+        option_renames: HashMap<String, String>,
+    },
     Delete,
 }
 
@@ -62,6 +88,9 @@ pub struct UpdateBody {
     name: Option<String>,
     fields: Option<Vec<UpdateField>>,
     permissions: Option<Permissions>,
+    hooks: Option<Hooks>,
+    rules: Option<Vec<Rule>>,
+    expected_version: Option<String>,
 }
 
 pub fn routes() -> Router<AppState> {
@@ -71,6 +100,128 @@ pub fn routes() -> Router<AppState> {
         .route("/update/:name", patch(update))
         .route("/delete/:name", delete(remove))
         .nest("/:name", custom::routes())
+        .layer(axum::middleware::from_fn(middleware::verified::run))
+}
+
+/// Custom tables share the project database with the built-in ones, like `users`, which
+/// relation fields can point at
+fn validate_name(name: &str) -> Result<(), Error> {
+    let reserved = [
+        System::table(),
+        User::table(),
+        Connection::table(),
+        RefreshTokenTree::table(),
+        CustomTableSchema::table(),
+        Passkey::table(),
+        UploadMetadata::table(),
+        Webhook::table(),
+        WebhookDelivery::table(),
+        Job::table(),
+        JobRun::table(),
+        ApiKey::table(),
+        Role::table(),
+        UserRole::table(),
+    ];
+    if reserved.iter().any(|table| table.to_string() == name) {
+        return Err(Error::BadRequest(format!(
+            "The table name '{name}' is reserved"
+        )));
+    }
+
+    Ok(())
+}
+
+/// Formats the version of a table or row as an `ETag` header
+fn etag(version: DateTime<Utc>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let tag = format!(
+        "\"{}\"",
+        version.to_rfc3339_opts(SecondsFormat::Micros, true)
+    );
+    if let Ok(value) = HeaderValue::from_str(&tag) {
+        headers.insert(header::ETAG, value);
+    }
+
+    headers
+}
+
+/// Fails with a conflict unless the version the client expects to overwrite, sent with
+/// `If-Match` or as `expected`, is `current`. `If-Match: *` matches any version.
+fn check_version(
+    headers: &HeaderMap,
+    expected: Option<&str>,
+    current: DateTime<Utc>,
+) -> Result<(), Error> {
+    let if_match = headers
+        .get(header::IF_MATCH)
+        .map(|value| {
+            value
+                .to_str()
+                .map_err(|_| Error::BadRequest("Invalid If-Match header".into()))
+        })
+        .transpose()?;
+
+    let expected = match if_match.map(str::trim) {
+        Some("*") => return Ok(()),
+        Some(tag) => tag.trim_start_matches("W/").trim_matches('"'),
+        None => match expected {
+            Some(expected) => expected,
+            None => return Ok(()),
+        },
+    };
+    let expected = DateTime::parse_from_rfc3339(expected)
+        .map_err(|_| Error::BadRequest(format!("Invalid version '{expected}'")))?;
+
+    if expected != current {
+        return Err(conflict());
+    }
+
+    Ok(())
+}
+
+fn pool_error(error: deadpool_postgres::PoolError) -> Error {
+    error!(error = ?error);
+    Error::InternalServerError("Failed to get a database connection".into())
+}
+
+fn conflict() -> Error {
+    Error::Custom(
+        StatusCode::CONFLICT,
+        "The resource was changed since it was read".into(),
+    )
+}
+
+/// Maps errors from creating a table or its junction tables, which can point at tables that
+/// don't exist or clash with existing ones
+fn create_error(error: tokio_postgres::Error) -> Error {
+    let Some(db_error) = error.as_db_error() else {
+        return Error::InternalServerError("Unable to convert error".to_string());
+    };
+    // Another request created the table since it was checked for
+    if db_error.code() == &SqlState::DUPLICATE_TABLE {
+        return Error::BadRequest("A table with this name already exists".into());
+    }
+    let Some(routine) = db_error.routine() else {
+        return Error::InternalServerError("Unable to get error info".to_string());
+    };
+    let Some(error) = postgres::Error::try_from(routine).ok() else {
+        return Error::InternalServerError("Unsupported database error code".to_string());
+    };
+
+    match error {
+        postgres::Error::NonExistentTable => {
+            let pre = Regex::new(r#"".+""#).unwrap();
+
+            let Some(matched) = pre.find(db_error.message()) else {
+                return Error::InternalServerError("Invalid error details".to_string());
+            };
+
+            let table_name = matched.as_str().replace('\"', "");
+
+            Error::BadRequest(format!("Table '{}' doesn't exist", table_name))
+        }
+        _ => Error::InternalServerError("Unable to create the table".to_string()),
+    }
 }
 
 pub async fn list(
@@ -82,10 +233,12 @@ pub async fn list(
 }
 
 pub async fn create(
-    _: AnyUser,
+    Member(member): Member,
     ProjectDatabase(db): ProjectDatabase,
     Json(body): Json<CreateBody>,
 ) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
     let custom_table = CustomTableSchema {
         id: Id::new().to_string(),
         name: body.name.to_snake_case(),
@@ -98,10 +251,21 @@ pub async fn create(
             })
             .collect(),
         permissions: body.permissions,
+        hooks: body.hooks,
+        rules: body.rules,
         created_at: Utc::now(),
         updated_at: None,
     };
 
+    validate_name(&custom_table.name)?;
+    custom_table
+        .hooks
+        .validate(&custom_table, &CustomTableSchema::find().all(&db).await?)?;
+    custom_table.permissions.validate(&custom_table)?;
+    rules::validate(&custom_table.rules, &custom_table)?;
+    computed::validate(&custom_table.fields)?;
+    select::validate(&custom_table.fields)?;
+
     let found_table = CustomTableSchema::find()
         .by_name(custom_table.name.clone())
         .one(&db)
@@ -112,9 +276,15 @@ pub async fn create(
         ));
     }
 
-    db.get()
-        .await
-        .unwrap()
+    // The table, its junction tables, rule constraints and schema are only saved together
+    let table_error = |e: tokio_postgres::Error| {
+        error!(error = ?e);
+        Error::InternalServerError("Failed to create the table".into())
+    };
+    let mut conn = db.get().await.map_err(pool_error)?;
+    let transaction = conn.transaction().await.map_err(table_error)?;
+
+    transaction
         .execute(
             TableCreateStatement::from(&custom_table)
                 .to_string(PostgresQueryBuilder)
@@ -122,68 +292,64 @@ pub async fn create(
             &[],
         )
         .await
-        .map_err(|error| {
-            let Some(db_error) = error.as_db_error() else {
-                return Error::InternalServerError("Unable to convert error".to_string());
-            };
-            let Some(routine) = db_error.routine() else {
-                return Error::InternalServerError("Unable to get error info".to_string());
-            };
-            let Some(error) = postgres::Error::try_from(routine).ok() else {
-                return Error::InternalServerError("Unsupported database error code".to_string());
-            };
-
-            match error {
-                postgres::Error::NonExistentTable => {
-                    let pre = Regex::new(r#"".+""#).unwrap();
-
-                    let Some(matched) = pre.find(db_error.message()) else {
-                        return Error::InternalServerError("Invalid error details".to_string());
-                    };
-
-                    let table_name = matched.as_str().replace('\"', "");
-
-                    Error::BadRequest(format!("Table '{}' doesn't exist", table_name))
-                }
-                _ => todo!(),
-            }
-        })?;
-
-    custom_table.create(&db).await?;
+        .map_err(create_error)?;
 
     for query in ManyToManyRelationTable::create_queries(&custom_table) {
-        db.get()
-            .await
-            .unwrap()
+        transaction
             .execute(query.to_string(PostgresQueryBuilder).as_str(), &[])
             .await
-            .unwrap_or_log();
+            .map_err(create_error)?;
     }
 
+    for query in custom_table
+        .rules
+        .iter()
+        .filter_map(|r| r.add_constraint_query(&custom_table.name))
+    {
+        transaction
+            .execute(query.as_str(), &[])
+            .await
+            .map_err(table_error)?;
+    }
+
+    custom_table.insert(&transaction).await?;
+    transaction.commit().await.map_err(table_error)?;
+
     Ok(Json(custom_table))
 }
 
 pub async fn update(
-    _: AnyUser,
+    Member(member): Member,
     Path(path): Path<String>,
     ProjectDatabase(db): ProjectDatabase,
+    headers: HeaderMap,
     Json(body): Json<UpdateBody>,
 ) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
     let custom_table = CustomTableSchema::find()
         .by_name(path.clone())
         .one(&db)
         .await?;
+    check_version(
+        &headers,
+        body.expected_version.as_deref(),
+        custom_table.version(),
+    )?;
 
     let mut altered = false;
     let mut alter_query = Table::alter();
     let mut additional_queries = vec![];
     let mut more_queries = vec![];
+    let mut after_queries = vec![];
     let mut update = UpdateCustomTableSchema {
         ..Default::default()
     };
 
     if let Some(name) = body.name {
         if name != custom_table.name {
+            validate_name(&name)?;
+
             let found_table = CustomTableSchema::find()
                 .by_name(name.clone())
                 .one(&db)
@@ -206,12 +372,20 @@ pub async fn update(
                 alter_query.add_column(&mut field.column());
                 altered = true;
             }
-            Action::Update { field } => {
+            Action::Update {
+                field,
+                option_renames,
+            } => {
                 updated_fields = updated_fields
                     .clone()
                     .into_iter()
                     .map(|f| {
                         if f.name == update.name {
+                            let (before, after) =
+                                select::migrate(&custom_table.name, &f, field, option_renames);
+                            more_queries.extend(before);
+                            after_queries.extend(after);
+
                             if let FieldInfo::Relation {
                                 table,
                                 target,
@@ -337,6 +511,20 @@ pub async fn update(
                                         }
                                     }
                                 }
+                            } else if let FieldInfo::Computed { .. } = &field.info {
+                                // Generation expressions can't be altered, so the column is recreated
+                                more_queries.push(
+                                    Table::alter()
+                                        .table(Alias::new(&custom_table.name))
+                                        .drop_column(Alias::new(&update.name))
+                                        .to_string(PostgresQueryBuilder),
+                                );
+                                more_queries.push(
+                                    Table::alter()
+                                        .table(Alias::new(&custom_table.name))
+                                        .add_column(&mut field.column())
+                                        .to_string(PostgresQueryBuilder),
+                                );
                             } else if update.name != field.name {
                                 alter_query.rename_column(
                                     Alias::new(&update.name),
@@ -363,25 +551,61 @@ pub async fn update(
             }
         });
 
+        computed::validate(&updated_fields)?;
+        select::validate(&updated_fields)?;
         update.fields = Some(updated_fields);
     }
 
+    if let Some(hooks) = body.hooks {
+        hooks.validate(
+            &CustomTableSchema {
+                fields: update.fields.clone().unwrap_or(custom_table.fields.clone()),
+                ..custom_table.clone()
+            },
+            &CustomTableSchema::find().all(&db).await?,
+        )?;
+
+        update.hooks = Some(hooks);
+    }
+
+    if let Some(permissions) = &body.permissions {
+        permissions.validate(&CustomTableSchema {
+            fields: update.fields.clone().unwrap_or(custom_table.fields.clone()),
+            ..custom_table.clone()
+        })?;
+    }
+
+    // Field changes can orphan the existing rules, so they're checked again too
+    if body.rules.is_some() || update.fields.is_some() {
+        rules::validate(
+            body.rules.as_ref().unwrap_or(&custom_table.rules),
+            &CustomTableSchema {
+                fields: update.fields.clone().unwrap_or(custom_table.fields.clone()),
+                ..custom_table.clone()
+            },
+        )?;
+    }
+
+    update.rules = body.rules;
     update.permissions = body.permissions;
-    custom_table.update(&db, update.clone()).await?;
+
+    // The table only changes along with the schema describing it
+    let table_error = |e: tokio_postgres::Error| {
+        error!(error = ?e);
+        Error::InternalServerError("Failed to update the table".into())
+    };
+    let mut conn = db.get().await.unwrap();
+    let transaction = conn.transaction().await.map_err(table_error)?;
 
     for query in more_queries {
-        db.get()
-            .await
-            .unwrap()
+        transaction
             .execute(query.as_str(), &[])
             .await
-            .unwrap_or_log();
+            .map_err(table_error)?;
     }
 
     if altered {
-        db.get()
-            .await
-            .unwrap()
+        transaction
             .execute(
                 alter_query
                     .table(Alias::new(&custom_table.name))
@@ -390,59 +614,89 @@ pub async fn update(
                 &[],
             )
             .await
-            .unwrap();
+            .map_err(table_error)?;
     }
 
-    for additional_query in additional_queries {
-        db.get()
-            .await
-            .unwrap()
-            .execute(additional_query.1.as_str(), &[])
-            .await
-            .unwrap();
-
-        db.get()
+    for (relation_table, query) in additional_queries {
+        transaction
+            .execute(query.as_str(), &[])
             .await
-            .unwrap()
+            .map_err(table_error)?;
+        transaction
             .execute(
-                sea_query::Table::drop()
-                    .table(Alias::new(additional_query.0))
+                Table::drop()
+                    .table(Alias::new(relation_table))
                     .to_string(PostgresQueryBuilder)
                     .as_str(),
                 &[],
             )
             .await
-            .unwrap();
+            .map_err(table_error)?;
     }
 
-    if let Some(name) = update.name {
-        db.get()
+    for query in after_queries {
+        transaction
+            .execute(query.as_str(), &[])
             .await
-            .unwrap()
+            .map_err(|_| {
+                Error::BadRequest(
+                    "Existing rows use options that were removed, map them to a remaining \
+                     option with 'optionRenames'"
+                        .into(),
+                )
+            })?;
+    }
+
+    if let Some(rules) = &update.rules {
+        let queries = custom_table
+            .rules
+            .iter()
+            .filter_map(|r| r.drop_constraint_query(&custom_table.name))
+            .chain(
+                rules
+                    .iter()
+                    .filter_map(|r| r.add_constraint_query(&custom_table.name)),
+            );
+
+        for query in queries {
+            transaction
+                .execute(query.as_str(), &[])
+                .await
+                .map_err(|_| {
+                    Error::BadRequest("Existing rows don't satisfy the table's rules".into())
+                })?;
+        }
+    }
+
+    if let Some(name) = &update.name {
+        transaction
             .execute(
                 Table::rename()
-                    .table(Alias::new(&custom_table.name), Alias::new(name.clone()))
+                    .table(Alias::new(&custom_table.name), Alias::new(name))
                     .to_string(PostgresQueryBuilder)
                     .as_str(),
                 &[],
             )
             .await
-            .unwrap();
+            .map_err(table_error)?;
     }
 
-    let custom_table = CustomTableSchema::find()
-        .by_name(path.clone())
-        .one(&db)
-        .await?;
+    let name = update.name.clone().unwrap_or(path);
+    custom_table.update(&transaction, update).await?;
+    transaction.commit().await.map_err(table_error)?;
 
-    Ok(Json(custom_table))
+    let custom_table = CustomTableSchema::find().by_name(name).one(&db).await?;
+
+    Ok((etag(custom_table.version()), Json(custom_table)))
 }
 
 pub async fn remove(
-    _: AnyUser,
+    Member(member): Member,
     Path(path): Path<String>,
     ProjectDatabase(db): ProjectDatabase,
 ) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
     let custom_table = CustomTableSchema::find()
         .by_name(path.clone())
         .one(&db)
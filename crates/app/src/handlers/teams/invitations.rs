@@ -0,0 +1,180 @@
+use adrastos_core::{
+    entities::{self, TeamInvitation, TeamMember, TeamRole},
+    error::Error,
+    id::Id,
+};
+use axum::{
+    extract::Path,
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    handlers::auth::send_email,
+    middleware::extractors::{Config, Mailer, SystemDatabase, SystemUser},
+    state::AppState,
+    util,
+};
+
+#[derive(Deserialize)]
+pub struct CreateBody {
+    email: String,
+    role: TeamRole,
+}
+
+#[derive(Deserialize)]
+pub struct AcceptBody {
+    token: String,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list))
+        .route("/create", post(create))
+        .route("/revoke/:id", delete(revoke))
+}
+
+pub async fn list(
+    SystemUser(user): SystemUser,
+    Path(team_id): Path<String>,
+    SystemDatabase(db): SystemDatabase,
+) -> Result<impl IntoResponse, Error> {
+    TeamMember::require(&db, &team_id, &user.id, TeamRole::Admin).await?;
+
+    let invitations = TeamInvitation::find().by_team_id(team_id).all(&db).await?;
+    Ok(Json(invitations))
+}
+
+/// Emails a link to join the team that expires in a week, only owners can invite owners
+pub async fn create(
+    mailer: Mailer,
+    SystemUser(user): SystemUser,
+    Path(team_id): Path<String>,
+    Config(config): Config,
+    SystemDatabase(db): SystemDatabase,
+    Json(body): Json<CreateBody>,
+) -> Result<impl IntoResponse, Error> {
+    let caller = TeamMember::require(&db, &team_id, &user.id, TeamRole::Admin).await?;
+    if body.role == TeamRole::Owner {
+        caller.check(TeamRole::Owner)?;
+    }
+
+    if !mailchecker::is_valid(body.email.as_str()) {
+        return Err(Error::BadRequest("Invalid email".into()));
+    }
+
+    if let Ok(invited) = entities::SystemUser::find()
+        .by_email(body.email.clone())
+        .one(&db)
+        .await
+        && TeamMember::find()
+            .by_team_id(team_id.clone())
+            .by_user_id(invited.id)
+            .one(&db)
+            .await
+            .is_ok()
+    {
+        return Err(Error::BadRequest("User is already a member".into()));
+    }
+
+    let team = entities::Team::find_by_id(&team_id).one(&db).await?;
+    let token = TeamInvitation::generate_token();
+    let invitation = TeamInvitation {
+        id: Id::new().to_string(),
+        team_id: team.id,
+        email: body.email.clone(),
+        role: body.role,
+        token_hash: TeamInvitation::hash(&token),
+        invited_by: user.id,
+        expires_at: Utc::now() + Duration::days(7),
+        created_at: Utc::now(),
+        updated_at: None,
+    };
+    invitation.create(&db).await?;
+
+    let url = format!("{}/teams/invitations?token={token}", config.client_url);
+    send_email(
+        &mailer,
+        &config,
+        &body.email,
+        "You've Been Invited to a Team",
+        format!(
+            r#"<p>{} {} invited you to join {} with the {} role. Click the link below to accept, it expires in 7 days.</p><p><a href="{url}">{url}</a></p><p>If you weren't expecting this, you can ignore this email.</p>"#,
+            util::escape_html(&user.first_name),
+            util::escape_html(&user.last_name),
+            util::escape_html(&team.name),
+            invitation.role,
+        ),
+    )
+    .await?;
+
+    Ok(Json(invitation))
+}
+
+pub async fn revoke(
+    SystemUser(user): SystemUser,
+    Path((team_id, id)): Path<(String, String)>,
+    SystemDatabase(db): SystemDatabase,
+) -> Result<impl IntoResponse, Error> {
+    TeamMember::require(&db, &team_id, &user.id, TeamRole::Admin).await?;
+
+    let invitation = TeamInvitation::find_by_id(&id).one(&db).await?;
+    if invitation.team_id != team_id {
+        return Err(Error::NotFound);
+    }
+
+    invitation.delete(&db).await?;
+    Ok(Json(Value::Null))
+}
+
+/// Joins the team, the invitation has to have been sent to the user's email
+pub async fn accept(
+    SystemUser(user): SystemUser,
+    SystemDatabase(db): SystemDatabase,
+    Json(body): Json<AcceptBody>,
+) -> Result<impl IntoResponse, Error> {
+    let invitation = TeamInvitation::find()
+        .by_token_hash(TeamInvitation::hash(&body.token))
+        .one(&db)
+        .await
+        .map_err(|_| Error::BadRequest("Invalid invitation".into()))?;
+
+    if invitation.is_expired() {
+        invitation.delete(&db).await?;
+        return Err(Error::BadRequest("Invitation has expired".into()));
+    }
+    if !invitation.email.eq_ignore_ascii_case(&user.email) {
+        return Err(Error::Forbidden(
+            "This invitation was sent to another email".into(),
+        ));
+    }
+
+    let member = match TeamMember::find()
+        .by_team_id(invitation.team_id.clone())
+        .by_user_id(user.id.clone())
+        .one(&db)
+        .await
+    {
+        Ok(member) => member,
+        Err(_) => {
+            let member = TeamMember {
+                id: Id::new().to_string(),
+                team_id: invitation.team_id.clone(),
+                user_id: user.id,
+                role: invitation.role,
+                created_at: Utc::now(),
+                updated_at: None,
+            };
+            member.create(&db).await?;
+
+            member
+        }
+    };
+
+    invitation.delete(&db).await?;
+    Ok(Json(member))
+}
@@ -0,0 +1,145 @@
+use adrastos_core::{
+    entities::{self, SystemUserIden, TeamMember, TeamRole, UpdateTeamMember},
+    error::Error,
+};
+use axum::{
+    extract::Path,
+    response::IntoResponse,
+    routing::{delete, get, patch},
+    Json, Router,
+};
+use sea_query::Expr;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    middleware::extractors::{SystemDatabase, SystemUser},
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+pub struct UpdateBody {
+    role: TeamRole,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list))
+        .route("/update/:id", patch(update))
+        .route("/remove/:id", delete(remove))
+}
+
+async fn find_member(
+    db: &deadpool_postgres::Pool,
+    team_id: &str,
+    id: &str,
+) -> Result<TeamMember, Error> {
+    TeamMember::find_by_id(id)
+        .one(db)
+        .await
+        .ok()
+        .filter(|member| member.team_id == team_id)
+        .ok_or(Error::NotFound)
+}
+
+/// Teams always keep an owner, so the last one can't be demoted or removed
+async fn ensure_other_owner(
+    db: &deadpool_postgres::Pool,
+    member: &TeamMember,
+) -> Result<(), Error> {
+    if member.role != TeamRole::Owner {
+        return Ok(());
+    }
+
+    let owners = TeamMember::find()
+        .by_team_id(member.team_id.clone())
+        .all(db)
+        .await?
+        .into_iter()
+        .filter(|m| m.role == TeamRole::Owner)
+        .count();
+    if owners <= 1 {
+        return Err(Error::BadRequest("A team needs at least one owner".into()));
+    }
+
+    Ok(())
+}
+
+pub async fn list(
+    SystemUser(user): SystemUser,
+    Path(team_id): Path<String>,
+    SystemDatabase(db): SystemDatabase,
+) -> Result<impl IntoResponse, Error> {
+    TeamMember::require(&db, &team_id, &user.id, TeamRole::Viewer).await?;
+
+    let members = TeamMember::find().by_team_id(team_id).all(&db).await?;
+    let users = entities::SystemUser::find()
+        .and_where(vec![
+            Expr::col(SystemUserIden::Id).is_in(members.iter().map(|m| m.user_id.clone()))
+        ])
+        .all(&db)
+        .await?;
+
+    let members = members
+        .into_iter()
+        .map(|member| {
+            let user = users.iter().find(|u| u.id == member.user_id);
+            let mut value = json!(member);
+            value["user"] = json!(user);
+
+            value
+        })
+        .collect::<Vec<_>>();
+    Ok(Json(members))
+}
+
+/// Only owners can hand out the owner role or change another owner's role
+pub async fn update(
+    SystemUser(user): SystemUser,
+    Path((team_id, id)): Path<(String, String)>,
+    SystemDatabase(db): SystemDatabase,
+    Json(body): Json<UpdateBody>,
+) -> Result<impl IntoResponse, Error> {
+    let caller = TeamMember::require(&db, &team_id, &user.id, TeamRole::Admin).await?;
+    let member = find_member(&db, &team_id, &id).await?;
+
+    if member.role == TeamRole::Owner || body.role == TeamRole::Owner {
+        caller.check(TeamRole::Owner)?;
+    }
+    if body.role != TeamRole::Owner {
+        ensure_other_owner(&db, &member).await?;
+    }
+
+    member
+        .update(
+            &db,
+            UpdateTeamMember {
+                role: Some(body.role),
+            },
+        )
+        .await?;
+
+    let member = TeamMember::find_by_id(&id).one(&db).await?;
+    Ok(Json(member))
+}
+
+/// Admins can remove members, anyone can leave a team
+pub async fn remove(
+    SystemUser(user): SystemUser,
+    Path((team_id, id)): Path<(String, String)>,
+    SystemDatabase(db): SystemDatabase,
+) -> Result<impl IntoResponse, Error> {
+    let caller = TeamMember::require(&db, &team_id, &user.id, TeamRole::Viewer).await?;
+    let member = find_member(&db, &team_id, &id).await?;
+
+    if member.id != caller.id {
+        caller.check(TeamRole::Admin)?;
+        if member.role == TeamRole::Owner {
+            caller.check(TeamRole::Owner)?;
+        }
+    }
+    ensure_other_owner(&db, &member).await?;
+
+    member.delete(&db).await?;
+    Ok(Json(Value::Null))
+}
@@ -1,4 +1,8 @@
-use adrastos_core::{entities, error::Error, id::Id};
+use adrastos_core::{
+    entities::{self, TeamIden, TeamMember, TeamRole},
+    error::Error,
+    id::Id,
+};
 use axum::{
     extract::Path,
     response::IntoResponse,
@@ -6,6 +10,7 @@ use axum::{
     Json, Router,
 };
 use chrono::Utc;
+use sea_query::Expr;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -14,6 +19,8 @@ use crate::{
     state::AppState,
 };
 
+pub mod invitations;
+pub mod members;
 pub mod projects;
 
 #[derive(Serialize, Deserialize)]
@@ -27,38 +34,94 @@ pub fn routes() -> Router<AppState> {
         .route("/create", post(create))
         .route("/delete/:id", delete(remove))
         .route("/projects/:id", get(projects::get_by_id))
+        .route("/invitations/accept", post(invitations::accept))
         .nest("/:team_id/projects", projects::routes())
+        .nest("/:team_id/members", members::routes())
+        .nest("/:team_id/invitations", invitations::routes())
 }
 
+/// The teams the user is a member of
 pub async fn list(
-    _: SystemUser,
+    SystemUser(user): SystemUser,
     SystemDatabase(db): SystemDatabase,
 ) -> Result<impl IntoResponse, Error> {
-    let teams = entities::Team::find().all(&db).await?;
+    let team_ids = TeamMember::find()
+        .by_user_id(user.id)
+        .all(&db)
+        .await?
+        .into_iter()
+        .map(|member| member.team_id)
+        .collect::<Vec<_>>();
+
+    let teams = entities::Team::find()
+        .and_where(vec![Expr::col(TeamIden::Id).is_in(team_ids)])
+        .all(&db)
+        .await?;
     Ok(Json(teams))
 }
 
+/// The user creating the team becomes its owner
 pub async fn create(
-    _: SystemUser,
+    SystemUser(user): SystemUser,
     SystemDatabase(db): SystemDatabase,
     Json(body): Json<CreateBody>,
 ) -> Result<impl IntoResponse, Error> {
+    let team = create_owned(&db, body.name, user.id).await?;
+    Ok(Json(team))
+}
+
+/// New system users get a team of their own to create projects in
+pub async fn create_personal(
+    db: &deadpool_postgres::Pool,
+    user: &entities::AnyUser,
+) -> Result<(), Error> {
+    let name = format!("{}'s Team", user.username);
+
+    // Team names are unique, so fall back to one that can't be taken yet
+    if create_owned(db, name.clone(), user.id.clone())
+        .await
+        .is_err()
+    {
+        create_owned(db, format!("{name} ({})", Id::new()), user.id.clone()).await?;
+    }
+
+    Ok(())
+}
+
+async fn create_owned(
+    db: &deadpool_postgres::Pool,
+    name: String,
+    user_id: String,
+) -> Result<entities::Team, Error> {
     let team = entities::Team {
         id: Id::new().to_string(),
-        name: body.name.clone(),
+        name,
         created_at: Utc::now(),
         ..Default::default()
     };
 
-    team.create(&db).await?;
-    Ok(Json(team))
+    team.create(db).await?;
+    TeamMember {
+        id: Id::new().to_string(),
+        team_id: team.id.clone(),
+        user_id,
+        role: TeamRole::Owner,
+        created_at: Utc::now(),
+        updated_at: None,
+    }
+    .create(db)
+    .await?;
+
+    Ok(team)
 }
 
 pub async fn remove(
-    _: SystemUser,
+    SystemUser(user): SystemUser,
     Path(id): Path<String>,
     SystemDatabase(db): SystemDatabase,
 ) -> Result<impl IntoResponse, Error> {
+    TeamMember::require(&db, &id, &user.id, TeamRole::Owner).await?;
+
     entities::Team::find_by_id(&id)
         .one(&db)
         .await?
@@ -1,4 +1,8 @@
-use adrastos_core::{entities, error::Error, id::Id};
+use adrastos_core::{
+    entities::{self, ProjectIden, TeamMember, TeamRole},
+    error::Error,
+    id::Id,
+};
 use axum::{
     extract::Path,
     response::IntoResponse,
@@ -6,6 +10,7 @@ use axum::{
     Json, Router,
 };
 use chrono::Utc;
+use sea_query::Expr;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
@@ -33,28 +38,38 @@ pub fn routes() -> Router<AppState> {
 }
 
 pub async fn get_by_id(
-    _: SystemUser,
+    SystemUser(user): SystemUser,
     Path(path): Path<String>,
     SystemDatabase(db): SystemDatabase,
 ) -> Result<impl IntoResponse, Error> {
     let project = entities::Project::find_by_id(&path).one(&db).await?;
+    TeamMember::require(&db, &project.team_id, &user.id, TeamRole::Viewer).await?;
+
     Ok(Json(project))
 }
 
 pub async fn list(
-    _: SystemUser,
+    SystemUser(user): SystemUser,
+    Path(team_id): Path<String>,
     SystemDatabase(db): SystemDatabase,
 ) -> Result<impl IntoResponse, Error> {
-    let projects = entities::Project::find().all(&db).await?;
+    TeamMember::require(&db, &team_id, &user.id, TeamRole::Viewer).await?;
+
+    let projects = entities::Project::find()
+        .and_where(vec![Expr::col(ProjectIden::TeamId).eq(team_id)])
+        .all(&db)
+        .await?;
     Ok(Json(projects))
 }
 
 pub async fn create(
-    _: SystemUser,
+    SystemUser(user): SystemUser,
     Path(team_id): Path<String>,
     SystemDatabase(db): SystemDatabase,
     Json(body): Json<CreateBody>,
 ) -> Result<impl IntoResponse, Error> {
+    TeamMember::require(&db, &team_id, &user.id, TeamRole::Admin).await?;
+
     let project = entities::Project {
         id: Id::new().to_string(),
         name: body.name.clone(),
@@ -69,14 +84,17 @@ pub async fn create(
 }
 
 pub async fn remove(
-    _: SystemUser,
-    Path((_, id)): Path<(String, String)>,
+    SystemUser(user): SystemUser,
+    Path((team_id, id)): Path<(String, String)>,
     SystemDatabase(db): SystemDatabase,
 ) -> Result<impl IntoResponse, Error> {
-    entities::Project::find_by_id(&id)
-        .one(&db)
-        .await?
-        .delete(&db)
-        .await?;
+    TeamMember::require(&db, &team_id, &user.id, TeamRole::Admin).await?;
+
+    let project = entities::Project::find_by_id(&id).one(&db).await?;
+    if project.team_id != team_id {
+        return Err(Error::NotFound);
+    }
+
+    project.delete(&db).await?;
     Ok(Json(Value::Null))
 }
@@ -0,0 +1,62 @@
+use adrastos_core::{
+    entities::{RefreshTokenTree, TeamRole, UpdateUser, User},
+    error::Error,
+};
+use axum::{extract::Path, response::IntoResponse, routing::post, Json, Router};
+
+use crate::{
+    middleware::extractors::{Member, ProjectDatabase},
+    state::AppState,
+};
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/ban/:id", post(ban))
+        .route("/unban/:id", post(unban))
+}
+
+/// Bans the user and logs them out everywhere, their access tokens stop working right away
+pub async fn ban(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    let user = User::find_by_id(&id).one(&db).await?;
+    user.update(
+        &db,
+        UpdateUser {
+            banned: Some(true),
+            ..Default::default()
+        },
+    )
+    .await?;
+    RefreshTokenTree::delete_all(&db, &user.id).await?;
+
+    let user = User::find_by_id(&id).one(&db).await?;
+    Ok(Json(user))
+}
+
+pub async fn unban(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Admin)?;
+
+    User::find_by_id(&id)
+        .one(&db)
+        .await?
+        .update(
+            &db,
+            UpdateUser {
+                banned: Some(false),
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    let user = User::find_by_id(&id).one(&db).await?;
+    Ok(Json(user))
+}
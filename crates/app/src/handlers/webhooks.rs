@@ -0,0 +1,143 @@
+use adrastos_core::{
+    entities::{TeamRole, UpdateWebhook, Webhook, WebhookDelivery},
+    error::Error,
+    id::Id,
+    webhooks::{self, WebhookEvent},
+};
+use axum::{
+    extract::Path,
+    response::IntoResponse,
+    routing::{delete, get, patch, post},
+    Json, Router,
+};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    middleware::extractors::{Config, Member, ProjectDatabase},
+    state::AppState,
+};
+
+#[derive(Deserialize)]
+pub struct CreateBody {
+    url: String,
+    events: Vec<String>,
+    secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateBody {
+    url: Option<String>,
+    events: Option<Vec<String>>,
+    secret: Option<String>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list))
+        .route("/create", post(create))
+        .route("/update/:id", patch(update))
+        .route("/delete/:id", delete(remove))
+        .route("/:id/deliveries", get(deliveries))
+}
+
+fn validate_events(events: &[String]) -> Result<(), Error> {
+    events
+        .iter()
+        .try_for_each(|event| WebhookEvent::try_from(event.as_str()).map(|_| ()))
+}
+
+pub async fn list(
+    Member(member): Member,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    let webhooks = Webhook::find().all(&db).await?;
+    Ok(Json(webhooks))
+}
+
+pub async fn create(
+    Member(member): Member,
+    Config(config): Config,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<CreateBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    validate_events(&body.events)?;
+    webhooks::validate_url(&body.url, config.allow_private_webhooks).await?;
+
+    let webhook = Webhook {
+        id: Id::new().to_string(),
+        url: body.url,
+        events: body.events,
+        secret: body.secret.unwrap_or_else(Webhook::generate_secret),
+        created_at: Utc::now(),
+        updated_at: None,
+    };
+
+    webhook.create(&db).await?;
+
+    // The secret is only ever returned here
+    let mut response = json!(webhook);
+    response["secret"] = json!(webhook.secret);
+
+    Ok(Json(response))
+}
+
+pub async fn update(
+    Member(member): Member,
+    Path(id): Path<String>,
+    Config(config): Config,
+    ProjectDatabase(db): ProjectDatabase,
+    Json(body): Json<UpdateBody>,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    if let Some(events) = &body.events {
+        validate_events(events)?;
+    }
+    if let Some(url) = &body.url {
+        webhooks::validate_url(url, config.allow_private_webhooks).await?;
+    }
+
+    Webhook::find_by_id(&id)
+        .one(&db)
+        .await?
+        .update(
+            &db,
+            UpdateWebhook {
+                url: body.url,
+                events: body.events,
+                secret: body.secret,
+            },
+        )
+        .await?;
+
+    let webhook = Webhook::find_by_id(&id).one(&db).await?;
+    Ok(Json(webhook))
+}
+
+pub async fn remove(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    Webhook::find_by_id(&id).one(&db).await?.delete(&db).await?;
+    Ok(Json(Value::Null))
+}
+
+pub async fn deliveries(
+    Member(member): Member,
+    Path(id): Path<String>,
+    ProjectDatabase(db): ProjectDatabase,
+) -> Result<impl IntoResponse, Error> {
+    member.check(TeamRole::Developer)?;
+
+    let deliveries = WebhookDelivery::find().by_webhook_id(id).all(&db).await?;
+    Ok(Json(deliveries))
+}
@@ -1,6 +1,12 @@
 #![feature(let_chains)]
 
-use std::{net::TcpListener, path::PathBuf, process, sync::Arc};
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    process,
+    sync::Arc,
+    time::Duration,
+};
 
 use adrastos_core::{
     config::Config,
@@ -8,17 +14,16 @@ use adrastos_core::{
         postgres::{DatabaseType, Databases},
         redis,
     },
-    entities::System,
-    migrations::Migrations,
+    entities::{SigningKeys, System},
+    jobs,
     s3::S3,
 };
-use axum::{routing::get, Router};
+use axum::{extract::Request, routing::get, Router, ServiceExt};
 use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
 use cli::{Cli, Command};
 use dotenvy::dotenv;
 use rustls::crypto;
-use sea_query::PostgresQueryBuilder;
 use secrecy::ExposeSecret;
 use sentry_tower::NewSentryLayer;
 use state::{AppState, Flag};
@@ -30,7 +35,7 @@ use tower_http::{
 use tower_sessions::{cookie::Key, SessionManagerLayer};
 use tower_sessions_redis_store::RedisStore;
 use tracing::{error, info};
-use tracing_unwrap::{OptionExt, ResultExt};
+use tracing_unwrap::ResultExt;
 
 mod assets;
 mod cli;
@@ -56,88 +61,115 @@ async fn main() {
     let _ = Databases::start_expiry_worker(databases.clone());
     let db = databases.get(&DatabaseType::System, &config).await;
 
+    let cli = Cli::parse();
+    // Older databases can't be read into the config until they're migrated
+    if let Some(Command::Migrate) = cli.command {
+        if let Err(err) = cli::migrate(&databases, &config, &db).await {
+            error!("{err}");
+            process::exit(1);
+        }
+
+        return;
+    }
+
     {
-        config.attach_system(
-            &db.get()
-                .await
-                .unwrap_or_log()
-                .query(&System::get(), &[])
-                .await
-                .unwrap_or_log()
-                .into_iter()
-                .next()
-                .unwrap_or_log()
-                .into(),
-        );
+        let system = db
+            .get()
+            .await
+            .unwrap_or_log()
+            .query_one(&System::get(), &[])
+            .await
+            .unwrap_or_else(|err| {
+                error!(error = ?err, "Unable to read the system config, run the migrate command");
+                process::exit(1);
+            });
+
+        config.attach_system(&system.into());
     }
 
-    let cli = Cli::parse();
-    if cli.command == Some(Command::Migrate) {
-        let conn = db.get().await.unwrap_or_log();
-
-        let migrations = Migrations::all_from(&config.previous_version);
-        for migration in &migrations {
-            info!("Migration: {}", migration.version);
-            for query in &migration.queries {
-                info!("Query: {}", query.to_string(PostgresQueryBuilder));
-
-                conn.execute(query.to_string(PostgresQueryBuilder).as_str(), &[])
-                    .await
-                    .unwrap_or_log();
-            }
+    if let Some(Command::Keys { command }) = cli.command {
+        if let Err(err) = cli::keys(command, &db).await {
+            error!("{err}");
+            process::exit(1);
         }
 
         return;
     }
 
+    // Tokens would silently fall back to the shared secret if the keys were skipped
+    let signing_keys = SigningKeys::load(&db).await.unwrap_or_else(|err| {
+        error!("Unable to load the signing keys: {err}");
+        process::exit(1);
+    });
+    let _keys_task = signing_keys.start_refresh_worker(db.clone(), Duration::from_secs(60));
+
     let (redis_pool, subscriber) = redis::create_pool_and_subscriber(&config).await;
     #[allow(clippy::let_underscore_future)]
     let _rs_task = subscriber.manage_subscriptions();
 
+    let s3 = Arc::new(S3::new(&config).await);
+    let _jobs_task = jobs::start_worker(
+        databases.clone(),
+        redis_pool.clone(),
+        s3.clone(),
+        config.clone(),
+    );
+
     let state = AppState {
+        s3,
         databases,
         subscriber,
         config: config.clone(),
         redis_pool: redis_pool.clone(),
-        s3: Arc::new(S3::new(&config).await),
+        signing_keys,
         flags: vec![("/api/storage/get".into(), vec![Flag::AllowProjectIdParam])],
     };
 
-    let app = tower::make::Shared::new(NormalizePath::trim_trailing_slash(
-        Router::new()
-            .route("/api", get(handlers::api))
-            .route("/api/me", get(handlers::me))
-            .nest("/api/auth", handlers::auth::routes())
-            .nest("/api/teams", handlers::teams::routes())
-            .nest("/api/config", handlers::config::routes())
-            .nest("/api/tables", handlers::tables::routes())
-            .nest("/api/storage", handlers::storage::routes())
-            .fallback(handlers::root)
-            .with_state(state.clone())
-            .layer(
-                ServiceBuilder::new()
-                    .set_x_request_id(MakeRequestUuid)
-                    .layer(
-                        TraceLayer::new_for_http()
-                            .make_span_with(middleware::trace::MakeSpan)
-                            .on_response(middleware::trace::OnResponse),
-                    )
-                    .propagate_x_request_id()
-                    .layer(NewSentryLayer::new_from_top())
-                    .layer(axum::middleware::from_fn_with_state(
-                        state.clone(),
-                        middleware::cors::run,
-                    ))
-                    .layer(
-                        SessionManagerLayer::new(RedisStore::new(redis_pool))
-                            .with_signed(Key::from(config.secret_key.expose_secret().as_bytes())),
-                    )
-                    .layer(axum::middleware::from_fn_with_state(
-                        state.clone(),
-                        middleware::run,
-                    )),
-            ),
-    ));
+    // The peer address is the client's IP unless `trusted_proxies` says otherwise
+    let app = ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(
+        NormalizePath::trim_trailing_slash(
+            Router::new()
+                .route("/api", get(handlers::api))
+                .route("/.well-known/jwks.json", get(handlers::jwks))
+                .route("/api/me", get(handlers::me).patch(handlers::update_me))
+                .nest("/api/auth", handlers::auth::routes())
+                .nest("/api/teams", handlers::teams::routes())
+                .nest("/api/config", handlers::config::routes())
+                .nest("/api/tables", handlers::tables::routes())
+                .nest("/api/storage", handlers::storage::routes())
+                .nest("/api/webhooks", handlers::webhooks::routes())
+                .nest("/api/api-keys", handlers::api_keys::routes())
+                .nest("/api/jobs", handlers::jobs::routes())
+                .nest("/api/users", handlers::users::routes())
+                .nest("/api/roles", handlers::roles::routes())
+                .fallback(handlers::root)
+                .with_state(state.clone())
+                .layer(
+                    ServiceBuilder::new()
+                        .set_x_request_id(MakeRequestUuid)
+                        .layer(
+                            TraceLayer::new_for_http()
+                                .make_span_with(middleware::trace::MakeSpan)
+                                .on_response(middleware::trace::OnResponse),
+                        )
+                        .propagate_x_request_id()
+                        .layer(NewSentryLayer::new_from_top())
+                        .layer(axum::middleware::from_fn_with_state(
+                            state.clone(),
+                            middleware::cors::run,
+                        ))
+                        .layer(
+                            SessionManagerLayer::new(RedisStore::new(redis_pool)).with_signed(
+                                Key::from(config.secret_key.expose_secret().as_bytes()),
+                            ),
+                        )
+                        .layer(axum::middleware::from_fn_with_state(
+                            state.clone(),
+                            middleware::run,
+                        )),
+                ),
+        ),
+    );
 
     let listener = TcpListener::bind(&config.server_url).unwrap();
 
@@ -1,6 +1,6 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
-use adrastos_core::auth::oauth2;
+use adrastos_core::auth::{self, oauth2};
 use adrastos_core::entities::AlternateUserType;
 use adrastos_core::{config, entities};
 use adrastos_core::{
@@ -8,7 +8,11 @@ use adrastos_core::{
     error::Error,
 };
 use axum::RequestPartsExt;
-use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{AsyncSmtpTransport, Tokio1Executor};
 use secrecy::ExposeSecret;
@@ -154,6 +158,28 @@ where
     }
 }
 
+/// The system user's membership of the team that owns the requested project
+pub struct Member(pub entities::TeamMember);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Member
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<entities::TeamMember>().cloned() {
+            Some(v) => Ok(Member(v)),
+            // Non-members aren't authenticated for the project, so this is a system request
+            None if parts.extensions.get::<entities::SystemUser>().is_some() => {
+                Err(Error::BadRequest("Missing project ID".into()))
+            }
+            None => Err(Error::Unauthorized),
+        }
+    }
+}
+
 pub struct AnyUser(pub entities::AnyUser, pub AlternateUserType);
 
 #[async_trait]
@@ -169,12 +195,37 @@ where
             .get::<(entities::AnyUser, AlternateUserType)>()
             .cloned()
         {
+            Some((_, AlternateUserType::Service(_))) => Err(Error::Forbidden(
+                "API keys can't be used on this endpoint".into(),
+            )),
             Some(v) => Ok(AnyUser(v.0, v.1)),
             None => Err(Error::Unauthorized),
         }
     }
 }
 
+/// Like [`AnyUser`], but also accepts the service account of an API key
+pub struct Caller(pub entities::AnyUser, pub AlternateUserType);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Caller
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        match parts
+            .extensions
+            .get::<(entities::AnyUser, AlternateUserType)>()
+            .cloned()
+        {
+            Some(v) => Ok(Caller(v.0, v.1)),
+            None => Err(Error::Unauthorized),
+        }
+    }
+}
+
 pub struct Mailer(pub AsyncSmtpTransport<Tokio1Executor>);
 
 #[async_trait]
@@ -232,3 +283,33 @@ where
         Ok(OAuth2(oauth2::OAuth2::new(&config)))
     }
 }
+
+pub struct Device(pub auth::Device);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Device
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let trusted_proxies = parts
+            .extensions
+            .get::<config::Config>()
+            .map_or(0, |config| config.trusted_proxies);
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr);
+
+        Ok(Device(auth::Device {
+            user_agent: parts
+                .headers
+                .get("user-agent")
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            ip_address: crate::util::client_ip(&parts.headers, peer, trusted_proxies),
+        }))
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use adrastos_core::{
-    auth::TokenType,
+    auth::{self, TokenType},
     config::Config,
     db::postgres::{Database, DatabaseType},
     entities::{self, AlternateUserType, System, SystemUserJoin, UserJoin},
@@ -22,8 +22,10 @@ use crate::state::{AppState, Flag};
 
 pub mod cors;
 pub mod extractors;
+pub mod rate_limit;
 pub mod size_limiter;
 pub mod trace;
+pub mod verified;
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -39,6 +41,8 @@ pub async fn run(
         config,
         databases,
         flags,
+        redis_pool,
+        signing_keys,
         ..
     }): State<AppState>,
     mut req: Request,
@@ -96,13 +100,39 @@ pub async fn run(
                 .unwrap_or_log()
                 .into(),
         );
-        req.extensions_mut().insert::<Config>(updated_config);
+        updated_config.signing_keys = signing_keys.get().await;
+        req.extensions_mut()
+            .insert::<Config>(updated_config.clone());
+        req.extensions_mut().insert(redis_pool);
 
         req.extensions_mut()
             .insert::<Database>(Database(system_db.clone(), DatabaseType::System));
         req.extensions_mut()
             .insert::<(Arc<deadpool_postgres::Pool>, DatabaseType)>((db.clone(), db_type.clone()));
 
+        let api_key = req
+            .headers()
+            .get("x-api-key")
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+        if let (Some(api_key), DatabaseType::Project(_)) = (api_key, &db_type) {
+            if let Ok(api_key) = entities::ApiKey::find()
+                .by_key_hash(entities::ApiKey::hash(&api_key))
+                .one(&db)
+                .await
+            {
+                if !api_key.is_expired() {
+                    api_key.touch(&db).await.ok();
+
+                    req.extensions_mut()
+                        .insert::<(entities::AnyUser, AlternateUserType)>((
+                            api_key.clone().into(),
+                            AlternateUserType::Service(Box::new(api_key)),
+                        ));
+                }
+            }
+        }
+
         let authorization = authorization.map(|auth| auth.token().to_string()).or({
             if flags.contains(&Flag::AllowAuthParam) {
                 req_params.auth
@@ -112,48 +142,36 @@ pub async fn run(
         });
 
         if let Some(token) = authorization {
-            if let Ok(access_token) = TokenType::verify(&config, token) {
-                match db_type {
-                    DatabaseType::Project(_) => {
-                        if let Ok(user) = entities::User::find_by_id(&access_token.claims.sub)
-                            .join(UserJoin::Connections)
-                            .join(UserJoin::RefreshTokenTrees)
-                            .join(UserJoin::Passkeys)
-                            .one(&db)
-                            .await
-                        {
-                            req.extensions_mut().insert::<entities::User>(user.clone());
-                            req.extensions_mut()
-                                .insert::<(entities::AnyUser, AlternateUserType)>((
-                                    user.into(),
-                                    AlternateUserType::Normal,
-                                ));
-                        }
+            // Project requests also accept system tokens, so admins can manage the project
+            let audiences = [db_type.clone(), DatabaseType::System];
+            if let Ok(access_token) = TokenType::verify(&updated_config, &audiences, token) {
+                // The audience says which database the subject belongs to
+                if access_token.claims.aud == auth::audience(&DatabaseType::System) {
+                    if let Ok(user) = entities::SystemUser::find_by_id(&access_token.claims.sub)
+                        .join(SystemUserJoin::Connections)
+                        .join(SystemUserJoin::RefreshTokenTrees)
+                        .join(SystemUserJoin::Passkeys)
+                        .one(&system_db)
+                        .await
+                    {
+                        let team_id = req
+                            .extensions()
+                            .get::<entities::Project>()
+                            .map(|project| project.team_id.clone());
+                        let member = match team_id {
+                            Some(team_id) => entities::TeamMember::find()
+                                .by_team_id(team_id)
+                                .by_user_id(user.id.clone())
+                                .one(&system_db)
+                                .await
+                                .ok(),
+                            None => None,
+                        };
 
-                        if let Ok(user) = entities::SystemUser::find_by_id(&access_token.claims.sub)
-                            .join(SystemUserJoin::Connections)
-                            .join(SystemUserJoin::RefreshTokenTrees)
-                            .join(SystemUserJoin::Passkeys)
-                            .one(&system_db)
-                            .await
-                        {
-                            req.extensions_mut()
-                                .insert::<entities::SystemUser>(user.clone());
-                            req.extensions_mut()
-                                .insert::<(entities::AnyUser, AlternateUserType)>((
-                                    user.into(),
-                                    AlternateUserType::System,
-                                ));
-                        }
-                    }
-                    DatabaseType::System => {
-                        if let Ok(user) = entities::SystemUser::find_by_id(&access_token.claims.sub)
-                            .join(SystemUserJoin::Connections)
-                            .join(SystemUserJoin::RefreshTokenTrees)
-                            .join(SystemUserJoin::Passkeys)
-                            .one(&db)
-                            .await
-                        {
+                        if can_act_on(&db_type, member.as_ref()) {
+                            if let Some(member) = member {
+                                req.extensions_mut().insert::<entities::TeamMember>(member);
+                            }
                             req.extensions_mut()
                                 .insert::<entities::SystemUser>(user.clone());
                             req.extensions_mut()
@@ -163,6 +181,30 @@ pub async fn run(
                                 ));
                         }
                     }
+                } else if let Some(user) = entities::User::find_by_id(&access_token.claims.sub)
+                    .join(UserJoin::Connections)
+                    .join(UserJoin::RefreshTokenTrees)
+                    .join(UserJoin::Passkeys)
+                    .one(&db)
+                    .await
+                    .ok()
+                    // Access tokens outlive a ban, so it's checked on every request
+                    .filter(|user| !user.banned)
+                {
+                    req.extensions_mut().insert::<entities::User>(user.clone());
+
+                    let mut user: entities::AnyUser = user.into();
+                    user.roles = entities::Role::for_user(&db, &user.id)
+                        .await
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|role| role.name)
+                        .collect();
+                    req.extensions_mut()
+                        .insert::<(entities::AnyUser, AlternateUserType)>((
+                            user,
+                            AlternateUserType::Normal,
+                        ));
                 }
             }
         }
@@ -170,3 +212,35 @@ pub async fn run(
 
     next.run(req).await
 }
+
+/// System users only act on a project as members of its team
+fn can_act_on(db_type: &DatabaseType, member: Option<&entities::TeamMember>) -> bool {
+    match db_type {
+        DatabaseType::System => true,
+        DatabaseType::Project(_) => member.is_some(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    #[test]
+    fn only_members_act_on_projects() {
+        let project = DatabaseType::Project("project".into());
+        let member = entities::TeamMember {
+            id: "member".into(),
+            team_id: "team".into(),
+            user_id: "user".into(),
+            role: entities::TeamRole::Viewer,
+            created_at: Utc::now(),
+            updated_at: None,
+        };
+
+        assert!(can_act_on(&DatabaseType::System, None));
+        assert!(can_act_on(&project, Some(&member)));
+        assert!(!can_act_on(&project, None));
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use adrastos_core::{config::Config, db::redis, error::Error};
+use axum::{
+    body::{self, Body},
+    extract::{ConnectInfo, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{Duration, Utc};
+use fred::{clients::RedisPool, interfaces::KeysInterface};
+use tower::{Layer, Service};
+use tracing::error;
+
+use crate::util;
+
+/// Bodies bigger than this aren't searched for an account identifier
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Limits for one route, counted separately for the client's IP and the account it targets
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    name: &'static str,
+    max: i64,
+    window: Duration,
+    /// The JSON body field holding the account identifier, like `email`
+    identifier: Option<&'static str>,
+    /// Failed responses allowed before the subject is locked out
+    max_failures: i64,
+    /// The first lockout, each one after it lasts twice as long
+    lockout: Duration,
+}
+
+impl RateLimit {
+    pub fn new(name: &'static str, max: i64, window: Duration) -> Self {
+        RateLimit {
+            name,
+            max,
+            window,
+            identifier: None,
+            max_failures: 5,
+            lockout: Duration::minutes(1),
+        }
+    }
+
+    pub fn identifier(mut self, field: &'static str) -> Self {
+        self.identifier = Some(field);
+        self
+    }
+
+    pub fn lockout(mut self, max_failures: i64, lockout: Duration) -> Self {
+        self.max_failures = max_failures;
+        self.lockout = lockout;
+        self
+    }
+
+    pub fn layer(self) -> RateLimitLayer {
+        RateLimitLayer(self)
+    }
+
+    /// The lockout started by the `failures`th failed response, if any, doubling every time
+    fn lockout_seconds(&self, failures: i64) -> Option<i64> {
+        if failures % self.max_failures != 0 {
+            return None;
+        }
+
+        let lockouts = (failures / self.max_failures).min(10);
+        Some(self.lockout.num_seconds() * 2_i64.pow(lockouts as u32 - 1))
+    }
+}
+
+/// A sliding window estimated from the current and previous fixed windows, `elapsed` being how
+/// far into the current one it is
+fn estimate(previous: i64, current: i64, elapsed: f64) -> f64 {
+    previous as f64 * (1.0 - elapsed) + current as f64
+}
+
+#[derive(Clone)]
+pub struct RateLimitLayer(RateLimit);
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limit: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    limit: RateLimit,
+}
+
+impl<S> Service<Request> for RateLimitService<S>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // The clone might not be ready, so the ready service is the one that's used
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limit = self.limit.clone();
+
+        Box::pin(async move {
+            let (Some(config), Some(redis_pool)) = (
+                req.extensions().get::<Config>().cloned(),
+                req.extensions().get::<RedisPool>().cloned(),
+            ) else {
+                return inner.call(req).await;
+            };
+
+            let (parts, body) = req.into_parts();
+            let bytes = match body::to_bytes(body, MAX_BODY_SIZE).await {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    return Ok(Error::Custom(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        "Payload is too large".into(),
+                    )
+                    .into_response())
+                }
+            };
+
+            let mut subjects = vec![];
+            let peer = parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| *addr);
+            if let Some(ip) = util::client_ip(&parts.headers, peer, config.trusted_proxies) {
+                subjects.push(format!("ip:{ip}"));
+            }
+            if let Some(account) = limit.identifier.and_then(|field| {
+                serde_json::from_slice::<serde_json::Value>(&bytes)
+                    .ok()?
+                    .get(field)?
+                    .as_str()
+                    .map(|v| v.to_lowercase())
+            }) {
+                subjects.push(format!("account:{account}"));
+            }
+
+            let limiter = Limiter {
+                config: &config,
+                redis_pool: &redis_pool,
+                limit: &limit,
+            };
+            for subject in &subjects {
+                match limiter.check(subject).await {
+                    Ok(None) => {}
+                    Ok(Some(retry_after)) => return Ok(too_many_requests(retry_after)),
+                    // A broken limiter shouldn't take logins down with it
+                    Err(err) => error!(error = ?err, "rate limiter failed"),
+                }
+            }
+
+            let response = inner
+                .call(Request::from_parts(parts, Body::from(bytes)))
+                .await?;
+
+            let failed = response.status().is_client_error()
+                && response.status() != StatusCode::TOO_MANY_REQUESTS;
+            for subject in &subjects {
+                let result = match failed {
+                    true => limiter.fail(subject).await,
+                    // Only the account is forgiven, so one good login can't clear an IP that
+                    // is guessing other accounts
+                    false if response.status().is_success() && subject.starts_with("account:") => {
+                        limiter.succeed(subject).await
+                    }
+                    false => Ok(()),
+                };
+                if let Err(err) = result {
+                    error!(error = ?err, "rate limiter failed");
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+fn too_many_requests(retry_after: i64) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.max(1).to_string())],
+        Error::Custom(
+            StatusCode::TOO_MANY_REQUESTS,
+            "Too many attempts, try again later".into(),
+        ),
+    )
+        .into_response()
+}
+
+struct Limiter<'a> {
+    config: &'a Config,
+    redis_pool: &'a RedisPool,
+    limit: &'a RateLimit,
+}
+
+impl Limiter<'_> {
+    fn key(&self, kind: &str, subject: &str) -> String {
+        redis::build_key(
+            self.config,
+            format!("rate-limit:{}:{kind}:{subject}", self.limit.name),
+        )
+    }
+
+    /// Counts a request, returning how many seconds to wait if it's over the limit
+    async fn check(&self, subject: &str) -> Result<Option<i64>, fred::error::RedisError> {
+        let lockout: i64 = self.redis_pool.ttl(self.key("lockout", subject)).await?;
+        if lockout > 0 {
+            return Ok(Some(lockout));
+        }
+
+        let window = self.limit.window.num_milliseconds().max(1);
+        let now = Utc::now().timestamp_millis();
+        let current = now / window;
+        let elapsed = (now % window) as f64 / window as f64;
+
+        let previous: Option<i64> = self
+            .redis_pool
+            .get(self.key(&(current - 1).to_string(), subject))
+            .await?;
+        let key = self.key(&current.to_string(), subject);
+        let count: i64 = self.redis_pool.incr(key.clone()).await?;
+        if count == 1 {
+            self.redis_pool
+                .expire::<(), _>(key, self.limit.window.num_seconds() * 2 + 1)
+                .await?;
+        }
+
+        if estimate(previous.unwrap_or_default(), count, elapsed) > self.limit.max as f64 {
+            let retry_after = ((1.0 - elapsed) * window as f64 / 1000.0).ceil() as i64;
+            return Ok(Some(retry_after));
+        }
+
+        Ok(None)
+    }
+
+    /// Locks the subject out once it failed too often, doubling the lockout every time
+    async fn fail(&self, subject: &str) -> Result<(), fred::error::RedisError> {
+        let failures_key = self.key("failures", subject);
+        let failures: i64 = self.redis_pool.incr(failures_key.clone()).await?;
+        self.redis_pool
+            .expire::<(), _>(failures_key.clone(), Duration::days(1).num_seconds())
+            .await?;
+
+        let Some(seconds) = self.limit.lockout_seconds(failures) else {
+            return Ok(());
+        };
+
+        self.redis_pool
+            .set::<(), _, _>(
+                self.key("lockout", subject),
+                1,
+                Some(fred::types::Expiration::EX(seconds)),
+                None,
+                false,
+            )
+            .await
+    }
+
+    async fn succeed(&self, subject: &str) -> Result<(), fred::error::RedisError> {
+        self.redis_pool
+            .del::<(), _>(self.key("failures", subject))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_sliding_window() {
+        assert_eq!(estimate(10, 2, 0.0), 12.0);
+        assert_eq!(estimate(10, 2, 0.25), 9.5);
+        assert_eq!(estimate(10, 2, 1.0), 2.0);
+        assert_eq!(estimate(0, 3, 0.5), 3.0);
+    }
+
+    #[test]
+    fn doubles_lockouts() {
+        let limit =
+            RateLimit::new("test", 10, Duration::minutes(1)).lockout(3, Duration::minutes(1));
+
+        assert_eq!(limit.lockout_seconds(1), None);
+        assert_eq!(limit.lockout_seconds(3), Some(60));
+        assert_eq!(limit.lockout_seconds(5), None);
+        assert_eq!(limit.lockout_seconds(6), Some(120));
+        assert_eq!(limit.lockout_seconds(9), Some(240));
+        // Capped after 10 lockouts
+        assert_eq!(limit.lockout_seconds(60), Some(60 * 512));
+        assert_eq!(limit.lockout_seconds(90), Some(60 * 512));
+    }
+}
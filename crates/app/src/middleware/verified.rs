@@ -0,0 +1,28 @@
+use adrastos_core::{
+    config,
+    entities::{AlternateUserType, AnyUser},
+    error::Error,
+};
+use axum::{extract::Request, middleware::Next, response::Response};
+
+/// Blocks users that haven't verified their email when the project requires it
+pub async fn run(req: Request, next: Next) -> Result<Response, Error> {
+    let config = req.extensions().get::<config::Config>().unwrap().clone();
+    let require_verified = config
+        .system()
+        .as_ref()
+        .and_then(|s| s.verification_config.as_ref())
+        .is_some_and(|c| c.require_verified);
+
+    if require_verified
+        && let Some((user, AlternateUserType::Normal)) =
+            req.extensions().get::<(AnyUser, AlternateUserType)>()
+        && !user.verified
+    {
+        return Err(Error::Forbidden(
+            "Your email needs to be verified first".into(),
+        ));
+    }
+
+    Ok(next.run(req).await)
+}
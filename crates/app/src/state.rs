@@ -1,6 +1,6 @@
 use std::sync::Arc;
 
-use adrastos_core::{config::Config, db::postgres::Databases, s3::S3};
+use adrastos_core::{config::Config, db::postgres::Databases, entities::SigningKeys, s3::S3};
 use fred::clients::SubscriberClient;
 
 #[derive(PartialEq, Clone, Debug)]
@@ -17,4 +17,5 @@ pub struct AppState {
     pub subscriber: SubscriberClient,
     pub flags: Vec<(String, Vec<Flag>)>,
     pub redis_pool: fred::clients::RedisPool,
+    pub signing_keys: SigningKeys,
 }
@@ -1,3 +1,6 @@
+use std::net::SocketAddr;
+
+use axum::http::HeaderMap;
 use json_patch::{AddOperation, PatchOperation};
 use jsonptr::Pointer;
 use serde_json::json;
@@ -42,3 +45,79 @@ pub fn attach_pagination_details(target: &mut serde_json::Value, info: Paginatio
 
     json_patch::patch(target, &diff).unwrap();
 }
+
+/// The client's address, read from forwarded headers only behind `trusted_proxies` proxies.
+/// Each proxy appends the address it got the request from to `X-Forwarded-For`, so anything
+/// left of their entries could be made up by the client.
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+    trusted_proxies: usize,
+) -> Option<String> {
+    let peer = peer.map(|addr| addr.ip().to_string());
+    if trusted_proxies == 0 {
+        return peer;
+    }
+
+    let get = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let forwarded = get("x-forwarded-for")
+        .map(|v| v.split(',').map(str::trim).collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    forwarded
+        .len()
+        .checked_sub(trusted_proxies)
+        .map(|i| forwarded[i].to_string())
+        .or_else(|| get("x-real-ip").map(|ip| ip.trim().to_string()))
+        .or(peer)
+}
+
+/// Escapes user-supplied text before it's interpolated into HTML, like emails
+pub fn escape_html(value: &str) -> String {
+    value
+        .chars()
+        .fold(String::with_capacity(value.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                _ => escaped.push(c),
+            }
+
+            escaped
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn client_ip_trusts_only_configured_proxies() {
+        let peer = Some(SocketAddr::from(([10, 0, 0, 1], 443)));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("1.1.1.1, 2.2.2.2, 10.0.0.2"),
+        );
+
+        assert_eq!(client_ip(&headers, peer, 0).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_ip(&headers, peer, 1).as_deref(), Some("10.0.0.2"));
+        assert_eq!(client_ip(&headers, peer, 2).as_deref(), Some("2.2.2.2"));
+        assert_eq!(client_ip(&headers, peer, 4).as_deref(), Some("10.0.0.1"));
+        assert_eq!(client_ip(&HeaderMap::new(), None, 0), None);
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & Jerry's</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(escape_html("Acme"), "Acme");
+    }
+}
@@ -12,7 +12,7 @@ use axum_extra::extract::{
 };
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use jsonwebtoken::{
-    decode, encode, errors::ErrorKind, DecodingKey, EncodingKey, Header, Validation,
+    decode, decode_header, encode, errors::ErrorKind, DecodingKey, EncodingKey, Header, Validation,
 };
 use secrecy::ExposeSecret;
 use serde::{Deserialize, Serialize};
@@ -20,6 +20,7 @@ use time::OffsetDateTime;
 
 use crate::{
     config::{self, Config},
+    db::postgres::{Database, DatabaseType},
     entities::{AnyUser, RefreshTokenTree},
     error::Error,
     id::Id,
@@ -28,6 +29,7 @@ use crate::{
 pub mod mfa;
 pub mod oauth2;
 pub mod passkeys;
+pub mod passwordless;
 
 #[derive(Clone, PartialEq)]
 pub enum TokenType {
@@ -46,16 +48,36 @@ impl fmt::Display for TokenType {
     }
 }
 
+/// The `iss` of every token we sign
+pub const ISSUER: &str = "adrastos";
+
+/// The `aud` binding a token to the system or to one project
+pub fn audience(db_type: &DatabaseType) -> String {
+    match db_type {
+        DatabaseType::System => "system".into(),
+        DatabaseType::Project(id) => format!("project:{id}"),
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub jti: String,
     iat: usize,
     exp: usize,
     pub sub: String,
+    pub aud: String,
+    pub iss: String,
     #[serde(rename = "type")]
     pub token_type: String,
 }
 
+/// Where a login came from, recorded on its refresh token tree
+#[derive(Clone, Debug, Default)]
+pub struct Device {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
 #[derive(Clone)]
 pub struct TokenInfo {
     pub token: String,
@@ -77,15 +99,18 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, password_hash
 }
 
 pub async fn authenticate(
-    db: &deadpool_postgres::Pool,
+    db: &Database,
     config: &Config,
     user: &AnyUser,
+    device: Device,
     jar: CookieJar,
 ) -> Result<(TokenInfo, CookieJar), Error> {
-    let access_token = TokenType::Access.sign(config, user).map_err(|_| {
+    user.ensure_not_banned()?;
+
+    let access_token = TokenType::Access.sign(config, &db.1, user).map_err(|_| {
         Error::InternalServerError("An error occurred while signing the access token".into())
     })?;
-    let refresh_token = TokenType::Refresh.sign(config, user).map_err(|_| {
+    let refresh_token = TokenType::Refresh.sign(config, &db.1, user).map_err(|_| {
         Error::InternalServerError("An error occurred while signing the refresh token".into())
     })?;
 
@@ -95,6 +120,9 @@ pub async fn authenticate(
         inactive_at: Utc::now() + chrono::Duration::try_days(15).unwrap(),
         expires_at: Utc::now() + chrono::Duration::try_days(90).unwrap(),
         tokens: vec![refresh_token.clone().claims.jti],
+        user_agent: device.user_agent,
+        ip_address: device.ip_address,
+        last_used_at: Some(Utc::now()),
         created_at: Utc::now(),
         updated_at: None,
     }
@@ -132,7 +160,12 @@ pub fn create_auth_cookies(refresh_token: TokenInfo, jar: CookieJar) -> Result<C
 }
 
 impl TokenType {
-    pub fn sign(&self, config: &config::Config, user: &AnyUser) -> Result<TokenInfo, Error> {
+    pub fn sign(
+        &self,
+        config: &config::Config,
+        db_type: &DatabaseType,
+        user: &AnyUser,
+    ) -> Result<TokenInfo, Error> {
         let expires_at = match self {
             TokenType::Access => Utc::now() + Duration::try_minutes(15).unwrap(),
             TokenType::Refresh => Utc::now() + Duration::try_days(15).unwrap(),
@@ -142,15 +175,27 @@ impl TokenType {
             iat: Utc::now().timestamp() as usize,
             exp: expires_at.timestamp() as usize,
             sub: user.id.clone(),
+            aud: audience(db_type),
+            iss: ISSUER.into(),
             token_type: self.to_string(),
         };
 
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(config.secret_key.expose_secret().as_bytes()),
-        )
-        .map_err(|err| {
+        // The shared secret is only used until a signing key has been generated
+        let (header, key) = match config.signing_keys.first() {
+            Some(signing_key) => (
+                Header {
+                    kid: Some(signing_key.id.clone()),
+                    ..Header::new(signing_key.jwt_algorithm()?)
+                },
+                signing_key.encoding_key()?,
+            ),
+            None => (
+                Header::default(),
+                EncodingKey::from_secret(config.secret_key.expose_secret().as_bytes()),
+            ),
+        };
+
+        let token = encode(&header, &claims, &key).map_err(|err| {
             Error::InternalServerError(format!("Unable to encode {self} token: {err}"))
         })?;
 
@@ -162,17 +207,47 @@ impl TokenType {
         })
     }
 
-    pub fn verify(config: &config::Config, token: String) -> Result<TokenInfo, Error> {
-        let claims = decode::<Claims>(
-            &token,
-            &DecodingKey::from_secret(config.secret_key.expose_secret().as_bytes()),
-            &Validation::default(),
-        )
-        .map(|data| data.claims)
-        .map_err(|err| match err.into_kind() {
-            ErrorKind::ExpiredSignature => Error::Unauthorized,
-            _ => Error::InternalServerError("Unable to decode token".into()),
-        })?;
+    /// Verifies a token that was issued for one of `db_types`
+    pub fn verify(
+        config: &config::Config,
+        db_types: &[DatabaseType],
+        token: String,
+    ) -> Result<TokenInfo, Error> {
+        let header = decode_header(&token).map_err(|_| Error::Unauthorized)?;
+        let (key, mut validation) = match (header.kid, config.signing_keys.is_empty()) {
+            (Some(kid), _) => {
+                // Retired keys aren't loaded, so their tokens are rejected here
+                let signing_key = config
+                    .signing_keys
+                    .iter()
+                    .find(|k| k.id == kid)
+                    .ok_or(Error::Unauthorized)?;
+
+                (
+                    signing_key.decoding_key()?,
+                    Validation::new(signing_key.jwt_algorithm()?),
+                )
+            }
+            (None, true) => (
+                DecodingKey::from_secret(config.secret_key.expose_secret().as_bytes()),
+                Validation::default(),
+            ),
+            (None, false) => return Err(Error::Unauthorized),
+        };
+
+        validation.set_audience(&db_types.iter().map(audience).collect::<Vec<_>>());
+        validation.set_issuer(&[ISSUER]);
+        validation.set_required_spec_claims(&["exp", "aud", "iss"]);
+
+        let claims = decode::<Claims>(&token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|err| match err.into_kind() {
+                ErrorKind::ExpiredSignature
+                | ErrorKind::InvalidAudience
+                | ErrorKind::InvalidIssuer
+                | ErrorKind::MissingRequiredClaim(_) => Error::Unauthorized,
+                _ => Error::InternalServerError("Unable to decode token".into()),
+            })?;
 
         let token_type = match claims.token_type.as_str() {
             "access" => TokenType::Access,
@@ -192,3 +267,70 @@ impl TokenType {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use crate::entities::SigningKey;
+
+    use super::*;
+
+    fn with_keys(signing_keys: Vec<SigningKey>) -> Config {
+        for (key, value) in [
+            ("POSTGRES_URL", "postgres://localhost"),
+            ("REDIS_URL", "redis://localhost"),
+            ("S3_BUCKET", "bucket"),
+            ("S3_REGION", "region"),
+            ("S3_ENDPOINT", "http://localhost"),
+            ("S3_ACCESS_KEY", "key"),
+            ("S3_SECRET_KEY", "secret"),
+        ] {
+            env::set_var(key, value);
+        }
+
+        Config {
+            signing_keys,
+            ..Config::new()
+        }
+    }
+
+    #[test]
+    fn signs_and_verifies_with_signing_keys() {
+        let user = AnyUser {
+            id: "user".into(),
+            ..Default::default()
+        };
+        let keys = [
+            SigningKey::generate().unwrap(),
+            SigningKey::import_rsa(include_str!("../../tests/fixtures/rsa.pem")).unwrap(),
+        ];
+
+        for key in keys {
+            let config = with_keys(vec![key.clone()]);
+            let signed = TokenType::Access
+                .sign(&config, &DatabaseType::System, &user)
+                .unwrap();
+
+            let header = decode_header(&signed.token).unwrap();
+            assert_eq!(header.kid, Some(key.id.clone()));
+            assert_eq!(header.alg, key.jwt_algorithm().unwrap());
+
+            let verified =
+                TokenType::verify(&config, &[DatabaseType::System], signed.token.clone()).unwrap();
+            assert_eq!(verified.claims.sub, "user");
+            assert!(TokenType::verify(
+                &config,
+                &[DatabaseType::Project("project".into())],
+                signed.token.clone()
+            )
+            .is_err());
+
+            // Retired keys aren't loaded, and the shared secret can't stand in for them
+            assert!(
+                TokenType::verify(&with_keys(vec![]), &[DatabaseType::System], signed.token)
+                    .is_err()
+            );
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
+
+/// How a passwordless login reaches the user
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Method {
+    /// A link carrying a single-use token
+    Link,
+    /// A short code typed back into the client
+    Code,
+}
+
+/// A 6 digit one-time code
+pub fn generate_code() -> String {
+    format!("{:06}", thread_rng().gen_range(0..1_000_000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_codes() {
+        for _ in 0..100 {
+            let code = generate_code();
+            assert_eq!(code.len(), 6);
+            assert!(code.chars().all(|c| c.is_ascii_digit()));
+        }
+    }
+}
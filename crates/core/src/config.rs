@@ -5,13 +5,15 @@ use std::{env, fmt};
 use secrecy::Secret;
 use tracing_unwrap::ResultExt;
 
-use crate::entities::{SizeUnit, System};
+use crate::entities::{SigningKey, SizeUnit, System};
 
 #[derive(Clone, Debug)]
 enum ConfigKey {
     SentryDsn,
     SecretKey,
     UseTls,
+    TrustedProxies,
+    AllowPrivateWebhooks,
     CertsPath,
     ClientUrl,
     ServerUrl,
@@ -31,6 +33,8 @@ impl fmt::Display for ConfigKey {
             Self::SentryDsn => "SENTRY_DSN",
             Self::SecretKey => "SECRET_KEY",
             Self::UseTls => "USE_TLS",
+            Self::TrustedProxies => "TRUSTED_PROXIES",
+            Self::AllowPrivateWebhooks => "ALLOW_PRIVATE_WEBHOOKS",
             Self::CertsPath => "CERTS_PATH",
             Self::ClientUrl => "CLIENT_URL",
             Self::ServerUrl => "SERVER_URL",
@@ -56,6 +60,11 @@ pub struct Config {
     pub sentry_dsn: Option<String>,
 
     pub use_tls: bool,
+    /// How many proxies sit in front of the server. Forwarded headers are ignored without any,
+    /// since clients could set them to anything.
+    pub trusted_proxies: usize,
+    /// Lets webhooks call loopback, link-local and private addresses, e.g. during development.
+    pub allow_private_webhooks: bool,
     pub certs_path: Option<String>,
 
     pub client_url: String,
@@ -66,6 +75,8 @@ pub struct Config {
     pub redis_prefix: Option<String>,
 
     pub secret_key: Secret<String>,
+    /// Keys that can verify tokens, newest first. The first one signs new tokens.
+    pub signing_keys: Vec<SigningKey>,
 
     pub s3_bucket: String,
     pub s3_region: String,
@@ -114,10 +125,19 @@ impl Config {
             secret_key: Secret::new(env::var(ConfigKey::SecretKey.to_string()).unwrap_or(
                 "l19OOJaOvpal21ofSlsxYyNVQN2EeTY6gEuq6p_hobH_QmJb3dPELmoGKFstBpCI".into(),
             )),
+            signing_keys: vec![],
             use_tls: env::var(ConfigKey::UseTls.to_string())
                 .unwrap_or("false".to_string())
                 .parse()
                 .unwrap_or_log(),
+            trusted_proxies: env::var(ConfigKey::TrustedProxies.to_string())
+                .unwrap_or("0".to_string())
+                .parse()
+                .unwrap_or_log(),
+            allow_private_webhooks: env::var(ConfigKey::AllowPrivateWebhooks.to_string())
+                .unwrap_or("false".to_string())
+                .parse()
+                .unwrap_or_log(),
             certs_path: env::var(ConfigKey::CertsPath.to_string()).ok(),
             client_url: env::var(ConfigKey::ClientUrl.to_string()).unwrap_or("/".into()),
             server_url: env::var(ConfigKey::ServerUrl.to_string())
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use crate::db::postgres::{Database, DatabaseType};
 
 use super::{
-    Connection, Passkey, RefreshTokenTree, SystemUser, UpdateSystemUser, UpdateUser, User,
+    ApiKey, Connection, Passkey, RefreshTokenTree, SystemUser, UpdateSystemUser, UpdateUser, User,
 };
 
 #[derive(Debug, Clone)]
@@ -18,6 +18,19 @@ pub enum UserType<'a> {
 pub enum AlternateUserType {
     System,
     Normal,
+    /// A project API key acting on its own behalf
+    Service(Box<ApiKey>),
+}
+
+impl AlternateUserType {
+    /// Whether the custom table permissions apply to the user
+    pub fn is_restricted(&self) -> bool {
+        match self {
+            AlternateUserType::System => false,
+            AlternateUserType::Normal => true,
+            AlternateUserType::Service(key) => !key.bypass_permissions,
+        }
+    }
 }
 
 #[enum_def]
@@ -34,6 +47,15 @@ pub struct AnyUser {
     pub mfa_secret: Option<String>,
     #[serde(skip_serializing)]
     pub mfa_backup_codes: Option<Vec<String>>,
+    /// Only project users can be banned or unverified, system users are never banned and always
+    /// verified
+    #[serde(default, skip_serializing)]
+    pub banned: bool,
+    #[serde(default, skip_serializing)]
+    pub verified: bool,
+    /// The names of the user's roles, only loaded for the request's user
+    #[serde(default, skip_serializing)]
+    pub roles: Vec<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
 
@@ -112,6 +134,7 @@ impl<'a> UserType<'a> {
                     email: user.email,
                     username: user.username,
                     password: user.password,
+                    verified: user.verified,
                     created_at: user.created_at,
                     ..Default::default()
                 }
@@ -191,7 +214,7 @@ impl<'a> UserType<'a> {
     }
 
     pub fn find(&self) -> AnyUserSelectBuilder {
-        AnyUserSelectBuilder {
+        let mut builder = AnyUserSelectBuilder {
             user_type: self.clone(),
             db: match self {
                 UserType::System(db) => db,
@@ -212,7 +235,15 @@ impl<'a> UserType<'a> {
                     AnyUserIden::UpdatedAt,
                 ])
                 .to_owned(),
+        };
+
+        if let UserType::Normal(_) = self {
+            builder
+                .query_builder
+                .columns([AnyUserIden::Banned, AnyUserIden::Verified]);
         }
+
+        builder
     }
 
     pub fn find_by_id(&self, id: &str) -> AnyUserSelectBuilder {
@@ -221,6 +252,16 @@ impl<'a> UserType<'a> {
     }
 }
 
+impl AnyUser {
+    pub fn ensure_not_banned(&self) -> Result<(), crate::error::Error> {
+        if self.banned {
+            return Err(crate::error::Error::Forbidden("User is banned".into()));
+        }
+
+        Ok(())
+    }
+}
+
 pub enum AnyUserJoin {
     Connections,
     RefreshTokenTrees,
@@ -354,6 +395,9 @@ impl From<deadpool_postgres::tokio_postgres::Row> for AnyUser {
             password: row.get("password"),
             mfa_secret: row.get("mfa_secret"),
             mfa_backup_codes: row.get("mfa_backup_codes"),
+            banned: row.try_get("banned").unwrap_or(false),
+            verified: row.try_get("verified").unwrap_or(true),
+            roles: vec![],
             created_at: row.get("created_at"),
             updated_at: row.get("updated_at"),
 
@@ -398,6 +442,9 @@ impl From<User> for AnyUser {
             updated_at: value.updated_at,
             mfa_secret: value.mfa_secret,
             mfa_backup_codes: value.mfa_backup_codes,
+            banned: value.banned,
+            verified: value.verified,
+            roles: vec![],
 
             connections: value.connections,
             refresh_token_trees: value.refresh_token_trees,
@@ -419,6 +466,9 @@ impl From<SystemUser> for AnyUser {
             updated_at: value.updated_at,
             mfa_secret: value.mfa_secret,
             mfa_backup_codes: value.mfa_backup_codes,
+            banned: false,
+            verified: true,
+            roles: vec![],
 
             connections: value.connections,
             refresh_token_trees: value.refresh_token_trees,
@@ -426,3 +476,17 @@ impl From<SystemUser> for AnyUser {
         }
     }
 }
+
+/// Lets permissions refer to a key through `@request.user`
+impl From<ApiKey> for AnyUser {
+    fn from(value: ApiKey) -> Self {
+        AnyUser {
+            id: value.id,
+            username: value.name,
+            created_at: value.created_at,
+            updated_at: value.updated_at,
+            verified: true,
+            ..Default::default()
+        }
+    }
+}
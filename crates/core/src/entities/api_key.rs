@@ -0,0 +1,169 @@
+use std::fmt;
+
+use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use chrono::{DateTime, Duration, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sea_query::{enum_def, Expr, PostgresQueryBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::error;
+use tracing_unwrap::ResultExt;
+
+use crate::error::Error;
+
+use super::Update;
+
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    #[adrastos(find, unique)]
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiKeyScope>,
+    pub bypass_permissions: bool,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Grants `operations` on a custom table, `*` matches every table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyScope {
+    pub table: String,
+    pub operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    View,
+    Create,
+    Update,
+    Delete,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operation::View => "view",
+            Operation::Create => "create",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateApiKey {
+    pub name: Option<String>,
+    pub scopes: Option<Vec<ApiKeyScope>>,
+    pub bypass_permissions: Option<bool>,
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiKey {
+    pub const PREFIX: &'static str = "adr_";
+
+    /// Generates a plaintext key, only its hash is ever stored
+    pub fn generate_key() -> String {
+        let secret = thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect::<String>();
+
+        format!("{}{secret}", Self::PREFIX)
+    }
+
+    pub fn hash(key: &str) -> String {
+        hex::encode(Sha256::digest(key.as_bytes()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= Utc::now())
+    }
+
+    pub fn allows(&self, table: &str, operation: Operation) -> bool {
+        self.scopes.iter().any(|scope| {
+            (scope.table == "*" || scope.table == table) && scope.operations.contains(&operation)
+        })
+    }
+
+    /// Records a use of the key, at most once a minute to avoid a write on every request
+    pub async fn touch(&self, db: &deadpool_postgres::Pool) -> Result<(), Error> {
+        let now = Utc::now();
+        if self
+            .last_used_at
+            .is_some_and(|last_used_at| now - last_used_at < Duration::minutes(1))
+        {
+            return Ok(());
+        }
+
+        self.update(
+            db,
+            UpdateApiKey {
+                last_used_at: Some(now),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn update(
+        &self,
+        db: &deadpool_postgres::Pool,
+        update: UpdateApiKey,
+    ) -> Result<(), Error> {
+        let mut values = vec![
+            (ApiKeyIden::Name, update.name.into()),
+            (
+                ApiKeyIden::Scopes,
+                update
+                    .scopes
+                    .map(|v| {
+                        v.into_iter()
+                            .map(|v| serde_json::to_string(&v).unwrap_or_log())
+                            .collect::<Vec<_>>()
+                    })
+                    .into(),
+            ),
+            (
+                ApiKeyIden::BypassPermissions,
+                update.bypass_permissions.into(),
+            ),
+            (ApiKeyIden::ExpiresAt, update.expires_at.into()),
+            (ApiKeyIden::LastUsedAt, update.last_used_at.into()),
+        ];
+        // Using a key isn't a change to it
+        if update.last_used_at.is_none() {
+            values.push((ApiKeyIden::UpdatedAt, Some(Utc::now()).into()));
+        }
+
+        let query = sea_query::Query::update()
+            .table(Self::table())
+            .values(Update::create(values))
+            .and_where(Expr::col(ApiKeyIden::Id).eq(self.id.clone()))
+            .to_string(PostgresQueryBuilder);
+
+        db.get()
+            .await
+            .unwrap_or_log()
+            .execute(&query, &[])
+            .await
+            .map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError("Failed to update API key".into())
+            })?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+use super::fields::{Field, FieldInfo};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ComputedType {
+    String,
+    Number,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Field(String),
+    Number(i64),
+    String(String),
+    Negate(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Concat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(i64),
+    String(String),
+    Operator(Operator),
+    Minus,
+    LeftParen,
+    RightParen,
+}
+
+impl Operator {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Subtract => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::Concat => "||",
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, Error> {
+    let invalid = |message: &str| Error::BadRequest(format!("Invalid expression, {message}"));
+
+    let mut tokens = vec![];
+    let mut chars = expression.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::LeftParen),
+            ')' => tokens.push(Token::RightParen),
+            '+' => tokens.push(Token::Operator(Operator::Add)),
+            '-' => tokens.push(Token::Minus),
+            '*' => tokens.push(Token::Operator(Operator::Multiply)),
+            '/' => tokens.push(Token::Operator(Operator::Divide)),
+            '|' => match chars.next() {
+                Some('|') => tokens.push(Token::Operator(Operator::Concat)),
+                _ => return Err(invalid("expected '||'")),
+            },
+            '\'' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\'') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            value.push('\'');
+                        }
+                        Some('\'') => break,
+                        Some(c) => value.push(c),
+                        None => return Err(invalid("unterminated string")),
+                    }
+                }
+
+                tokens.push(Token::String(value));
+            }
+            c if c.is_ascii_digit() => {
+                let mut value = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                    value.push(c);
+                }
+
+                tokens.push(Token::Number(
+                    value.parse().map_err(|_| invalid("number is too large"))?,
+                ));
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut value = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    value.push(c);
+                }
+
+                tokens.push(Token::Ident(value));
+            }
+            c => return Err(invalid(&format!("unexpected character '{c}'"))),
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+
+        token
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[Operator],
+        operand: fn(&mut Self) -> Result<Expression, Error>,
+    ) -> Result<Expression, Error> {
+        let mut left = operand(self)?;
+
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Minus) if operators.contains(&Operator::Subtract) => Operator::Subtract,
+                Some(Token::Operator(operator)) if operators.contains(operator) => *operator,
+                _ => return Ok(left),
+            };
+            self.advance();
+
+            left = Expression::Binary(Box::new(left), operator, Box::new(operand(self)?));
+        }
+    }
+
+    fn expression(&mut self) -> Result<Expression, Error> {
+        self.binary(
+            &[Operator::Add, Operator::Subtract, Operator::Concat],
+            Self::term,
+        )
+    }
+
+    fn term(&mut self) -> Result<Expression, Error> {
+        self.binary(&[Operator::Multiply, Operator::Divide], Self::factor)
+    }
+
+    fn factor(&mut self) -> Result<Expression, Error> {
+        match self.advance() {
+            Some(Token::Ident(name)) => Ok(Expression::Field(name)),
+            Some(Token::Number(value)) => Ok(Expression::Number(value)),
+            Some(Token::String(value)) => Ok(Expression::String(value)),
+            Some(Token::Minus) => Ok(Expression::Negate(Box::new(self.factor()?))),
+            Some(Token::LeftParen) => {
+                let expression = self.expression()?;
+                match self.advance() {
+                    Some(Token::RightParen) => Ok(expression),
+                    _ => Err(Error::BadRequest("Invalid expression, expected ')'".into())),
+                }
+            }
+            _ => Err(Error::BadRequest(
+                "Invalid expression, expected a field or value".into(),
+            )),
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(expression: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+
+        let expression = parser.expression()?;
+        if parser.peek().is_some() {
+            return Err(Error::BadRequest(
+                "Invalid expression, unexpected trailing input".into(),
+            ));
+        }
+
+        Ok(expression)
+    }
+
+    /// Type checks the expression against the fields it can reference
+    pub fn kind(&self, fields: &[Field]) -> Result<ComputedType, Error> {
+        match self {
+            Self::Number(_) => Ok(ComputedType::Number),
+            Self::String(_) => Ok(ComputedType::String),
+            Self::Field(name) => {
+                let field = fields.iter().find(|f| &f.name == name).ok_or_else(|| {
+                    Error::BadRequest(format!("Expression references unknown field '{name}'"))
+                })?;
+
+                match field.info {
+                    FieldInfo::String { .. } | FieldInfo::Email { .. } | FieldInfo::Url { .. } => {
+                        Ok(ComputedType::String)
+                    }
+                    FieldInfo::Number { .. } => Ok(ComputedType::Number),
+                    _ => Err(Error::BadRequest(format!(
+                        "Field '{name}' can't be used in an expression"
+                    ))),
+                }
+            }
+            Self::Negate(expression) => match expression.kind(fields)? {
+                ComputedType::Number => Ok(ComputedType::Number),
+                ComputedType::String => {
+                    Err(Error::BadRequest("Only numbers can be negated".into()))
+                }
+            },
+            Self::Binary(left, operator, right) => {
+                let expected = match operator {
+                    Operator::Concat => ComputedType::String,
+                    _ => ComputedType::Number,
+                };
+
+                if left.kind(fields)? != expected || right.kind(fields)? != expected {
+                    return Err(Error::BadRequest(format!(
+                        "Both sides of '{}' must be of type {expected:?}",
+                        operator.as_sql()
+                    )));
+                }
+
+                Ok(expected)
+            }
+        }
+    }
+
+    pub fn to_sql(&self) -> String {
+        match self {
+            Self::Field(name) => format!("\"{name}\""),
+            Self::Number(value) => value.to_string(),
+            Self::String(value) => format!("'{}'", value.replace('\'', "''")),
+            Self::Negate(expression) => format!("-({})", expression.to_sql()),
+            Self::Binary(left, operator, right) => {
+                format!(
+                    "({} {} {})",
+                    left.to_sql(),
+                    operator.as_sql(),
+                    right.to_sql()
+                )
+            }
+        }
+    }
+}
+
+/// Checks every computed field in `fields` parses and matches its declared type
+pub fn validate(fields: &[Field]) -> Result<(), Error> {
+    for field in fields {
+        let FieldInfo::Computed {
+            expression,
+            returns,
+        } = &field.info
+        else {
+            continue;
+        };
+
+        if &Expression::parse(expression)?.kind(fields)? != returns {
+            return Err(Error::BadRequest(format!(
+                "Expression for '{}' doesn't return a {returns:?}",
+                field.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Vec<Field> {
+        let string = FieldInfo::String {
+            min_length: None,
+            max_length: None,
+            pattern: None,
+            is_required: false,
+            is_unique: false,
+        };
+        let number = FieldInfo::Number {
+            min: None,
+            max: None,
+            is_required: false,
+            is_unique: false,
+        };
+
+        vec![
+            Field {
+                name: "first_name".into(),
+                info: string.clone(),
+            },
+            Field {
+                name: "last_name".into(),
+                info: string,
+            },
+            Field {
+                name: "price".into(),
+                info: number.clone(),
+            },
+            Field {
+                name: "quantity".into(),
+                info: number,
+            },
+        ]
+    }
+
+    #[test]
+    fn parses_and_type_checks() {
+        let expression = Expression::parse("first_name || ' ' || last_name").unwrap();
+        assert_eq!(expression.kind(&fields()), Ok(ComputedType::String));
+        assert_eq!(
+            expression.to_sql(),
+            "((\"first_name\" || ' ') || \"last_name\")"
+        );
+
+        let expression = Expression::parse("price * quantity - 1").unwrap();
+        assert_eq!(expression.kind(&fields()), Ok(ComputedType::Number));
+        assert_eq!(expression.to_sql(), "((\"price\" * \"quantity\") - 1)");
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        assert!(Expression::parse("price *").is_err());
+        assert!(Expression::parse("price; DROP TABLE users").is_err());
+        assert!(Expression::parse("price * first_name")
+            .unwrap()
+            .kind(&fields())
+            .is_err());
+        assert!(Expression::parse("total + 1")
+            .unwrap()
+            .kind(&fields())
+            .is_err());
+    }
+}
@@ -1,14 +1,20 @@
 use std::borrow::Cow;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
 use regex::Regex;
-use sea_query::{Alias, ColumnDef, ColumnType, SimpleExpr};
+use sea_query::{Alias, ColumnDef, ColumnType, Keyword, SimpleExpr};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use validator::ValidationError;
+use validator::{ValidateEmail, ValidationError};
 
 use crate::{url::Url, util};
 
+use super::{
+    computed::{ComputedType, Expression},
+    geo::Point,
+    select::{self, SelectOption},
+};
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum RelationTarget {
@@ -40,6 +46,30 @@ pub enum FieldInfo {
         is_required: bool,
         is_unique: bool,
     },
+    /// A calendar day without a time, like a birthday
+    #[serde(rename_all = "camelCase")]
+    DateOnly {
+        min: Option<NaiveDate>,
+        max: Option<NaiveDate>,
+        is_required: bool,
+        is_unique: bool,
+    },
+    /// A time of day without a date, like an opening hour
+    #[serde(rename_all = "camelCase")]
+    Time {
+        min: Option<NaiveTime>,
+        max: Option<NaiveTime>,
+        is_required: bool,
+        is_unique: bool,
+    },
+    /// A length of time, stored as whole seconds
+    #[serde(rename_all = "camelCase")]
+    Duration {
+        min: Option<i64>,
+        max: Option<i64>,
+        is_required: bool,
+        is_unique: bool,
+    },
     #[serde(rename_all = "camelCase")]
     Email {
         except: Vec<String>,
@@ -56,7 +86,10 @@ pub enum FieldInfo {
     },
     #[serde(rename_all = "camelCase")]
     Select {
-        options: Vec<String>,
+        options: Vec<SelectOption>,
+        /// Stores one value instead of an array
+        #[serde(default)]
+        single: bool,
         min_selected: Option<i32>,
         max_selected: Option<i32>,
         is_required: bool,
@@ -72,6 +105,15 @@ pub enum FieldInfo {
         is_required: bool,
         is_unique: bool,
     },
+    #[serde(rename_all = "camelCase")]
+    GeoPoint {
+        is_required: bool,
+    },
+    /// Read-only value derived from other fields through a generated column
+    Computed {
+        expression: String,
+        returns: ComputedType,
+    },
 }
 
 #[derive(Deserialize, Debug, Serialize, Clone)]
@@ -82,8 +124,153 @@ pub struct Field {
     pub info: FieldInfo,
 }
 
+fn type_error(expected: &str) -> ValidationError {
+    let mut error = ValidationError::new("type");
+    error.add_param(Cow::from("expected"), &expected);
+
+    error
+}
+
+/// Accepts integers, whole floats and numeric strings
+fn as_integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(number) => number.as_i64().or_else(|| {
+            number
+                .as_f64()
+                .filter(|n| n.fract() == 0.0 && n.abs() < i64::MAX as f64)
+                .map(|n| n as i64)
+        }),
+        Value::String(value) => value.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_bool(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(value) => Some(*value),
+        Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+fn as_strings(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| v.as_str().map(str::to_string))
+        .collect()
+}
+
+fn bounds_error<T: PartialOrd + Serialize>(
+    value: &T,
+    min: &Option<T>,
+    max: &Option<T>,
+) -> Option<ValidationError> {
+    let mut value_error = ValidationError::new("value");
+
+    if let Some(min) = min
+        && value < min
+    {
+        value_error.add_param(Cow::from("min"), min);
+    }
+    if let Some(max) = max
+        && value > max
+    {
+        value_error.add_param(Cow::from("max"), max);
+    }
+
+    (!value_error.params.is_empty()).then_some(value_error)
+}
+
+fn parse_date_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|v| v.with_timezone(&Utc))
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+fn parse_time(value: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(value, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+        .ok()
+}
+
+/// Accepts whole seconds or `HH:MM:SS`, where hours may exceed a day
+fn parse_duration(value: &Value) -> Option<i64> {
+    if let Some(seconds) = value.as_i64() {
+        return Some(seconds);
+    }
+
+    let parts = value
+        .as_str()?
+        .split(':')
+        .map(|p| p.parse::<i64>().ok().filter(|p| *p >= 0))
+        .collect::<Option<Vec<_>>>()?;
+    match parts.as_slice() {
+        [hours, minutes, seconds] if *minutes < 60 && *seconds < 60 => {
+            Some(hours * 3600 + minutes * 60 + seconds)
+        }
+        _ => None,
+    }
+}
+
 impl Field {
+    /// Parses a query string value for range filters like `starts_at.gte`, returning `None`
+    /// when the field can't be compared or the value is invalid
+    pub fn range_value(&self, value: &str) -> Option<SimpleExpr> {
+        match &self.info {
+            FieldInfo::Number { .. } => value.parse::<i64>().ok().map(Into::into),
+            FieldInfo::Date { .. } => parse_date_time(value).map(Into::into),
+            FieldInfo::DateOnly { .. } => parse_date(value).map(Into::into),
+            FieldInfo::Time { .. } => parse_time(value).map(Into::into),
+            FieldInfo::Duration { .. } => value
+                .parse::<i64>()
+                .ok()
+                .or_else(|| parse_duration(&Value::String(value.into())))
+                .map(Into::into),
+            _ => None,
+        }
+    }
+
+    /// Parses a query string value for equality filters, returning `None` when it doesn't fit
+    /// the field
+    pub fn filter_value(&self, value: &str) -> Option<SimpleExpr> {
+        match &self.info {
+            FieldInfo::String { .. }
+            | FieldInfo::Email { .. }
+            | FieldInfo::Url { .. }
+            | FieldInfo::Select { single: true, .. }
+            | FieldInfo::Relation {
+                target: RelationTarget::Single,
+                ..
+            }
+            | FieldInfo::Computed {
+                returns: ComputedType::String,
+                ..
+            } => Some(value.into()),
+            FieldInfo::Computed {
+                returns: ComputedType::Number,
+                ..
+            } => value.parse::<i64>().ok().map(Into::into),
+            FieldInfo::Boolean => value.parse::<bool>().ok().map(Into::into),
+            _ => self.range_value(value),
+        }
+    }
+
     pub fn column(&self) -> ColumnDef {
+        self.build_column(true)
+    }
+
+    /// The column without NOT NULL, for tables whose existing rows have no value. Required
+    /// values are still checked by `validate` on every write.
+    pub fn nullable_column(&self) -> ColumnDef {
+        self.build_column(false)
+    }
+
+    fn build_column(&self, enforce_required: bool) -> ColumnDef {
         let mut column = ColumnDef::new(Alias::new(&self.name));
 
         match &self.info {
@@ -92,7 +279,7 @@ impl Field {
                 is_unique,
                 ..
             } => {
-                if *is_required {
+                if *is_required && enforce_required {
                     column.not_null();
                 }
                 if *is_unique {
@@ -106,7 +293,7 @@ impl Field {
                 is_unique,
                 ..
             } => {
-                if *is_required {
+                if *is_required && enforce_required {
                     column.not_null();
                 }
                 if *is_unique {
@@ -122,7 +309,7 @@ impl Field {
                 is_required,
                 is_unique,
             } => {
-                if *is_required {
+                if *is_required && enforce_required {
                     column.not_null();
                 }
                 if *is_unique {
@@ -131,12 +318,54 @@ impl Field {
 
                 column.timestamp_with_time_zone();
             }
+            FieldInfo::DateOnly {
+                is_required,
+                is_unique,
+                ..
+            } => {
+                if *is_required && enforce_required {
+                    column.not_null();
+                }
+                if *is_unique {
+                    column.unique_key();
+                }
+
+                column.date();
+            }
+            FieldInfo::Time {
+                is_required,
+                is_unique,
+                ..
+            } => {
+                if *is_required && enforce_required {
+                    column.not_null();
+                }
+                if *is_unique {
+                    column.unique_key();
+                }
+
+                column.time();
+            }
+            FieldInfo::Duration {
+                is_required,
+                is_unique,
+                ..
+            } => {
+                if *is_required && enforce_required {
+                    column.not_null();
+                }
+                if *is_unique {
+                    column.unique_key();
+                }
+
+                column.big_integer();
+            }
             FieldInfo::Email {
                 is_required,
                 is_unique,
                 ..
             } => {
-                if *is_required {
+                if *is_required && enforce_required {
                     column.not_null();
                 }
                 if *is_unique {
@@ -150,7 +379,7 @@ impl Field {
                 is_unique,
                 ..
             } => {
-                if *is_required {
+                if *is_required && enforce_required {
                     column.not_null();
                 }
                 if *is_unique {
@@ -160,18 +389,25 @@ impl Field {
                 column.string();
             }
             FieldInfo::Select {
+                single,
                 is_required,
                 is_unique,
                 ..
             } => {
-                if *is_required {
+                if *is_required && enforce_required {
                     column.not_null();
                 }
                 if *is_unique {
                     column.unique_key();
                 }
 
-                column.array(ColumnType::String(None));
+                match single {
+                    true => column.string(),
+                    false => column.array(ColumnType::String(None)),
+                };
+                if let Some(constraint) = select::column_constraint(self) {
+                    column.extra(constraint);
+                }
             }
             FieldInfo::Relation {
                 target,
@@ -180,7 +416,7 @@ impl Field {
                 ..
             } => {
                 if target == &RelationTarget::Single {
-                    if *is_required {
+                    if *is_required && enforce_required {
                         column.not_null();
                     }
                     if *is_unique {
@@ -190,6 +426,30 @@ impl Field {
                     column.string();
                 };
             }
+            FieldInfo::GeoPoint { is_required } => {
+                if *is_required && enforce_required {
+                    column.not_null();
+                }
+
+                column.array(ColumnType::Double);
+            }
+            FieldInfo::Computed {
+                expression,
+                returns,
+            } => {
+                match returns {
+                    ComputedType::String => column.string(),
+                    ComputedType::Number => column.big_integer(),
+                };
+
+                // Expressions are checked by `computed::validate` before any query runs
+                column.extra(format!(
+                    "GENERATED ALWAYS AS ({}) STORED",
+                    Expression::parse(expression)
+                        .map(|e| e.to_sql())
+                        .unwrap_or_default()
+                ));
+            }
         }
 
         column
@@ -197,6 +457,8 @@ impl Field {
 
     pub fn validate(&self, value: Option<&Value>) -> Result<SimpleExpr, Vec<ValidationError>> {
         let mut errors = vec![];
+        // An explicit null is the same as leaving the field out
+        let value = value.filter(|v| !v.is_null());
 
         match &self.info {
             FieldInfo::String {
@@ -208,7 +470,9 @@ impl Field {
             } => {
                 match value {
                     Some(value) => {
-                        let value = value.as_str().unwrap();
+                        let Some(value) = value.as_str() else {
+                            return Err(vec![type_error("string")]);
+                        };
 
                         let mut length_error = ValidationError::new("length");
 
@@ -255,7 +519,9 @@ impl Field {
                 ..
             } => match value {
                 Some(value) => {
-                    let value = value.as_i64().unwrap();
+                    let Some(value) = as_integer(value) else {
+                        return Err(vec![type_error("integer")]);
+                    };
 
                     let mut value_error = ValidationError::new("value");
 
@@ -286,18 +552,77 @@ impl Field {
             },
             FieldInfo::Boolean => {
                 let value = match value {
-                    Some(value) => value.as_bool().unwrap(),
+                    Some(value) => match as_bool(value) {
+                        Some(value) => value,
+                        None => return Err(vec![type_error("boolean")]),
+                    },
                     None => false,
                 };
 
                 return Ok(value.into());
             }
             FieldInfo::Date { is_required, .. } => match value {
-                Some(value) => {
-                    return Ok(serde_json::from_value::<DateTime<Utc>>(value.to_owned())
-                        .unwrap()
-                        .into());
+                Some(value) => match value.as_str().and_then(parse_date_time) {
+                    Some(value) => return Ok(value.into()),
+                    None => errors.push(type_error("RFC 3339 date and time")),
+                },
+                None => {
+                    if *is_required {
+                        errors.push(ValidationError::new("required"));
+                    }
+                }
+            },
+            FieldInfo::DateOnly {
+                min,
+                max,
+                is_required,
+                ..
+            } => match value {
+                Some(value) => match value.as_str().and_then(parse_date) {
+                    Some(value) => match bounds_error(&value, min, max) {
+                        Some(error) => errors.push(error),
+                        None => return Ok(value.into()),
+                    },
+                    None => errors.push(type_error("YYYY-MM-DD date")),
+                },
+                None => {
+                    if *is_required {
+                        errors.push(ValidationError::new("required"));
+                    }
                 }
+            },
+            FieldInfo::Time {
+                min,
+                max,
+                is_required,
+                ..
+            } => match value {
+                Some(value) => match value.as_str().and_then(parse_time) {
+                    Some(value) => match bounds_error(&value, min, max) {
+                        Some(error) => errors.push(error),
+                        None => return Ok(value.into()),
+                    },
+                    None => errors.push(type_error("HH:MM or HH:MM:SS time")),
+                },
+                None => {
+                    if *is_required {
+                        errors.push(ValidationError::new("required"));
+                    }
+                }
+            },
+            FieldInfo::Duration {
+                min,
+                max,
+                is_required,
+                ..
+            } => match value {
+                Some(value) => match parse_duration(value) {
+                    Some(value) => match bounds_error(&value, min, max) {
+                        Some(error) => errors.push(error),
+                        None => return Ok(value.into()),
+                    },
+                    None => errors.push(type_error("number of seconds or HH:MM:SS duration")),
+                },
                 None => {
                     if *is_required {
                         errors.push(ValidationError::new("required"));
@@ -311,7 +636,12 @@ impl Field {
                 ..
             } => match value {
                 Some(value) => {
-                    let value = value.as_str().unwrap();
+                    let Some(value) = value.as_str() else {
+                        return Err(vec![type_error("string")]);
+                    };
+                    if !value.validate_email() {
+                        return Err(vec![ValidationError::new("email")]);
+                    }
                     let mut value_url = Url::from(value.to_owned());
 
                     let mut pattern_error = ValidationError::new("pattern");
@@ -357,7 +687,9 @@ impl Field {
                 ..
             } => match value {
                 Some(value) => {
-                    let value = value.as_str().unwrap();
+                    let Some(value) = value.as_str() else {
+                        return Err(vec![type_error("string")]);
+                    };
                     let mut value_url = Url::from(value.to_owned());
 
                     let mut pattern_error = ValidationError::new("pattern");
@@ -396,6 +728,33 @@ impl Field {
                     }
                 }
             },
+            FieldInfo::Select {
+                options,
+                single: true,
+                is_required,
+                ..
+            } => match value {
+                Some(value) => {
+                    let Some(value) = value.as_str() else {
+                        return Err(vec![type_error("string")]);
+                    };
+
+                    if !options.iter().any(|o| o.value == value) {
+                        let mut selections_error = ValidationError::new("selections");
+                        selections_error.add_param(Cow::from("invalid"), &[value]);
+                        errors.push(selections_error);
+                    }
+
+                    if errors.is_empty() {
+                        return Ok(value.into());
+                    }
+                }
+                None => {
+                    if *is_required {
+                        errors.push(ValidationError::new("required"));
+                    }
+                }
+            },
             FieldInfo::Select {
                 options,
                 max_selected,
@@ -404,18 +763,15 @@ impl Field {
                 ..
             } => match value {
                 Some(value) => {
-                    let value = value
-                        .as_array()
-                        .unwrap()
-                        .iter()
-                        .map(|v| v.as_str().unwrap().to_owned())
-                        .collect::<Vec<_>>();
+                    let Some(value) = as_strings(value) else {
+                        return Err(vec![type_error("array of strings")]);
+                    };
 
                     let mut selections_error = ValidationError::new("selections");
 
                     let invalid_selections = value
                         .iter()
-                        .map(|v| (v.to_owned(), options.contains(v)))
+                        .map(|v| (v.to_owned(), options.iter().any(|o| &o.value == v)))
                         .collect::<Vec<_>>();
 
                     if !invalid_selections.iter().all(|(_, contains)| *contains) {
@@ -467,13 +823,10 @@ impl Field {
                 ..
             } => match value {
                 Some(value) => {
-                    let return_val: Option<SimpleExpr> = if matches!(target, RelationTarget::Many) {
-                        let value = value
-                            .as_array()
-                            .unwrap()
-                            .iter()
-                            .map(|v| v.as_str().unwrap().to_string())
-                            .collect::<Vec<_>>();
+                    let return_val: SimpleExpr = if matches!(target, RelationTarget::Many) {
+                        let Some(value) = as_strings(value) else {
+                            return Err(vec![type_error("array of ids")]);
+                        };
 
                         let mut selections_error = ValidationError::new("selections");
 
@@ -492,23 +845,65 @@ impl Field {
                             errors.push(selections_error)
                         }
 
-                        Some(value.into())
+                        value.into()
                     } else {
-                        Some(value.as_str().unwrap().into())
+                        let Some(value) = value.as_str() else {
+                            return Err(vec![type_error("id")]);
+                        };
+
+                        value.into()
                     };
 
                     if errors.is_empty() {
-                        return Ok(return_val.unwrap());
+                        return Ok(return_val);
+                    }
+                }
+                None => {
+                    if *is_required {
+                        errors.push(ValidationError::new("required"));
                     }
                 }
+            },
+            FieldInfo::GeoPoint { is_required } => match value {
+                Some(value) => match Point::from_json(value) {
+                    Ok(point) => return Ok(vec![point.lat, point.lng].into()),
+                    Err(error) => errors.push(error),
+                },
                 None => {
                     if *is_required {
                         errors.push(ValidationError::new("required"));
                     }
                 }
             },
+            FieldInfo::Computed { .. } => {
+                if value.is_some() {
+                    errors.push(util::create_validation_error(
+                        "readOnly",
+                        Some("Computed fields can't be set".into()),
+                    ));
+                }
+            }
         }
 
         Err(errors)
     }
+
+    /// Validates `value` for an update. An absent value is skipped when `partial`, otherwise it
+    /// clears the column like an explicit null.
+    pub fn validate_update(
+        &self,
+        value: Option<&Value>,
+        partial: bool,
+    ) -> Result<Option<SimpleExpr>, Vec<ValidationError>> {
+        if partial && value.is_none() {
+            return Ok(None);
+        }
+
+        match self.validate(value) {
+            Ok(value) => Ok(Some(value)),
+            Err(errors) if !errors.is_empty() => Err(errors),
+            Err(_) if matches!(self.info, FieldInfo::Computed { .. }) => Ok(None),
+            Err(_) => Ok(Some(SimpleExpr::Keyword(Keyword::Null))),
+        }
+    }
 }
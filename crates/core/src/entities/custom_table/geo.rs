@@ -0,0 +1,175 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::ValidationError;
+
+use crate::error::Error;
+
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// A WGS84 coordinate, stored as a `[lat, lng]` double precision array
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct Point {
+    pub lat: f64,
+    pub lng: f64,
+}
+
+/// South-west and north-east corners of an area
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub south_west: Point,
+    pub north_east: Point,
+}
+
+impl Point {
+    pub fn new(lat: f64, lng: f64) -> Result<Self, ValidationError> {
+        let mut range_error = ValidationError::new("range");
+        if !(-90.0..=90.0).contains(&lat) {
+            range_error.add_param(Cow::from("lat"), &lat);
+        }
+        if !(-180.0..=180.0).contains(&lng) {
+            range_error.add_param(Cow::from("lng"), &lng);
+        }
+
+        match range_error.params.is_empty() {
+            true => Ok(Self { lat, lng }),
+            false => Err(range_error),
+        }
+    }
+
+    pub fn from_json(value: &Value) -> Result<Self, ValidationError> {
+        let coordinate = |name: &str| value.get(name).and_then(|v| v.as_f64());
+
+        match (coordinate("lat"), coordinate("lng")) {
+            (Some(lat), Some(lng)) => Self::new(lat, lng),
+            _ => {
+                let mut type_error = ValidationError::new("type");
+                type_error.add_param(Cow::from("expected"), &"{ lat: number, lng: number }");
+                Err(type_error)
+            }
+        }
+    }
+
+    /// Reads the `[lat, lng]` array returned by Postgres
+    pub fn from_column(value: &Value) -> Option<Self> {
+        let coordinates = value.as_array()?;
+
+        Some(Self {
+            lat: coordinates.first()?.as_f64()?,
+            lng: coordinates.get(1)?.as_f64()?,
+        })
+    }
+
+    /// Great-circle distance in meters between `column` and this point, using the haversine formula
+    pub fn distance_sql(&self, column: &str) -> String {
+        format!(
+            r#"({EARTH_RADIUS} * 2 * asin(sqrt(power(sin(radians("{column}"[1] - {lat}) / 2), 2) + cos(radians({lat})) * cos(radians("{column}"[1])) * power(sin(radians("{column}"[2] - {lng}) / 2), 2))))"#,
+            lat = self.lat,
+            lng = self.lng,
+        )
+    }
+}
+
+impl BoundingBox {
+    /// Boxes whose west edge is east of their east edge cross the antimeridian, so their
+    /// longitudes wrap around from 180 to -180
+    pub fn contains_sql(&self, column: &str) -> String {
+        let (west, east) = (self.south_west.lng, self.north_east.lng);
+        let lng = match west > east {
+            true => format!(r#"("{column}"[2] >= {west} OR "{column}"[2] <= {east})"#),
+            false => format!(r#""{column}"[2] BETWEEN {west} AND {east}"#),
+        };
+
+        format!(
+            r#"("{column}"[1] BETWEEN {} AND {} AND {lng})"#,
+            self.south_west.lat, self.north_east.lat
+        )
+    }
+}
+
+fn parse_numbers(value: &str, key: &str, counts: &[usize]) -> Result<Vec<f64>, Error> {
+    let numbers = value
+        .split(',')
+        .map(|n| n.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|n| counts.contains(&n.len()) && n.iter().all(|n| n.is_finite()));
+
+    numbers.ok_or_else(|| Error::BadRequest(format!("Invalid coordinates for '{key}'")))
+}
+
+fn parse_point(lat: f64, lng: f64, key: &str) -> Result<Point, Error> {
+    Point::new(lat, lng)
+        .map_err(|_| Error::BadRequest(format!("Coordinates out of range for '{key}'")))
+}
+
+/// Parses `lat,lng` or `lat,lng,meters` from a `near` filter
+pub fn parse_near(value: &str, key: &str) -> Result<(Point, Option<f64>), Error> {
+    let numbers = parse_numbers(value, key, &[2, 3])?;
+    let point = parse_point(numbers[0], numbers[1], key)?;
+
+    Ok((point, numbers.get(2).copied()))
+}
+
+/// Parses `south,west,north,east` from a `within` filter
+pub fn parse_within(value: &str, key: &str) -> Result<BoundingBox, Error> {
+    let numbers = parse_numbers(value, key, &[4])?;
+
+    Ok(BoundingBox {
+        south_west: parse_point(numbers[0], numbers[1], key)?,
+        north_east: parse_point(numbers[2], numbers[3], key)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn validates_points() {
+        assert_eq!(
+            Point::from_json(&json!({ "lat": 45.5, "lng": -73.6 })),
+            Ok(Point {
+                lat: 45.5,
+                lng: -73.6
+            })
+        );
+        assert!(Point::from_json(&json!({ "lat": 91, "lng": 0 })).is_err());
+        assert!(Point::from_json(&json!([45.5, -73.6])).is_err());
+    }
+
+    #[test]
+    fn parses_filters() {
+        assert_eq!(
+            parse_near("45.5,-73.6,500", "location").unwrap(),
+            (
+                Point {
+                    lat: 45.5,
+                    lng: -73.6
+                },
+                Some(500.0)
+            )
+        );
+        assert!(parse_near("45.5", "location").is_err());
+        assert!(parse_within("45,-74,46,-73", "location").is_ok());
+        assert!(parse_within("45,-74,46,-190", "location").is_err());
+    }
+
+    #[test]
+    fn boxes_crossing_the_antimeridian() {
+        let fiji = parse_within("-20,177,-15,-178", "location").unwrap();
+        assert_eq!(
+            fiji.contains_sql("location"),
+            r#"("location"[1] BETWEEN -20 AND -15 AND ("location"[2] >= 177 OR "location"[2] <= -178))"#
+        );
+
+        let montreal = parse_within("45,-74,46,-73", "location").unwrap();
+        assert_eq!(
+            montreal.contains_sql("location"),
+            r#"("location"[1] BETWEEN 45 AND 46 AND "location"[2] BETWEEN -74 AND -73)"#
+        );
+    }
+}
@@ -0,0 +1,492 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use heck::ToLowerCamelCase;
+use sea_query::{Alias, Expr, PostgresQueryBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::error;
+use validator::ValidationErrors;
+
+use crate::{error::Error, util};
+
+use super::{
+    fields::{FieldInfo, RelationTarget},
+    schema::CustomTableSchema,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookEvent {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hooks {
+    #[serde(default)]
+    pub before_create: Vec<BeforeHook>,
+    #[serde(default)]
+    pub after_create: Vec<AfterHook>,
+    #[serde(default)]
+    pub before_update: Vec<BeforeHook>,
+    #[serde(default)]
+    pub after_update: Vec<AfterHook>,
+    #[serde(default)]
+    pub before_delete: Vec<BeforeHook>,
+    #[serde(default)]
+    pub after_delete: Vec<AfterHook>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum BeforeHook {
+    /// Overwrites the field with the given value
+    Set { field: String, value: Value },
+    /// Sets the field to the given value when it wasn't provided
+    Default { field: String, value: Value },
+    /// Applies a string transformation to the field
+    Transform { field: String, transform: Transform },
+    /// Aborts the write when every condition matches
+    Reject {
+        when: Vec<Condition>,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum AfterHook {
+    /// Adds `by` to a numeric field on the row referenced by a single relation
+    Increment {
+        relation: String,
+        field: String,
+        by: i64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Transform {
+    Trim,
+    Lowercase,
+    Uppercase,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    pub field: String,
+    pub operator: Operator,
+    #[serde(default)]
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    GreaterThan,
+    LessThan,
+    Empty,
+    NotEmpty,
+}
+
+pub fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64()?.partial_cmp(&right.as_f64()?),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        (Value::Bool(left), Value::Bool(right)) => Some(left.cmp(right)),
+        _ => None,
+    }
+}
+
+pub fn is_empty(value: Option<&Value>) -> bool {
+    match value {
+        None | Some(Value::Null) => true,
+        Some(Value::String(value)) => value.is_empty(),
+        Some(Value::Array(value)) => value.is_empty(),
+        _ => false,
+    }
+}
+
+impl Operator {
+    pub fn matches(&self, left: Option<&Value>, right: &Value) -> bool {
+        match self {
+            Self::Empty => is_empty(left),
+            Self::NotEmpty => !is_empty(left),
+            Self::Equal => left.unwrap_or(&Value::Null) == right,
+            Self::NotEqual => left.unwrap_or(&Value::Null) != right,
+            Self::GreaterThan => left
+                .and_then(|left| compare(left, right))
+                .is_some_and(|o| o == Ordering::Greater),
+            Self::LessThan => left
+                .and_then(|left| compare(left, right))
+                .is_some_and(|o| o == Ordering::Less),
+        }
+    }
+}
+
+impl Condition {
+    pub(super) fn matches(&self, payload: &HashMap<String, Value>) -> bool {
+        self.operator
+            .matches(payload.get(&self.field.to_lower_camel_case()), &self.value)
+    }
+}
+
+impl Transform {
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Self::Trim => value.trim().to_string(),
+            Self::Lowercase => value.to_lowercase(),
+            Self::Uppercase => value.to_uppercase(),
+        }
+    }
+}
+
+impl Hooks {
+    pub fn before(&self, event: &HookEvent) -> &Vec<BeforeHook> {
+        match event {
+            HookEvent::Create => &self.before_create,
+            HookEvent::Update => &self.before_update,
+            HookEvent::Delete => &self.before_delete,
+        }
+    }
+
+    pub fn after(&self, event: &HookEvent) -> &Vec<AfterHook> {
+        match event {
+            HookEvent::Create => &self.after_create,
+            HookEvent::Update => &self.after_update,
+            HookEvent::Delete => &self.after_delete,
+        }
+    }
+
+    /// `tables` holds the project's other tables so increments can be checked
+    /// against the related table's fields
+    pub fn validate(
+        &self,
+        schema: &CustomTableSchema,
+        tables: &[CustomTableSchema],
+    ) -> Result<(), Error> {
+        let has_field = |name: &String| schema.fields.iter().any(|f| &f.name == name);
+
+        for event in [HookEvent::Create, HookEvent::Update, HookEvent::Delete] {
+            for hook in self.before(&event) {
+                let fields = match hook {
+                    // Nothing is written on delete, so changes to the row would be lost
+                    BeforeHook::Set { .. }
+                    | BeforeHook::Default { .. }
+                    | BeforeHook::Transform { .. }
+                        if event == HookEvent::Delete =>
+                    {
+                        return Err(Error::BadRequest(
+                            "Delete hooks can only reject the delete".into(),
+                        ));
+                    }
+                    BeforeHook::Set { field, .. }
+                    | BeforeHook::Default { field, .. }
+                    | BeforeHook::Transform { field, .. } => vec![field],
+                    BeforeHook::Reject { when, .. } => when.iter().map(|c| &c.field).collect(),
+                };
+
+                if let Some(field) = fields.into_iter().find(|f| !has_field(f)) {
+                    return Err(Error::BadRequest(format!(
+                        "Hook references unknown field '{field}'"
+                    )));
+                }
+            }
+
+            for hook in self.after(&event) {
+                let AfterHook::Increment {
+                    relation, field, ..
+                } = hook;
+
+                let Some(related) = schema.fields.iter().find_map(|f| match &f.info {
+                    FieldInfo::Relation {
+                        table,
+                        target: RelationTarget::Single,
+                        ..
+                    } if &f.name == relation => Some(table),
+                    _ => None,
+                }) else {
+                    return Err(Error::BadRequest(format!(
+                        "Hook field '{relation}' isn't a single relation"
+                    )));
+                };
+
+                let related_table = match related == &schema.name {
+                    true => Some(schema),
+                    false => tables.iter().find(|t| &t.name == related),
+                };
+                let is_number = related_table.is_some_and(|t| {
+                    t.fields
+                        .iter()
+                        .any(|f| &f.name == field && matches!(f.info, FieldInfo::Number { .. }))
+                });
+                if !is_number {
+                    return Err(Error::BadRequest(format!(
+                        "Hook field '{field}' isn't a number field of '{related}'"
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn run_before(
+        &self,
+        event: &HookEvent,
+        payload: &mut HashMap<String, Value>,
+    ) -> Result<(), Error> {
+        for hook in self.before(event) {
+            match hook {
+                BeforeHook::Set { field, value } => {
+                    payload.insert(field.to_lower_camel_case(), value.clone());
+                }
+                BeforeHook::Default { field, value } => {
+                    payload
+                        .entry(field.to_lower_camel_case())
+                        .or_insert(value.clone());
+                }
+                BeforeHook::Transform { field, transform } => {
+                    if let Some(Value::String(value)) =
+                        payload.get_mut(&field.to_lower_camel_case())
+                    {
+                        *value = transform.apply(value);
+                    }
+                }
+                BeforeHook::Reject { when, message } => {
+                    if !when.iter().all(|c| c.matches(payload)) {
+                        continue;
+                    }
+
+                    let mut errors = ValidationErrors::new();
+                    when.iter().for_each(|c| {
+                        errors.add(
+                            util::string_to_static_str(c.field.to_lower_camel_case()),
+                            util::create_validation_error("hook", Some(message.clone())),
+                        );
+                    });
+
+                    return Err(Error::ValidationErrors {
+                        message: message.clone(),
+                        errors,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs once the write is saved, so failures are logged instead of failing the request
+    pub async fn run_after(
+        &self,
+        event: &HookEvent,
+        schema: &CustomTableSchema,
+        row: &Value,
+        db: &deadpool_postgres::Pool,
+    ) {
+        for hook in self.after(event) {
+            let AfterHook::Increment {
+                relation,
+                field,
+                by,
+            } = hook;
+
+            let Some(FieldInfo::Relation { table, .. }) = schema
+                .fields
+                .iter()
+                .find(|f| &f.name == relation)
+                .map(|f| &f.info)
+            else {
+                continue;
+            };
+
+            let related_id = match row.get(relation.to_lower_camel_case()) {
+                Some(Value::String(id)) => id.clone(),
+                Some(Value::Object(related)) => match related.get("id") {
+                    Some(Value::String(id)) => id.clone(),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+
+            let query = sea_query::Query::update()
+                .table(Alias::new(table))
+                .value(
+                    Alias::new(field),
+                    Expr::col(Alias::new(field)).if_null(0).add(*by),
+                )
+                .and_where(Expr::col(Alias::new("id")).eq(related_id))
+                .to_string(PostgresQueryBuilder);
+
+            let result = match db.get().await {
+                Ok(conn) => conn.execute(&query, &[]).await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            if let Err(e) = result {
+                error!(error = %e, table = %schema.name, relation = %relation, "Failed to run after hook");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::entities::custom_table::fields::Field;
+
+    #[test]
+    fn before_hooks_modify_payload() {
+        let hooks = Hooks {
+            before_create: vec![
+                BeforeHook::Transform {
+                    field: "email".into(),
+                    transform: Transform::Lowercase,
+                },
+                BeforeHook::Default {
+                    field: "status".into(),
+                    value: json!("draft"),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let mut payload = HashMap::from([("email".to_string(), json!("Sam@Example.COM"))]);
+        let result = hooks.run_before(&HookEvent::Create, &mut payload);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(payload.get("email"), Some(&json!("sam@example.com")));
+        assert_eq!(payload.get("status"), Some(&json!("draft")));
+    }
+
+    #[test]
+    fn before_hooks_reject() {
+        let hooks = Hooks {
+            before_update: vec![BeforeHook::Reject {
+                when: vec![Condition {
+                    field: "total".into(),
+                    operator: Operator::LessThan,
+                    value: json!(0),
+                }],
+                message: "Total can't be negative".into(),
+            }],
+            ..Default::default()
+        };
+
+        let mut payload = HashMap::from([("total".to_string(), json!(-5))]);
+        assert!(hooks.run_before(&HookEvent::Update, &mut payload).is_err());
+
+        let mut payload = HashMap::from([("total".to_string(), json!(5))]);
+        assert_eq!(hooks.run_before(&HookEvent::Update, &mut payload), Ok(()));
+    }
+
+    #[test]
+    fn increment_requires_number_field() {
+        let table = |name: &str, fields: Vec<Field>| CustomTableSchema {
+            name: name.into(),
+            fields,
+            ..Default::default()
+        };
+        let posts = table(
+            "posts",
+            vec![
+                Field {
+                    name: "title".into(),
+                    info: FieldInfo::String {
+                        min_length: None,
+                        max_length: None,
+                        pattern: None,
+                        is_required: false,
+                        is_unique: false,
+                    },
+                },
+                Field {
+                    name: "comment_count".into(),
+                    info: FieldInfo::Number {
+                        min: None,
+                        max: None,
+                        is_required: false,
+                        is_unique: false,
+                    },
+                },
+            ],
+        );
+        let comments = table(
+            "comments",
+            vec![Field {
+                name: "post".into(),
+                info: FieldInfo::Relation {
+                    table: "posts".into(),
+                    target: RelationTarget::Single,
+                    min_selected: None,
+                    max_selected: None,
+                    cascade_delete: false,
+                    is_required: false,
+                    is_unique: false,
+                },
+            }],
+        );
+        let increment = |field: &str| Hooks {
+            after_create: vec![AfterHook::Increment {
+                relation: "post".into(),
+                field: field.into(),
+                by: 1,
+            }],
+            ..Default::default()
+        };
+
+        let tables = [posts];
+        assert_eq!(
+            increment("comment_count").validate(&comments, &tables),
+            Ok(())
+        );
+        assert!(increment("title").validate(&comments, &tables).is_err());
+        assert!(increment("\"id\" = 0; --")
+            .validate(&comments, &tables)
+            .is_err());
+    }
+
+    #[test]
+    fn delete_hooks_only_reject() {
+        let hooks = |hook: BeforeHook| Hooks {
+            before_delete: vec![hook],
+            ..Default::default()
+        };
+        let schema = CustomTableSchema {
+            name: "posts".into(),
+            fields: vec![Field {
+                name: "status".into(),
+                info: FieldInfo::String {
+                    min_length: None,
+                    max_length: None,
+                    pattern: None,
+                    is_required: false,
+                    is_unique: false,
+                },
+            }],
+            ..Default::default()
+        };
+
+        let reject = hooks(BeforeHook::Reject {
+            when: vec![Condition {
+                field: "status".into(),
+                operator: Operator::Equal,
+                value: json!("published"),
+            }],
+            message: "Published posts can't be deleted".into(),
+        });
+        assert_eq!(reject.validate(&schema, &[]), Ok(()));
+
+        let set = hooks(BeforeHook::Set {
+            field: "status".into(),
+            value: json!("deleted"),
+        });
+        assert!(set.validate(&schema, &[]).is_err());
+    }
+}
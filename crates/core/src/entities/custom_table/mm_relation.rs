@@ -1,6 +1,6 @@
 use sea_query::{
-    Alias, ColumnDef, ForeignKey, ForeignKeyAction, InsertStatement, Query, Table,
-    TableCreateStatement,
+    Alias, ColumnDef, DeleteStatement, Expr, ForeignKey, ForeignKeyAction, InsertStatement, Query,
+    Table, TableCreateStatement,
 };
 
 use crate::id::Id;
@@ -115,4 +115,26 @@ impl ManyToManyRelationTable {
 
         vec![]
     }
+
+    /// Unlinks `relations` from the row, or every relation of the row when `None`
+    pub fn delete_query(
+        schema: &CustomTableSchema,
+        f: &Field,
+        row_id: String,
+        relations: Option<Vec<String>>,
+    ) -> DeleteStatement {
+        let FieldInfo::Relation { table, .. } = &f.info else {
+            panic!("Field is not a relation");
+        };
+
+        let mut query = Query::delete();
+        query
+            .from_table(Alias::new(Self::table_name(schema, f)))
+            .and_where(Expr::col(Alias::new(format!("{}_id", schema.name))).eq(row_id));
+        if let Some(relations) = relations {
+            query.and_where(Expr::col(Alias::new(format!("{}_id", table))).is_in(relations));
+        }
+
+        query
+    }
 }
@@ -2,23 +2,36 @@
 
 use chrono::{DateTime, Utc};
 use sea_query::{
-    Alias, ColumnDef, Cond, Expr, ForeignKey, ForeignKeyAction, Keyword, PostgresQueryBuilder,
-    SimpleExpr, Table, TableCreateStatement,
+    Alias, ColumnDef, Cond, Expr, ForeignKey, ForeignKeyAction, Iden, Keyword, Order,
+    OrderedStatement, PostgresQueryBuilder, SimpleExpr, Table, TableCreateStatement,
 };
 use serde_json::{json, Map};
 
-use crate::error::Error;
+use crate::{
+    entities::{AnyUser, User},
+    error::Error,
+};
 
 use self::{
+    computed::ComputedType,
     fields::{FieldInfo, RelationTarget},
+    geo::{BoundingBox, Point},
     mm_relation::ManyToManyRelationTable,
+    permissions::Permission,
+    reverse::{CountOperator, ReverseRelation},
     schema::CustomTableSchema,
 };
 
+pub mod computed;
 pub mod fields;
+pub mod geo;
+pub mod hooks;
 pub mod mm_relation;
 pub mod permissions;
+pub mod reverse;
+pub mod rules;
 pub mod schema;
+pub mod select;
 
 #[derive(Clone, Debug)]
 enum ColType {
@@ -27,6 +40,7 @@ enum ColType {
     Boolean,
     Date,
     Array(Box<ColType>),
+    GeoPoint,
     Relation(String),
 }
 
@@ -66,16 +80,125 @@ impl ColType {
                 }
                 _ => todo!(),
             },
+            ColType::GeoPoint => {
+                let point = column.get(name).and_then(Point::from_column);
+
+                json!({ camel_case_name: point })
+            }
             ColType::Relation(name) => {
-                json!({ camel_case_name: column.get(name).unwrap() })
+                let mut related = column.get(name).unwrap().clone();
+                match &mut related {
+                    serde_json::Value::Array(rows) => rows
+                        .iter_mut()
+                        .filter_map(|row| row.as_object_mut())
+                        .for_each(strip_hidden),
+                    serde_json::Value::Object(row) => strip_hidden(row),
+                    _ => {}
+                }
+
+                json!({ camel_case_name: related })
             }
         }
     }
 }
 
+/// Removes the fields a joined row marks as hidden, along with the markers
+fn strip_hidden(row: &mut Map<String, serde_json::Value>) {
+    let markers = row
+        .keys()
+        .filter(|key| key.ends_with("_hidden_key"))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for marker in markers {
+        if row
+            .remove(&marker)
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true)
+        {
+            row.remove(marker.trim_end_matches("_hidden_key"));
+        }
+    }
+}
+
+/// Rows of a reverse relation nested under each row
+struct Expansion {
+    name: String,
+    schema: CustomTableSchema,
+    hidden: Vec<String>,
+}
+
+/// The columns read from each row of `schema`. Nested rows aren't joined, so their single
+/// relations hold plain ids.
+fn row_columns(schema: &CustomTableSchema, is_joined: bool) -> Vec<(&str, ColType)> {
+    let mut columns = vec![
+        ("id", ColType::String),
+        ("created_at", ColType::Date),
+        ("updated_at", ColType::Date),
+    ];
+
+    schema.fields.iter().for_each(|f| match f.info {
+        FieldInfo::String { .. } => columns.push((&f.name, ColType::String)),
+        FieldInfo::Number { .. } => columns.push((&f.name, ColType::Number)),
+        FieldInfo::Boolean => columns.push((&f.name, ColType::Boolean)),
+        FieldInfo::Date { .. } => columns.push((&f.name, ColType::Date)),
+        FieldInfo::DateOnly { .. } => columns.push((&f.name, ColType::String)),
+        FieldInfo::Time { .. } => columns.push((&f.name, ColType::String)),
+        FieldInfo::Duration { .. } => columns.push((&f.name, ColType::Number)),
+        FieldInfo::Email { .. } => columns.push((&f.name, ColType::String)),
+        FieldInfo::Url { .. } => columns.push((&f.name, ColType::String)),
+        FieldInfo::Select { single: true, .. } => columns.push((&f.name, ColType::String)),
+        FieldInfo::Select { .. } => {
+            columns.push((&f.name, ColType::Array(Box::new(ColType::String))))
+        }
+        FieldInfo::Relation { .. } if is_joined => columns.push((
+            &f.name,
+            ColType::Relation(format!("{}_relation_key", f.name)),
+        )),
+        FieldInfo::Relation {
+            target: RelationTarget::Single,
+            ..
+        } => columns.push((&f.name, ColType::String)),
+        FieldInfo::Relation { .. } => {}
+        FieldInfo::GeoPoint { .. } => columns.push((&f.name, ColType::GeoPoint)),
+        FieldInfo::Computed { ref returns, .. } => match returns {
+            ComputedType::String => columns.push((&f.name, ColType::String)),
+            ComputedType::Number => columns.push((&f.name, ColType::Number)),
+        },
+    });
+
+    columns
+}
+
+/// Converts a row to camelCase JSON, leaving out the fields hidden on it
+fn to_row(
+    row: &Map<String, serde_json::Value>,
+    columns: &[(&str, ColType)],
+    hidden: &[String],
+) -> serde_json::Value {
+    let mut data = json!({});
+
+    columns.iter().for_each(|(name, col_type)| {
+        let is_hidden = hidden.iter().any(|h| h == name)
+            && row
+                .get(&format!("{name}_hidden_key"))
+                .and_then(|v| v.as_bool())
+                .unwrap_or(true);
+        if is_hidden {
+            return;
+        }
+
+        json_patch::merge(&mut data, &col_type.to_json(row, name));
+    });
+
+    data
+}
+
 pub struct CustomTableSelectBuilder {
     is_count: bool,
     schema: CustomTableSchema,
+    expanded: Vec<Expansion>,
+    hidden: Vec<String>,
     query_builder: sea_query::SelectStatement,
 }
 
@@ -85,11 +208,14 @@ impl CustomTableSelectBuilder {
             is_count: true,
             query_builder: self.query_builder.clone(),
             schema: self.schema.clone(),
+            expanded: vec![],
+            hidden: vec![],
         };
 
         builder.query_builder.reset_limit();
         builder.query_builder.reset_offset();
         builder.query_builder.clear_selects();
+        builder.query_builder.clear_order_by();
 
         builder
             .query_builder
@@ -120,16 +246,23 @@ impl CustomTableSelectBuilder {
             && let Some(limit) = limit
         {
             self.query_builder.limit(limit);
-            self.query_builder.offset((page - 1) * limit);
+            self.query_builder
+                .offset(page.saturating_sub(1).saturating_mul(limit));
         }
 
         self
     }
 
-    pub fn join(&mut self) -> &mut Self {
-        self.schema.fields.iter().for_each(|f| {
+    /// Joins the rows each relation points at. When `user` is set, related rows of the tables in
+    /// `related` are limited to the rows and fields they can view.
+    pub fn join(
+        &mut self,
+        related: &[CustomTableSchema],
+        user: Option<&AnyUser>,
+    ) -> Result<&mut Self, Error> {
+        for f in &self.schema.fields {
             let FieldInfo::Relation { table, target, .. } = &f.info else {
-                return;
+                continue;
             };
 
             let json_func = if target == &RelationTarget::Single {
@@ -149,18 +282,149 @@ impl CustomTableSelectBuilder {
                 ),
             };
 
+            let mut query = sea_query::Query::select();
+            query
+                .from(Alias::new(table))
+                .and_where(Expr::cust(format!("id {where_clause}")));
+
+            // Only the public columns of users are ever exposed
+            if *table == User::table().to_string() {
+                query.columns(User::PUBLIC_COLUMNS.map(Alias::new));
+            } else {
+                query.column(sea_query::Asterisk);
+            }
+
+            if let Some(user) = user
+                && let Some(schema) = related.iter().find(|t| &t.name == table)
+            {
+                if let Some(rule) = schema.permissions.view.clone() {
+                    query.cond_where(Permission::parse(schema, rule)?.to_sql_cond(user));
+                }
+                for (name, condition) in schema.permissions.field_view_conds(schema, user)? {
+                    query.expr_as(
+                        Expr::case(condition, false).finally(true),
+                        Alias::new(format!("{name}_hidden_key")),
+                    );
+                }
+            }
+
             self.query_builder.expr(Expr::cust(
                 format!(
-                    "(SELECT {}({table}) FROM (SELECT * FROM {table} WHERE id {}) {table}) as {}",
+                    "(SELECT {}({table}) FROM ({}) {table}) as {}",
                     json_func,
-                    where_clause,
+                    query.to_string(PostgresQueryBuilder),
                     format_args!("{}_relation_key", f.name),
                     table = table
                 )
                 .as_str(),
             ));
+        }
+
+        Ok(self)
+    }
+
+    /// Nests the rows of `relation` under each row. When `user` is set, only the rows and
+    /// fields they can view are included.
+    pub fn expand(
+        &mut self,
+        relation: &ReverseRelation,
+        limit: Option<u64>,
+        sort: Option<(String, Order)>,
+        user: Option<&AnyUser>,
+    ) -> Result<&mut Self, Error> {
+        let expansion = relation.expand(&self.schema, limit, sort, user)?;
+
+        self.query_builder.expr(Expr::cust(format!(
+            "(SELECT COALESCE(json_agg(reverse), '[]'::json) FROM ({}) reverse) as {}_reverse_key",
+            expansion.query_builder.to_string(PostgresQueryBuilder),
+            relation.name
+        )));
+        self.expanded.push(Expansion {
+            name: relation.name.clone(),
+            schema: expansion.schema,
+            hidden: expansion.hidden,
         });
 
+        Ok(self)
+    }
+
+    /// Keeps rows whose number of `relation` rows visible to `user` matches the count
+    pub fn count_where(
+        &mut self,
+        relation: &ReverseRelation,
+        operator: CountOperator,
+        count: i64,
+        user: Option<&AnyUser>,
+    ) -> Result<&mut Self, Error> {
+        let query = relation
+            .count(&self.schema, user)?
+            .query_builder
+            .to_string(PostgresQueryBuilder);
+
+        self.query_builder.and_where(Expr::cust(format!(
+            "({query}) {} {count}",
+            operator.as_sql()
+        )));
+
+        Ok(self)
+    }
+
+    /// Keeps the rows `user` can view and strips the fields they can't. Rows where a field in
+    /// `probed` is hidden are left out so filtering or sorting on it can't reveal its values.
+    pub fn view_as(&mut self, user: &AnyUser, probed: &[&str]) -> Result<&mut Self, Error> {
+        let schema = self.schema.clone();
+
+        if let Some(rule) = schema.permissions.view.clone() {
+            self.query_builder
+                .cond_where(Permission::parse(&schema, rule)?.to_sql_cond(user));
+        }
+        for (name, condition) in schema.permissions.field_view_conds(&schema, user)? {
+            if probed.contains(&name.as_str()) {
+                self.query_builder.cond_where(condition.clone());
+            }
+            self.restrict(&name, condition);
+        }
+
+        Ok(self)
+    }
+
+    /// Strips `field` from the rows where `condition` doesn't hold
+    pub fn restrict(&mut self, field: &str, condition: Cond) -> &mut Self {
+        self.query_builder.expr_as(
+            Expr::case(condition, false).finally(true),
+            Alias::new(format!("{field}_hidden_key")),
+        );
+        self.hidden.push(field.to_string());
+
+        self
+    }
+
+    /// Keeps rows whose `field` lies within `meters` of `point`, optionally ordering them by distance
+    pub fn near(
+        &mut self,
+        field: &str,
+        point: Point,
+        meters: Option<f64>,
+        order: Option<Order>,
+    ) -> &mut Self {
+        let distance = point.distance_sql(field);
+
+        if let Some(meters) = meters {
+            self.query_builder
+                .and_where(Expr::cust(format!("{distance} <= {meters}")));
+        }
+        if let Some(order) = order {
+            self.query_builder
+                .order_by_expr(Expr::cust(distance), order);
+        }
+
+        self
+    }
+
+    pub fn within(&mut self, field: &str, bounding_box: BoundingBox) -> &mut Self {
+        self.query_builder
+            .and_where(Expr::cust(bounding_box.contains_sql(field)));
+
         self
     }
 
@@ -205,27 +469,7 @@ impl CustomTableSelectBuilder {
             return Ok(count.into());
         }
 
-        let mut columns = vec![
-            ("id", ColType::String),
-            ("created_at", ColType::Date),
-            ("updated_at", ColType::Date),
-        ];
-
-        self.schema.fields.iter().for_each(|f| match f.info {
-            FieldInfo::String { .. } => columns.push((&f.name, ColType::String)),
-            FieldInfo::Number { .. } => columns.push((&f.name, ColType::Number)),
-            FieldInfo::Boolean => columns.push((&f.name, ColType::Boolean)),
-            FieldInfo::Date { .. } => columns.push((&f.name, ColType::Date)),
-            FieldInfo::Email { .. } => columns.push((&f.name, ColType::String)),
-            FieldInfo::Url { .. } => columns.push((&f.name, ColType::String)),
-            FieldInfo::Select { .. } => {
-                columns.push((&f.name, ColType::Array(Box::new(ColType::String))))
-            }
-            FieldInfo::Relation { .. } => columns.push((
-                &f.name,
-                ColType::Relation(format!("{}_relation_key", f.name)),
-            )),
-        });
+        let columns = row_columns(&self.schema, true);
 
         let data = serde_json::from_value::<Vec<Map<String, serde_json::Value>>>(
             row.try_get("columns")
@@ -234,10 +478,21 @@ impl CustomTableSelectBuilder {
         .unwrap()
         .iter()
         .map(|col| {
-            let mut data = json!({});
-
-            columns.clone().into_iter().for_each(|(name, col_type)| {
-                json_patch::merge(&mut data, &col_type.to_json(col, name));
+            let mut data = to_row(col, &columns, &self.hidden);
+
+            self.expanded.iter().for_each(|expansion| {
+                let columns = row_columns(&expansion.schema, false);
+                let rows = col
+                    .get(&format!("{}_reverse_key", expansion.name))
+                    .and_then(|rows| rows.as_array())
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|row| row.as_object())
+                    .map(|row| to_row(row, &columns, &expansion.hidden))
+                    .collect::<Vec<_>>();
+
+                let name = heck::AsLowerCamelCase(&expansion.name).to_string();
+                json_patch::merge(&mut data, &json!({ name: rows }));
             });
 
             data
@@ -261,6 +516,9 @@ impl From<&CustomTableSchema> for CustomTableSelectBuilder {
             FieldInfo::Number { .. } => columns.push(Alias::new(&field.name)),
             FieldInfo::Boolean => columns.push(Alias::new(&field.name)),
             FieldInfo::Date { .. } => columns.push(Alias::new(&field.name)),
+            FieldInfo::DateOnly { .. } => columns.push(Alias::new(&field.name)),
+            FieldInfo::Time { .. } => columns.push(Alias::new(&field.name)),
+            FieldInfo::Duration { .. } => columns.push(Alias::new(&field.name)),
             FieldInfo::Email { .. } => columns.push(Alias::new(&field.name)),
             FieldInfo::Url { .. } => columns.push(Alias::new(&field.name)),
             FieldInfo::Select { .. } => columns.push(Alias::new(&field.name)),
@@ -269,11 +527,15 @@ impl From<&CustomTableSchema> for CustomTableSelectBuilder {
                     columns.push(Alias::new(&field.name));
                 }
             }
+            FieldInfo::GeoPoint { .. } => columns.push(Alias::new(&field.name)),
+            FieldInfo::Computed { .. } => columns.push(Alias::new(&field.name)),
         });
 
         CustomTableSelectBuilder {
             is_count: false,
             schema: schema.clone(),
+            expanded: vec![],
+            hidden: vec![],
             query_builder: sea_query::Query::select()
                 .from(Alias::new(&schema.name))
                 .columns(columns)
@@ -342,3 +604,28 @@ impl From<&CustomTableSchema> for TableCreateStatement {
         builder.to_owned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_hidden_joined_fields() {
+        let mut row = json!({
+            "id": "1",
+            "email": "sam@example.com",
+            "email_hidden_key": true,
+            "phone": "555",
+            "phone_hidden_key": false,
+        })
+        .as_object()
+        .cloned()
+        .unwrap();
+        strip_hidden(&mut row);
+
+        assert_eq!(
+            serde_json::Value::Object(row),
+            json!({ "id": "1", "phone": "555" })
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fmt, hash::Hash};
+use std::{collections::HashMap, fmt, hash::Hash};
 
 use regex::Regex;
 use sea_query::{all, any, Alias, Cond, Expr, SimpleExpr};
@@ -6,7 +6,11 @@ use serde::{Deserialize, Serialize};
 
 use crate::{entities::AnyUser, error::Error};
 
-use super::schema::CustomTableSchema;
+use super::{
+    fields::{FieldInfo, RelationTarget},
+    mm_relation::ManyToManyRelationTable,
+    schema::CustomTableSchema,
+};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Permissions {
@@ -14,6 +18,51 @@ pub struct Permissions {
     pub create: Option<String>,
     pub update: Option<String>,
     pub delete: Option<String>,
+    #[serde(default)]
+    pub fields: HashMap<String, FieldPermissions>,
+}
+
+/// Rules restricting who can see or change a single field of a visible row
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FieldPermissions {
+    pub view: Option<String>,
+    pub update: Option<String>,
+}
+
+impl Permissions {
+    pub fn validate(&self, schema: &CustomTableSchema) -> Result<(), Error> {
+        for (name, rules) in &self.fields {
+            if !schema.fields.iter().any(|f| &f.name == name) {
+                return Err(Error::BadRequest(format!(
+                    "Permission references unknown field '{name}'"
+                )));
+            }
+
+            for rule in [&rules.view, &rules.update].into_iter().flatten() {
+                Permission::parse(schema, rule.clone())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The condition under which each field with a view rule is visible to `user`
+    pub fn field_view_conds(
+        &self,
+        schema: &CustomTableSchema,
+        user: &AnyUser,
+    ) -> Result<Vec<(String, Cond)>, Error> {
+        self.fields
+            .iter()
+            .filter_map(|(name, rules)| Some((name, rules.view.clone()?)))
+            .map(|(name, rule)| {
+                Ok((
+                    name.clone(),
+                    Permission::parse(schema, rule)?.to_sql_cond(user),
+                ))
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Hash, PartialEq)]
@@ -44,12 +93,20 @@ enum ExpressionOperator {
 enum ClauseOperator {
     Equal,
     NotEqual,
+    /// Whether a role is one of `@request.user.roles`
+    In,
 }
 
 #[derive(Debug, Clone, Hash, PartialEq)]
 enum Symbol {
     Builtin(BuiltinSymbol),
     Database(String),
+    /// A many relation, matched through its join table
+    Relation {
+        table: String,
+        column: String,
+        foreign_column: String,
+    },
     Value(Value),
 }
 
@@ -62,6 +119,7 @@ enum Value {
 #[derive(Debug, Clone, Hash, PartialEq)]
 enum BuiltinSymbol {
     RequestUser,
+    RequestUserRoles,
 }
 
 #[derive(Debug, Clone)]
@@ -91,6 +149,7 @@ impl fmt::Display for ClauseOperator {
             match self {
                 Self::Equal => "==",
                 Self::NotEqual => "!=",
+                Self::In => "in",
             }
         )
     }
@@ -115,6 +174,7 @@ impl TryFrom<String> for ClauseOperator {
         match value {
             _ if Self::Equal.to_string() == value => Ok(Self::Equal),
             _ if Self::NotEqual.to_string() == value => Ok(Self::NotEqual),
+            _ if Self::In.to_string() == value => Ok(Self::In),
             _ => Err(Error::BadRequest("Invalid expression was provided".into())),
         }
     }
@@ -126,6 +186,7 @@ impl Symbols for ExpressionOperator {
     }
 }
 
+/// `in` isn't included since field names can contain it, [`Clause::parse`] looks for it separately
 impl Symbols for ClauseOperator {
     fn symbols() -> Vec<String> {
         vec![Self::Equal.to_string(), Self::NotEqual.to_string()]
@@ -211,17 +272,48 @@ impl Permission {
 impl Clause {
     fn parse(schema: &CustomTableSchema, value: String) -> Result<Self, Error> {
         let regex = Regex::new(&ClauseOperator::regex_symbols().join("|")).unwrap();
-        let mat = regex
-            .find(&value)
-            .ok_or(Error::BadRequest("Invalid expression".into()))?;
-
-        Ok(Clause {
-            operator: ClauseOperator::try_from(mat.as_str().to_string())?,
-            operands: [
-                Symbol::parse(schema, value[0..mat.start()].to_string())?,
-                Symbol::parse(schema, value[mat.end()..value.len()].to_string())?,
-            ],
-        })
+        let clause = match regex.find(&value) {
+            Some(mat) => Clause {
+                operator: ClauseOperator::try_from(mat.as_str().to_string())?,
+                operands: [
+                    Symbol::parse(schema, value[0..mat.start()].to_string())?,
+                    Symbol::parse(schema, value[mat.end()..value.len()].to_string())?,
+                ],
+            },
+            // Spaces are already stripped, so `in` is only an operator right before a builtin
+            None => {
+                let start = value
+                    .find("in@")
+                    .ok_or(Error::BadRequest("Invalid expression".into()))?;
+
+                Clause {
+                    operator: ClauseOperator::In,
+                    operands: [
+                        Symbol::parse(schema, value[0..start].to_string())?,
+                        Symbol::parse(schema, value[start + 2..value.len()].to_string())?,
+                    ],
+                }
+            }
+        };
+
+        let roles = Symbol::Builtin(BuiltinSymbol::RequestUserRoles);
+        let valid = match clause.operator {
+            ClauseOperator::In => {
+                clause.operands[1] == roles
+                    && matches!(
+                        clause.operands[0],
+                        Symbol::Database(_) | Symbol::Value(Value::String(_))
+                    )
+            }
+            _ => !clause.operands.contains(&roles),
+        };
+        if !valid {
+            return Err(Error::BadRequest(
+                "@request.user.roles can only be used as `'role' in @request.user.roles`".into(),
+            ));
+        }
+
+        Ok(clause)
     }
 }
 
@@ -231,7 +323,18 @@ impl Symbol {
             Symbol::Builtin(BuiltinSymbol::try_from(value.replace('@', ""))?)
         } else {
             match schema.fields.iter().find(|f| f.name == value) {
-                Some(_) => Symbol::Database(value),
+                Some(field) => match &field.info {
+                    FieldInfo::Relation {
+                        table,
+                        target: RelationTarget::Many,
+                        ..
+                    } => Symbol::Relation {
+                        table: ManyToManyRelationTable::table_name(schema, field),
+                        column: format!("{}_id", schema.name),
+                        foreign_column: format!("{}_id", table),
+                    },
+                    _ => Symbol::Database(value),
+                },
                 None => {
                     let string_regex = Regex::new(r"'.+'").unwrap();
                     if string_regex.find(&value).is_some() {
@@ -258,6 +361,7 @@ impl TryFrom<String> for BuiltinSymbol {
     fn try_from(value: String) -> Result<Self, Self::Error> {
         match value.as_str() {
             "request.user" => Ok(BuiltinSymbol::RequestUser),
+            "request.user.roles" => Ok(BuiltinSymbol::RequestUserRoles),
             _ => Err(Error::BadRequest(
                 "Invalid builtin symbol was provided".into(),
             )),
@@ -282,23 +386,31 @@ impl Permission {
                     ]
                 }
             },
+            Self::Clause(clause) if clause.operator == ClauseOperator::In => {
+                match &clause.operands[0] {
+                    Symbol::Database(column) => {
+                        all![Expr::col(Alias::new(column)).is_in(user.roles.clone())]
+                    }
+                    Symbol::Value(Value::String(role)) => {
+                        all![Expr::value(user.roles.contains(role))]
+                    }
+                    _ => unreachable!(),
+                }
+            }
             Self::Clause(clause) => {
-                let database_alias = clause
+                let database_symbol = clause
                     .operands
                     .iter()
-                    .filter_map(|o| match o {
-                        Symbol::Database(alias) => Some(alias),
-                        _ => None,
-                    })
-                    .next()
+                    .find(|o| matches!(o, Symbol::Database(_) | Symbol::Relation { .. }))
                     .unwrap();
                 let other = clause
                     .operands
                     .iter()
                     .filter_map(|o| match o {
-                        Symbol::Database(_) => None,
+                        Symbol::Database(_) | Symbol::Relation { .. } => None,
                         Symbol::Builtin(builtin) => match builtin {
                             BuiltinSymbol::RequestUser => Some(SimpleExpr::from(user.id.clone())),
+                            BuiltinSymbol::RequestUserRoles => unreachable!(),
                         },
                         Symbol::Value(value) => match value {
                             Value::String(value) => Some(SimpleExpr::from(value)),
@@ -308,10 +420,35 @@ impl Permission {
                     .next()
                     .unwrap();
 
+                if let Symbol::Relation {
+                    table,
+                    column,
+                    foreign_column,
+                } = database_symbol
+                {
+                    let operator = match clause.operator {
+                        ClauseOperator::Equal => "IN",
+                        ClauseOperator::NotEqual => "NOT IN",
+                        ClauseOperator::In => unreachable!(),
+                    };
+
+                    return all![Expr::cust_with_exprs(
+                        format!(
+                            "id {operator} (SELECT {column} FROM {table} WHERE {foreign_column} = $1)"
+                        ),
+                        [other]
+                    )];
+                }
+
+                let Symbol::Database(database_alias) = database_symbol else {
+                    unreachable!();
+                };
+
                 let expr = Expr::col(Alias::new(database_alias));
                 all![match clause.operator {
                     ClauseOperator::Equal => expr.eq(other),
                     ClauseOperator::NotEqual => expr.ne(other),
+                    ClauseOperator::In => unreachable!(),
                 }]
             }
         }
@@ -407,6 +544,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn validates_field_permissions() {
+        let schema = CustomTableSchema {
+            fields: vec![
+                Field {
+                    name: "user_id".into(),
+                    info: FieldInfo::Boolean,
+                },
+                Field {
+                    name: "salary".into(),
+                    info: FieldInfo::Boolean,
+                },
+            ],
+            ..Default::default()
+        };
+        let permissions = |name: &str| Permissions {
+            fields: HashMap::from([(
+                name.to_string(),
+                FieldPermissions {
+                    view: Some("@request.user == user_id".into()),
+                    update: None,
+                },
+            )]),
+            ..Default::default()
+        };
+
+        assert!(permissions("salary").validate(&schema).is_ok());
+        assert!(permissions("bonus").validate(&schema).is_err());
+    }
+
     #[test]
     fn simple_cond() {
         let schema = CustomTableSchema {
@@ -471,4 +638,61 @@ mod tests {
             ])
         )
     }
+
+    #[test]
+    fn roles_parse() {
+        let schema = CustomTableSchema {
+            fields: vec![Field {
+                name: "min_role".into(),
+                info: FieldInfo::Boolean,
+            }],
+            ..Default::default()
+        };
+
+        let result = Permission::parse(&schema, "'editor' in @request.user.roles".to_string());
+        assert_eq!(
+            result,
+            Ok(Permission::Clause(Clause {
+                operator: ClauseOperator::In,
+                operands: [
+                    Symbol::Value(Value::String("editor".into())),
+                    Symbol::Builtin(BuiltinSymbol::RequestUserRoles)
+                ]
+            }))
+        );
+
+        assert!(Permission::parse(&schema, "min_role in @request.user.roles".to_string()).is_ok());
+        assert!(Permission::parse(&schema, "@request.user.roles == 'editor'".to_string()).is_err());
+        assert!(Permission::parse(&schema, "'editor' in @request.user".to_string()).is_err());
+    }
+
+    #[test]
+    fn roles_cond() {
+        let schema = CustomTableSchema {
+            fields: vec![Field {
+                name: "user_id".into(),
+                info: FieldInfo::Boolean,
+            }],
+            ..Default::default()
+        };
+        let permission = Permission::parse(
+            &schema,
+            "'admin' in @request.user.roles || @request.user == user_id".to_string(),
+        );
+
+        let user = AnyUser {
+            id: "test_user".into(),
+            roles: vec!["editor".into()],
+            ..Default::default()
+        };
+
+        let result = permission.map(|p| p.to_sql_cond(&user));
+        assert_eq!(
+            result,
+            Ok(any![
+                Expr::value(false),
+                Expr::col(Alias::new("user_id")).eq(user.id)
+            ])
+        )
+    }
 }
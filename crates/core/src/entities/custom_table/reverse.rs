@@ -0,0 +1,285 @@
+use sea_query::{Alias, Expr, Order};
+
+use crate::{entities::AnyUser, error::Error};
+
+use super::{
+    fields::{Field, FieldInfo, RelationTarget},
+    mm_relation::ManyToManyRelationTable,
+    schema::CustomTableSchema,
+    CustomTableSelectBuilder,
+};
+
+/// A relation field on another table that points back at this one
+#[derive(Debug, Clone)]
+pub struct ReverseRelation {
+    pub name: String,
+    pub table: CustomTableSchema,
+    pub field: Field,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CountOperator {
+    Equal,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+}
+
+impl CountOperator {
+    /// Splits values like `>5` or `<=2` into an operator and a count
+    pub fn parse(value: &str) -> Result<(Self, i64), Error> {
+        let (operator, count) = [
+            (">=", Self::GreaterThanOrEqual),
+            ("<=", Self::LessThanOrEqual),
+            (">", Self::GreaterThan),
+            ("<", Self::LessThan),
+            ("=", Self::Equal),
+        ]
+        .into_iter()
+        .find_map(|(prefix, operator)| value.strip_prefix(prefix).map(|v| (operator, v)))
+        .unwrap_or((Self::Equal, value));
+
+        let count = count
+            .trim()
+            .parse()
+            .map_err(|_| Error::BadRequest(format!("Invalid count filter '{value}'")))?;
+
+        Ok((operator, count))
+    }
+
+    pub(super) fn as_sql(&self) -> &'static str {
+        match self {
+            Self::Equal => "=",
+            Self::GreaterThan => ">",
+            Self::GreaterThanOrEqual => ">=",
+            Self::LessThan => "<",
+            Self::LessThanOrEqual => "<=",
+        }
+    }
+}
+
+impl ReverseRelation {
+    /// Derives the back-references of `schema` from every table pointing at it. They're
+    /// named after the owning table, or `{table}_{field}` when it has several such fields.
+    pub fn all(schema: &CustomTableSchema, tables: &[CustomTableSchema]) -> Vec<Self> {
+        tables
+            .iter()
+            .flat_map(|table| {
+                let fields = table
+                    .fields
+                    .iter()
+                    .filter(|f| {
+                        matches!(&f.info, FieldInfo::Relation { table, .. } if table == &schema.name)
+                    })
+                    .collect::<Vec<_>>();
+
+                let is_ambiguous = fields.len() > 1;
+                fields.into_iter().map(move |field| Self {
+                    name: if is_ambiguous {
+                        format!("{}_{}", table.name, field.name)
+                    } else {
+                        table.name.clone()
+                    },
+                    table: table.clone(),
+                    field: field.clone(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn find<'a>(relations: &'a [Self], name: &str) -> Result<&'a Self, Error> {
+        relations
+            .iter()
+            .find(|r| r.name == name)
+            .ok_or_else(|| Error::BadRequest(format!("Unknown reverse relation '{name}'")))
+    }
+
+    fn condition(&self, schema: &CustomTableSchema) -> String {
+        let FieldInfo::Relation { target, .. } = &self.field.info else {
+            unreachable!("Reverse relations are only built from relation fields");
+        };
+
+        match target {
+            RelationTarget::Single => format!("reverse.{} = {}.id", self.field.name, schema.name),
+            RelationTarget::Many => format!(
+                "reverse.id IN (SELECT {}_id FROM {} WHERE {}_id = {}.id)",
+                self.table.name,
+                ManyToManyRelationTable::table_name(&self.table, &self.field),
+                schema.name,
+                schema.name,
+            ),
+        }
+    }
+
+    fn has_column(&self, column: &str) -> bool {
+        ["id", "created_at", "updated_at"].contains(&column)
+            || self.table.fields.iter().any(|f| {
+                f.name == column
+                    && !matches!(
+                        f.info,
+                        FieldInfo::Relation {
+                            target: RelationTarget::Many,
+                            ..
+                        }
+                    )
+            })
+    }
+
+    /// The rows of the owning table pointing at a row of `schema`, limited to what `user` can
+    /// view when set
+    fn select(
+        &self,
+        schema: &CustomTableSchema,
+        user: Option<&AnyUser>,
+        probed: &[&str],
+    ) -> Result<CustomTableSelectBuilder, Error> {
+        let mut builder = CustomTableSelectBuilder::from(&self.table);
+        builder
+            .query_builder
+            .reset_limit()
+            .from_clear()
+            .from_as(Alias::new(&self.table.name), Alias::new("reverse"))
+            .and_where(Expr::cust(self.condition(schema)));
+
+        if let Some(user) = user {
+            builder.view_as(user, probed)?;
+        }
+
+        Ok(builder)
+    }
+
+    pub fn expand(
+        &self,
+        schema: &CustomTableSchema,
+        limit: Option<u64>,
+        sort: Option<(String, Order)>,
+        user: Option<&AnyUser>,
+    ) -> Result<CustomTableSelectBuilder, Error> {
+        if let Some((column, _)) = &sort
+            && !self.has_column(column)
+        {
+            return Err(Error::BadRequest(format!(
+                "Can't sort '{}' by unknown field '{column}'",
+                self.name
+            )));
+        }
+
+        let probed = sort
+            .iter()
+            .map(|(column, _)| column.as_str())
+            .collect::<Vec<_>>();
+        let mut builder = self.select(schema, user, &probed)?;
+        if let Some((column, order)) = sort {
+            builder.query_builder.order_by(Alias::new(column), order);
+        }
+        if let Some(limit) = limit {
+            builder.query_builder.limit(limit);
+        }
+
+        Ok(builder)
+    }
+
+    pub fn count(
+        &self,
+        schema: &CustomTableSchema,
+        user: Option<&AnyUser>,
+    ) -> Result<CustomTableSelectBuilder, Error> {
+        Ok(self.select(schema, user, &[])?.count())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use sea_query::PostgresQueryBuilder;
+
+    use super::*;
+    use crate::entities::custom_table::permissions::{FieldPermissions, Permissions};
+
+    #[test]
+    fn parses_count_filters() {
+        assert_eq!(
+            CountOperator::parse(">5"),
+            Ok((CountOperator::GreaterThan, 5))
+        );
+        assert_eq!(
+            CountOperator::parse("<=2"),
+            Ok((CountOperator::LessThanOrEqual, 2))
+        );
+        assert_eq!(CountOperator::parse("3"), Ok((CountOperator::Equal, 3)));
+        assert!(CountOperator::parse(">five").is_err());
+    }
+
+    #[test]
+    fn expansions_follow_view_permissions() {
+        let posts = CustomTableSchema {
+            name: "posts".into(),
+            ..Default::default()
+        };
+        let rule = "@request.user == author".to_string();
+        let comments = CustomTableSchema {
+            name: "comments".into(),
+            fields: vec![
+                Field {
+                    name: "post".into(),
+                    info: FieldInfo::Relation {
+                        table: "posts".into(),
+                        target: RelationTarget::Single,
+                        min_selected: None,
+                        max_selected: None,
+                        cascade_delete: false,
+                        is_required: false,
+                        is_unique: false,
+                    },
+                },
+                Field {
+                    name: "author".into(),
+                    info: FieldInfo::Boolean,
+                },
+                Field {
+                    name: "email".into(),
+                    info: FieldInfo::Boolean,
+                },
+            ],
+            permissions: Permissions {
+                view: Some(rule.clone()),
+                fields: HashMap::from([(
+                    "email".to_string(),
+                    FieldPermissions {
+                        view: Some(rule),
+                        update: None,
+                    },
+                )]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let relations = ReverseRelation::all(&posts, &[comments]);
+        let relation = ReverseRelation::find(&relations, "comments").unwrap();
+        let user = AnyUser {
+            id: "sam".into(),
+            ..Default::default()
+        };
+
+        let expansion = relation.expand(&posts, Some(5), None, Some(&user)).unwrap();
+        let query = expansion.query_builder.to_string(PostgresQueryBuilder);
+        assert!(query.contains(r#""author" = 'sam'"#));
+        assert!(query.contains("email_hidden_key"));
+        assert_eq!(expansion.hidden, vec!["email"]);
+
+        let query = relation
+            .count(&posts, Some(&user))
+            .unwrap()
+            .query_builder
+            .to_string(PostgresQueryBuilder);
+        assert!(query.starts_with("SELECT COUNT(*)"));
+        assert!(query.contains(r#""author" = 'sam'"#));
+
+        let expansion = relation.expand(&posts, None, None, None).unwrap();
+        let query = expansion.query_builder.to_string(PostgresQueryBuilder);
+        assert!(!query.contains("author\" ="));
+        assert!(expansion.hidden.is_empty());
+    }
+}
@@ -0,0 +1,321 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use chrono::DateTime;
+use heck::ToLowerCamelCase;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::{ValidationError, ValidationErrors};
+
+use crate::{error::Error, util};
+
+use super::{
+    hooks::{self, Condition},
+    schema::CustomTableSchema,
+};
+
+/// A constraint spanning several fields of a row
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: RuleKind,
+    pub message: Option<String>,
+    /// Also enforces the rule with a Postgres `CHECK` constraint
+    #[serde(default)]
+    pub check: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum RuleKind {
+    /// `field` must compare to `other` with `operator`, skipped while either is empty
+    Compare {
+        field: String,
+        operator: CompareOperator,
+        other: String,
+    },
+    /// At least one of `fields` must be provided
+    AnyOf { fields: Vec<String> },
+    /// `field` must be provided when every condition matches
+    RequiredIf { field: String, when: Vec<Condition> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum CompareOperator {
+    Equal,
+    NotEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+}
+
+impl CompareOperator {
+    fn matches(&self, ordering: Ordering) -> bool {
+        match self {
+            Self::Equal => ordering == Ordering::Equal,
+            Self::NotEqual => ordering != Ordering::Equal,
+            Self::GreaterThan => ordering == Ordering::Greater,
+            Self::GreaterThanOrEqual => ordering != Ordering::Less,
+            Self::LessThan => ordering == Ordering::Less,
+            Self::LessThanOrEqual => ordering != Ordering::Greater,
+        }
+    }
+
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Self::Equal => "=",
+            Self::NotEqual => "<>",
+            Self::GreaterThan => ">",
+            Self::GreaterThanOrEqual => ">=",
+            Self::LessThan => "<",
+            Self::LessThanOrEqual => "<=",
+        }
+    }
+}
+
+/// Compares dates chronologically and everything else like hooks do
+fn compare(left: &Value, right: &Value) -> Option<Ordering> {
+    if let (Some(left), Some(right)) = (left.as_str(), right.as_str())
+        && let (Ok(left), Ok(right)) = (
+            DateTime::parse_from_rfc3339(left),
+            DateTime::parse_from_rfc3339(right),
+        )
+    {
+        return Some(left.cmp(&right));
+    }
+
+    hooks::compare(left, right)
+}
+
+impl Rule {
+    fn fields(&self) -> Vec<&String> {
+        match &self.kind {
+            RuleKind::Compare { field, other, .. } => vec![field, other],
+            RuleKind::AnyOf { fields } => fields.iter().collect(),
+            RuleKind::RequiredIf { field, when } => {
+                let mut fields = vec![field];
+                fields.extend(when.iter().map(|c| &c.field));
+                fields
+            }
+        }
+    }
+
+    fn constraint_name(&self) -> String {
+        format!("CK_{}", self.name)
+    }
+
+    fn check_sql(&self) -> Option<String> {
+        match &self.kind {
+            RuleKind::Compare {
+                field,
+                operator,
+                other,
+            } => Some(format!(r#""{field}" {} "{other}""#, operator.as_sql())),
+            RuleKind::AnyOf { fields } => Some(
+                fields
+                    .iter()
+                    .map(|f| format!(r#""{f}" IS NOT NULL"#))
+                    .collect::<Vec<_>>()
+                    .join(" OR "),
+            ),
+            RuleKind::RequiredIf { .. } => None,
+        }
+    }
+
+    pub fn add_constraint_query(&self, table: &str) -> Option<String> {
+        if !self.check {
+            return None;
+        }
+
+        self.check_sql().map(|sql| {
+            format!(
+                r#"ALTER TABLE "{table}" ADD CONSTRAINT "{}" CHECK ({sql})"#,
+                self.constraint_name()
+            )
+        })
+    }
+
+    pub fn drop_constraint_query(&self, table: &str) -> Option<String> {
+        self.check.then(|| {
+            format!(
+                r#"ALTER TABLE "{table}" DROP CONSTRAINT IF EXISTS "{}""#,
+                self.constraint_name()
+            )
+        })
+    }
+
+    fn error(&self, code: &str) -> ValidationError {
+        let mut error = util::create_validation_error(code, self.message.clone());
+        error.add_param("rule".into(), &self.name);
+
+        error
+    }
+
+    fn evaluate(&self, payload: &HashMap<String, Value>) -> Vec<(&String, ValidationError)> {
+        let get = |field: &String| payload.get(&field.to_lower_camel_case());
+
+        match &self.kind {
+            RuleKind::Compare {
+                field,
+                operator,
+                other,
+            } => {
+                let (Some(left), Some(right)) = (get(field), get(other)) else {
+                    return vec![];
+                };
+                if hooks::is_empty(Some(left)) || hooks::is_empty(Some(right)) {
+                    return vec![];
+                }
+
+                match compare(left, right) {
+                    Some(ordering) if operator.matches(ordering) => vec![],
+                    _ => vec![(field, self.error("compare"))],
+                }
+            }
+            RuleKind::AnyOf { fields } => {
+                if fields.iter().any(|f| !hooks::is_empty(get(f))) {
+                    return vec![];
+                }
+
+                fields.iter().map(|f| (f, self.error("anyOf"))).collect()
+            }
+            RuleKind::RequiredIf { field, when } => {
+                if !hooks::is_empty(get(field)) || !when.iter().all(|c| c.matches(payload)) {
+                    return vec![];
+                }
+
+                vec![(field, self.error("required"))]
+            }
+        }
+    }
+}
+
+pub fn validate(rules: &[Rule], schema: &CustomTableSchema) -> Result<(), Error> {
+    let name_regex = Regex::new(r"^[a-z][a-z0-9_]*$").unwrap();
+
+    for (idx, rule) in rules.iter().enumerate() {
+        if !name_regex.is_match(&rule.name) {
+            return Err(Error::BadRequest(format!(
+                "Invalid rule name '{}'",
+                rule.name
+            )));
+        }
+        if rules[..idx].iter().any(|r| r.name == rule.name) {
+            return Err(Error::BadRequest(format!(
+                "Duplicate rule name '{}'",
+                rule.name
+            )));
+        }
+        if let Some(field) = rule
+            .fields()
+            .into_iter()
+            .find(|f| !schema.fields.iter().any(|sf| &sf.name == *f))
+        {
+            return Err(Error::BadRequest(format!(
+                "Rule '{}' references unknown field '{field}'",
+                rule.name
+            )));
+        }
+        if rule.check && rule.check_sql().is_none() {
+            return Err(Error::BadRequest(format!(
+                "Rule '{}' can't be backed by a CHECK constraint",
+                rule.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Evaluates every rule against `payload`, adding failures to `errors` under the offending fields
+pub fn check(rules: &[Rule], payload: &HashMap<String, Value>, errors: &mut ValidationErrors) {
+    let payload = payload
+        .iter()
+        .map(|(key, value)| (key.to_lower_camel_case(), value.clone()))
+        .collect::<HashMap<_, _>>();
+
+    for rule in rules {
+        for (field, error) in rule.evaluate(&payload) {
+            errors.add(
+                util::string_to_static_str(field.to_lower_camel_case()),
+                error,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn payload(value: Value) -> HashMap<String, Value> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn compares_fields() {
+        let rules = vec![Rule {
+            name: "ends_after_start".into(),
+            kind: RuleKind::Compare {
+                field: "end_date".into(),
+                operator: CompareOperator::GreaterThan,
+                other: "start_date".into(),
+            },
+            message: None,
+            check: true,
+        }];
+
+        let mut errors = ValidationErrors::new();
+        check(
+            &rules,
+            &payload(json!({
+                "startDate": "2024-05-02T00:00:00Z",
+                "endDate": "2024-05-01T00:00:00Z",
+            })),
+            &mut errors,
+        );
+        assert!(errors.errors().contains_key("endDate"));
+
+        let mut errors = ValidationErrors::new();
+        check(
+            &rules,
+            &payload(json!({ "start_date": "2024-05-01T00:00:00Z" })),
+            &mut errors,
+        );
+        assert!(errors.is_empty());
+
+        assert_eq!(
+            rules[0].add_constraint_query("events"),
+            Some(
+                r#"ALTER TABLE "events" ADD CONSTRAINT "CK_ends_after_start" CHECK ("end_date" > "start_date")"#
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn requires_any_of() {
+        let rules = vec![Rule {
+            name: "contact".into(),
+            kind: RuleKind::AnyOf {
+                fields: vec!["email".into(), "phone".into()],
+            },
+            message: Some("An email or phone number is required".into()),
+            check: false,
+        }];
+
+        let mut errors = ValidationErrors::new();
+        check(&rules, &payload(json!({ "email": "" })), &mut errors);
+        assert_eq!(errors.errors().len(), 2);
+
+        let mut errors = ValidationErrors::new();
+        check(&rules, &payload(json!({ "phone": "555" })), &mut errors);
+        assert!(errors.is_empty());
+    }
+}
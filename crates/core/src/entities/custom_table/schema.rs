@@ -1,13 +1,14 @@
 use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use axum::http::StatusCode;
 use chrono::{DateTime, Utc};
-use sea_query::{enum_def, Expr, PostgresQueryBuilder};
+use sea_query::{enum_def, Expr, Func, PostgresQueryBuilder};
 use serde::{Deserialize, Serialize};
 use tracing::error;
 use tracing_unwrap::ResultExt;
 
 use crate::{entities::Update, error::Error};
 
-use super::{fields::Field, permissions::Permissions};
+use super::{fields::Field, hooks::Hooks, permissions::Permissions, rules::Rule};
 
 #[enum_def]
 #[derive(Debug, Default, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
@@ -19,6 +20,9 @@ pub struct CustomTableSchema {
     pub fields: Vec<Field>,
     #[adrastos(json)]
     pub permissions: Permissions,
+    #[adrastos(json)]
+    pub hooks: Hooks,
+    pub rules: Vec<Rule>,
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
 }
@@ -28,12 +32,68 @@ pub struct UpdateCustomTableSchema {
     pub name: Option<String>,
     pub fields: Option<Vec<Field>>,
     pub permissions: Option<Permissions>,
+    pub hooks: Option<Hooks>,
+    pub rules: Option<Vec<Rule>>,
 }
 
 impl CustomTableSchema {
+    /// Changes on every write, clients send it back to avoid overwriting each other
+    pub fn version(&self) -> DateTime<Utc> {
+        self.updated_at.unwrap_or(self.created_at)
+    }
+
+    /// Like `create`, but runs in the transaction creating the table so both are saved together
+    pub async fn insert(
+        &self,
+        transaction: &deadpool_postgres::Transaction<'_>,
+    ) -> Result<(), Error> {
+        let query = sea_query::Query::insert()
+            .into_table(Self::table())
+            .columns([
+                CustomTableSchemaIden::Id,
+                CustomTableSchemaIden::Name,
+                CustomTableSchemaIden::Fields,
+                CustomTableSchemaIden::Permissions,
+                CustomTableSchemaIden::Hooks,
+                CustomTableSchemaIden::Rules,
+                CustomTableSchemaIden::CreatedAt,
+                CustomTableSchemaIden::UpdatedAt,
+            ])
+            .values_panic([
+                self.id.clone().into(),
+                self.name.clone().into(),
+                self.fields
+                    .iter()
+                    .map(|v| serde_json::to_string(v).unwrap_or_log())
+                    .collect::<Vec<_>>()
+                    .into(),
+                serde_json::to_string(&self.permissions)
+                    .unwrap_or_log()
+                    .into(),
+                serde_json::to_string(&self.hooks).unwrap_or_log().into(),
+                self.rules
+                    .iter()
+                    .map(|v| serde_json::to_string(v).unwrap_or_log())
+                    .collect::<Vec<_>>()
+                    .into(),
+                self.created_at.into(),
+                self.updated_at.into(),
+            ])
+            .to_string(PostgresQueryBuilder);
+
+        transaction.execute(&query, &[]).await.map_err(|e| {
+            error!(error = ?e);
+            Error::InternalServerError("Failed to create custom table schema".into())
+        })?;
+
+        Ok(())
+    }
+
+    /// Only applies if the schema wasn't changed since it was read, failing with a conflict otherwise.
+    /// Runs in the transaction changing the table so both are saved together.
     pub async fn update(
         &self,
-        db: &deadpool_postgres::Pool,
+        transaction: &deadpool_postgres::Transaction<'_>,
         update: UpdateCustomTableSchema,
     ) -> Result<(), Error> {
         let query = sea_query::Query::update()
@@ -59,20 +119,46 @@ impl CustomTableSchema {
                         .map(|p| serde_json::to_string(&p).unwrap())
                         .into(),
                 ),
+                (
+                    CustomTableSchemaIden::Hooks,
+                    update
+                        .hooks
+                        .map(|h| serde_json::to_string(&h).unwrap())
+                        .into(),
+                ),
+                (
+                    CustomTableSchemaIden::Rules,
+                    update
+                        .rules
+                        .map(|v| {
+                            v.into_iter()
+                                .map(|v| serde_json::to_string(&v).unwrap_or_log())
+                                .collect::<Vec<_>>()
+                        })
+                        .into(),
+                ),
                 (CustomTableSchemaIden::UpdatedAt, Some(Utc::now()).into()),
             ]))
             .and_where(Expr::col(CustomTableSchemaIden::Id).eq(self.id.clone()))
+            .and_where(
+                Expr::expr(Func::coalesce([
+                    Expr::col(CustomTableSchemaIden::UpdatedAt).into(),
+                    Expr::col(CustomTableSchemaIden::CreatedAt).into(),
+                ]))
+                .eq(self.version()),
+            )
             .to_string(PostgresQueryBuilder);
 
-        db.get()
-            .await
-            .unwrap_or_log()
-            .execute(&query, &[])
-            .await
-            .map_err(|e| {
-                error!(error = ?e);
-                Error::InternalServerError("Failed to update custom table schema".into())
-            })?;
+        let updated = transaction.execute(&query, &[]).await.map_err(|e| {
+            error!(error = ?e);
+            Error::InternalServerError("Failed to update custom table schema".into())
+        })?;
+        if updated == 0 {
+            return Err(Error::Custom(
+                StatusCode::CONFLICT,
+                "The table was changed since it was read".into(),
+            ));
+        }
 
         Ok(())
     }
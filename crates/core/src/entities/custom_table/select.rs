@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+use super::fields::{Field, FieldInfo};
+
+/// A choice of a select field, along with how clients should present it
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(from = "SelectOptionRepr", rename_all = "camelCase")]
+pub struct SelectOption {
+    pub value: String,
+    pub label: Option<String>,
+    pub color: Option<String>,
+    pub order: i32,
+}
+
+/// Options used to be plain strings, which are still accepted
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SelectOptionRepr {
+    Value(String),
+    Option {
+        value: String,
+        label: Option<String>,
+        color: Option<String>,
+        #[serde(default)]
+        order: i32,
+    },
+}
+
+impl From<SelectOptionRepr> for SelectOption {
+    fn from(repr: SelectOptionRepr) -> Self {
+        match repr {
+            SelectOptionRepr::Value(value) => Self {
+                value,
+                label: None,
+                color: None,
+                order: 0,
+            },
+            SelectOptionRepr::Option {
+                value,
+                label,
+                color,
+                order,
+            } => Self {
+                value,
+                label,
+                color,
+                order,
+            },
+        }
+    }
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn constraint_name(field: &str) -> String {
+    format!("CK_{field}_options")
+}
+
+/// The condition keeping a select column within its options
+pub fn check_sql(field: &Field) -> Option<String> {
+    let FieldInfo::Select {
+        options, single, ..
+    } = &field.info
+    else {
+        return None;
+    };
+    if options.is_empty() {
+        return None;
+    }
+
+    let values = options
+        .iter()
+        .map(|o| quote(&o.value))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Some(match single {
+        true => format!(r#""{}" IN ({values})"#, field.name),
+        false => format!(r#""{}" <@ ARRAY[{values}]::text[]"#, field.name),
+    })
+}
+
+/// Inline constraint for the column definition of a select field
+pub fn column_constraint(field: &Field) -> Option<String> {
+    check_sql(field).map(|sql| {
+        format!(
+            r#"CONSTRAINT "{}" CHECK ({sql})"#,
+            constraint_name(&field.name)
+        )
+    })
+}
+
+pub fn validate(fields: &[Field]) -> Result<(), Error> {
+    for field in fields {
+        let FieldInfo::Select { options, .. } = &field.info else {
+            continue;
+        };
+
+        for (idx, option) in options.iter().enumerate() {
+            if options[..idx].iter().any(|o| o.value == option.value) {
+                return Err(Error::BadRequest(format!(
+                    "Duplicate option '{}' on '{}'",
+                    option.value, field.name
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Queries moving the data of a select column from `old` to `new`. `renames` maps old option
+/// values to new ones and values of removed options are dropped, except on required single
+/// selects where they're kept so the new constraint rejects them. The first queries run
+/// before the column is renamed, the second after.
+pub fn migrate(
+    table: &str,
+    old: &Field,
+    new: &Field,
+    renames: &HashMap<String, String>,
+) -> (Vec<String>, Vec<String>) {
+    let (
+        FieldInfo::Select {
+            options: old_options,
+            single: old_single,
+            ..
+        },
+        FieldInfo::Select {
+            options: new_options,
+            single,
+            is_required,
+            ..
+        },
+    ) = (&old.info, &new.info)
+    else {
+        return (vec![], vec![]);
+    };
+
+    let column = &old.name;
+    let mut before = vec![format!(
+        r#"ALTER TABLE "{table}" DROP CONSTRAINT IF EXISTS "{}""#,
+        constraint_name(column)
+    )];
+
+    match (old_single, single) {
+        (false, true) => before.push(format!(
+            r#"ALTER TABLE "{table}" ALTER COLUMN "{column}" TYPE text USING "{column}"[1]"#
+        )),
+        (true, false) => before.push(format!(
+            r#"ALTER TABLE "{table}" ALTER COLUMN "{column}" TYPE text[] USING CASE WHEN "{column}" IS NULL THEN NULL ELSE ARRAY["{column}"] END"#
+        )),
+        _ => {}
+    }
+
+    for option in old_options {
+        let from = quote(&option.value);
+        let to = match renames.get(&option.value) {
+            Some(to) => Some(to),
+            None if new_options.iter().any(|o| o.value == option.value) => continue,
+            // Rows of a required select can't be left empty, they need a rename
+            None if *single && *is_required => continue,
+            None => None,
+        };
+
+        before.push(match (single, to) {
+            (true, Some(to)) => format!(
+                r#"UPDATE "{table}" SET "{column}" = {} WHERE "{column}" = {from}"#,
+                quote(to)
+            ),
+            (true, None) => {
+                format!(r#"UPDATE "{table}" SET "{column}" = NULL WHERE "{column}" = {from}"#)
+            }
+            (false, Some(to)) => format!(
+                r#"UPDATE "{table}" SET "{column}" = array_replace("{column}", {from}, {})"#,
+                quote(to)
+            ),
+            (false, None) => {
+                format!(r#"UPDATE "{table}" SET "{column}" = array_remove("{column}", {from})"#)
+            }
+        });
+    }
+
+    let after = check_sql(new)
+        .map(|sql| {
+            format!(
+                r#"ALTER TABLE "{table}" ADD CONSTRAINT "{}" CHECK ({sql})"#,
+                constraint_name(&new.name)
+            )
+        })
+        .into_iter()
+        .collect();
+
+    (before, after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(options: &[&str], single: bool) -> Field {
+        required_status(options, single, false)
+    }
+
+    fn required_status(options: &[&str], single: bool, is_required: bool) -> Field {
+        Field {
+            name: "status".into(),
+            info: FieldInfo::Select {
+                options: options
+                    .iter()
+                    .map(|o| SelectOption::from(SelectOptionRepr::Value(o.to_string())))
+                    .collect(),
+                single,
+                min_selected: None,
+                max_selected: None,
+                is_required,
+                is_unique: false,
+            },
+        }
+    }
+
+    #[test]
+    fn parses_plain_options() {
+        let options = serde_json::from_str::<Vec<SelectOption>>(
+            r##"["draft", { "value": "done", "label": "Done", "color": "#0f0", "order": 2 }]"##,
+        )
+        .unwrap();
+
+        assert_eq!(options[0].value, "draft");
+        assert_eq!(options[1].label.as_deref(), Some("Done"));
+        assert_eq!(options[1].order, 2);
+    }
+
+    #[test]
+    fn migrates_options() {
+        let (before, after) = migrate(
+            "tasks",
+            &status(&["todo", "doing", "done"], true),
+            &status(&["todo", "in_progress"], true),
+            &HashMap::from([("doing".to_string(), "in_progress".to_string())]),
+        );
+
+        assert_eq!(
+            before,
+            vec![
+                r#"ALTER TABLE "tasks" DROP CONSTRAINT IF EXISTS "CK_status_options""#,
+                r#"UPDATE "tasks" SET "status" = 'in_progress' WHERE "status" = 'doing'"#,
+                r#"UPDATE "tasks" SET "status" = NULL WHERE "status" = 'done'"#,
+            ]
+        );
+        assert_eq!(
+            after,
+            vec![
+                r#"ALTER TABLE "tasks" ADD CONSTRAINT "CK_status_options" CHECK ("status" IN ('todo', 'in_progress'))"#
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_removed_options_of_required_fields() {
+        let (before, after) = migrate(
+            "tasks",
+            &required_status(&["todo", "done"], true, true),
+            &required_status(&["todo"], true, true),
+            &HashMap::new(),
+        );
+
+        assert_eq!(
+            before,
+            vec![r#"ALTER TABLE "tasks" DROP CONSTRAINT IF EXISTS "CK_status_options""#]
+        );
+        assert_eq!(after.len(), 1);
+    }
+}
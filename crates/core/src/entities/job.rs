@@ -0,0 +1,179 @@
+use std::{collections::HashMap, str::FromStr};
+
+use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use sea_query::{enum_def, Expr, PostgresQueryBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use tracing_unwrap::ResultExt;
+
+use crate::error::Error;
+
+use super::Update;
+
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Job {
+    pub id: String,
+    #[adrastos(unique)]
+    pub name: String,
+    pub schedule: String,
+    #[adrastos(json)]
+    pub action: JobAction,
+    pub enabled: bool,
+    pub last_run_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum JobAction {
+    /// Deletes the rows of a custom table whose columns equal the filter values
+    #[serde(rename_all = "camelCase")]
+    DeleteRows {
+        table: String,
+        #[serde(default)]
+        filter: HashMap<String, String>,
+        older_than: Option<i64>,
+    },
+    /// Deletes refresh token trees that have expired or gone inactive
+    PurgeRefreshTokens,
+    /// Deletes uploads older than `older_than` seconds
+    #[serde(rename_all = "camelCase")]
+    PurgeUploads { older_than: i64 },
+    /// Sends a POST request to the url, signed when a secret is set
+    CallWebhook { url: String, secret: Option<String> },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateJob {
+    pub name: Option<String>,
+    pub schedule: Option<String>,
+    pub action: Option<JobAction>,
+    pub enabled: Option<bool>,
+    pub last_run_at: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    pub fn parse_schedule(schedule: &str) -> Result<Schedule, Error> {
+        Schedule::from_str(schedule)
+            .map_err(|_| Error::BadRequest(format!("Invalid cron expression '{schedule}'")))
+    }
+
+    /// The first scheduled time after the job last ran, if it's already passed
+    pub fn due_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if !self.enabled {
+            return None;
+        }
+
+        Self::parse_schedule(&self.schedule)
+            .ok()?
+            .after(&self.last_run_at.unwrap_or(self.created_at))
+            .next()
+            .filter(|next| next <= &now)
+    }
+
+    pub async fn update(
+        &self,
+        db: &deadpool_postgres::Pool,
+        update: UpdateJob,
+    ) -> Result<(), Error> {
+        let query = sea_query::Query::update()
+            .table(Self::table())
+            .values(Update::create([
+                (JobIden::Name, update.name.into()),
+                (JobIden::Schedule, update.schedule.into()),
+                (
+                    JobIden::Action,
+                    update
+                        .action
+                        .map(|a| serde_json::to_string(&a).unwrap())
+                        .into(),
+                ),
+                (JobIden::Enabled, update.enabled.into()),
+                (JobIden::LastRunAt, update.last_run_at.into()),
+                (JobIden::UpdatedAt, Some(Utc::now()).into()),
+            ]))
+            .and_where(Expr::col(JobIden::Id).eq(self.id.clone()))
+            .to_string(PostgresQueryBuilder);
+
+        db.get()
+            .await
+            .unwrap_or_log()
+            .execute(&query, &[])
+            .await
+            .map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError("Failed to update job".into())
+            })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn job(last_run_at: Option<DateTime<Utc>>) -> Job {
+        Job {
+            id: "job".into(),
+            name: "hourly".into(),
+            schedule: "0 0 * * * *".into(),
+            action: JobAction::PurgeRefreshTokens,
+            enabled: true,
+            last_run_at,
+            created_at: Utc.with_ymd_and_hms(2024, 1, 1, 11, 30, 0).unwrap(),
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn due_at_after_creation() {
+        let due_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        assert_eq!(
+            job(None).due_at(due_at - chrono::Duration::minutes(1)),
+            None
+        );
+        assert_eq!(job(None).due_at(due_at), Some(due_at));
+        assert_eq!(
+            job(None).due_at(due_at + chrono::Duration::hours(3)),
+            Some(due_at)
+        );
+    }
+
+    #[test]
+    fn due_at_after_last_run() {
+        let last_run_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 1, 1, 12, 30, 0).unwrap();
+
+        assert_eq!(job(Some(last_run_at)).due_at(now), None);
+        assert_eq!(
+            job(Some(last_run_at)).due_at(now + chrono::Duration::hours(1)),
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 13, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn due_at_skips_disabled_and_invalid() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+
+        let disabled = Job {
+            enabled: false,
+            ..job(None)
+        };
+        assert_eq!(disabled.due_at(now), None);
+
+        let invalid = Job {
+            schedule: "every hour".into(),
+            ..job(None)
+        };
+        assert_eq!(invalid.due_at(now), None);
+    }
+}
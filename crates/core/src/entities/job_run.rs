@@ -0,0 +1,19 @@
+use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use chrono::{DateTime, Utc};
+use sea_query::enum_def;
+use serde::{Deserialize, Serialize};
+
+use super::Job;
+
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct JobRun {
+    pub id: String,
+    #[adrastos(find, relation = Job)]
+    pub job_id: String,
+    pub status: String,
+    pub duration: i64,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,35 +1,54 @@
 // TODO(@Xenfo): use `*Iden::Table` instead of Alias::new() once https://github.com/SeaQL/sea-query/issues/533 is fixed
 
-use chrono::Utc;
 use sea_query::{IntoIden, PostgresQueryBuilder, SimpleExpr};
 use secrecy::ExposeSecret;
 
-use crate::{config::Config, db::postgres::DatabaseType, id::Id};
+use crate::{config::Config, db::postgres::DatabaseType};
 
 use self::custom_table::schema::CustomTableSchema;
 
 pub use any_user::*;
+pub use api_key::*;
 pub use connection::*;
+pub use job::*;
+pub use job_run::*;
 pub use passkey::*;
 pub use project::*;
 pub use refresh_token_tree::*;
+pub use role::*;
+pub use signing_key::*;
 pub use system::*;
 pub use system_user::*;
 pub use team::*;
+pub use team_invitation::*;
+pub use team_member::*;
 pub use upload_meta::*;
 pub use user::*;
+pub use user_schema::*;
+pub use webhook::*;
+pub use webhook_delivery::*;
 
 pub mod any_user;
+pub mod api_key;
 pub mod connection;
 pub mod custom_table;
+pub mod job;
+pub mod job_run;
 pub mod passkey;
 pub mod project;
 pub mod refresh_token_tree;
+pub mod role;
+pub mod signing_key;
 pub mod system;
 pub mod system_user;
 pub mod team;
+pub mod team_invitation;
+pub mod team_member;
 pub mod upload_meta;
 pub mod user;
+pub mod user_schema;
+pub mod webhook;
+pub mod webhook_delivery;
 
 #[derive(Debug, Clone)]
 enum Update {
@@ -90,6 +109,9 @@ pub async fn init(db_type: &DatabaseType, db: &deadpool_postgres::Pool, config:
                 Connection::init(),
                 Passkey::init(),
                 RefreshTokenTree::init(),
+                SigningKey::init(),
+                TeamMember::init(),
+                TeamInvitation::init(),
             ]
         }
         DatabaseType::Project(_) => {
@@ -101,6 +123,13 @@ pub async fn init(db_type: &DatabaseType, db: &deadpool_postgres::Pool, config:
                 CustomTableSchema::init(),
                 Passkey::init(),
                 UploadMetadata::init(),
+                Webhook::init(),
+                WebhookDelivery::init(),
+                Job::init(),
+                JobRun::init(),
+                ApiKey::init(),
+                Role::init(),
+                UserRole::init(),
             ]
         }
     };
@@ -158,16 +187,6 @@ pub async fn init(db_type: &DatabaseType, db: &deadpool_postgres::Pool, config:
             }
         }
 
-        Team {
-            id: Id::new().to_string(),
-            name: "Personal Projects".into(),
-            created_at: Utc::now(),
-            ..Default::default()
-        }
-        .create(db)
-        .await
-        .unwrap();
-
         query = query
             .columns([
                 SystemIden::Id,
@@ -1,5 +1,3 @@
-// TODO(@Xenfo): support many browser tabs being open at the same time, currently it'll invalidate the other tabs
-
 use adrastos_macros::{DbCommon, DbQuery, DbSelect};
 use chrono::{DateTime, Duration, Utc};
 use sea_query::{enum_def, Expr, PostgresQueryBuilder};
@@ -13,13 +11,18 @@ use super::{Update, User, UserIden};
 
 #[enum_def]
 #[derive(Debug, Serialize, Deserialize, Clone, DbCommon, DbSelect, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
 pub struct RefreshTokenTree {
     pub id: String,
     #[adrastos(relation = User)]
     pub user_id: String,
     pub inactive_at: DateTime<Utc>,
     pub expires_at: DateTime<Utc>,
+    #[serde(skip_serializing)]
     pub tokens: Vec<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: Option<DateTime<Utc>>,
 }
@@ -45,6 +48,7 @@ impl RefreshTokenTree {
                     RefreshTokenTreeIden::InactiveAt,
                     Some(Utc::now() + Duration::try_days(15).unwrap()).into(),
                 ),
+                (RefreshTokenTreeIden::LastUsedAt, Some(Utc::now()).into()),
                 (RefreshTokenTreeIden::UpdatedAt, Some(Utc::now()).into()),
             ]))
             .and_where(Expr::col(UserIden::Id).eq(self.id.clone()))
@@ -62,4 +66,45 @@ impl RefreshTokenTree {
 
         Ok(())
     }
+
+    /// Revokes every session of a user
+    pub async fn delete_all(db: &deadpool_postgres::Pool, user_id: &str) -> Result<(), Error> {
+        Self::delete_many(db, user_id, None).await
+    }
+
+    /// Revokes every session of a user except `keep_id`
+    pub async fn delete_others(
+        db: &deadpool_postgres::Pool,
+        user_id: &str,
+        keep_id: &str,
+    ) -> Result<(), Error> {
+        Self::delete_many(db, user_id, Some(keep_id)).await
+    }
+
+    async fn delete_many(
+        db: &deadpool_postgres::Pool,
+        user_id: &str,
+        keep_id: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut query = sea_query::Query::delete();
+        query
+            .from_table(Self::table())
+            .and_where(Expr::col(RefreshTokenTreeIden::UserId).eq(user_id));
+        if let Some(keep_id) = keep_id {
+            query.and_where(Expr::col(RefreshTokenTreeIden::Id).ne(keep_id));
+        }
+        let query = query.to_string(PostgresQueryBuilder);
+
+        db.get()
+            .await
+            .unwrap_or_log()
+            .execute(&query, &[])
+            .await
+            .map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError("Failed to delete refresh token trees".into())
+            })?;
+
+        Ok(())
+    }
 }
@@ -0,0 +1,95 @@
+use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use chrono::{DateTime, Utc};
+use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use tracing_unwrap::ResultExt;
+
+use crate::error::Error;
+
+use super::{Update, User};
+
+/// A project-defined role, rules can check for it through `@request.user.roles`
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Role {
+    pub id: String,
+    #[adrastos(find, unique)]
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Grants a [`Role`] to a project user
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct UserRole {
+    pub id: String,
+    #[adrastos(find, relation = User)]
+    pub user_id: String,
+    #[adrastos(find, relation = Role)]
+    pub role_id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateRole {
+    pub name: Option<String>,
+    pub description: Option<Option<String>>,
+}
+
+impl Role {
+    /// Names can be compared against in rules, where spaces are ignored and quotes end strings
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    /// Every role granted to the user
+    pub async fn for_user(db: &deadpool_postgres::Pool, user_id: &str) -> Result<Vec<Self>, Error> {
+        Self::find()
+            .and_where(vec![Expr::col(RoleIden::Id).in_subquery(
+                Query::select()
+                    .column(UserRoleIden::RoleId)
+                    .from(UserRole::table())
+                    .and_where(Expr::col(UserRoleIden::UserId).eq(user_id))
+                    .to_owned(),
+            )])
+            .all(db)
+            .await
+    }
+
+    pub async fn update(
+        &self,
+        db: &deadpool_postgres::Pool,
+        update: UpdateRole,
+    ) -> Result<(), Error> {
+        let query = sea_query::Query::update()
+            .table(Self::table())
+            .values(Update::create([
+                (RoleIden::Name, update.name.into()),
+                (RoleIden::Description, update.description.into()),
+                (RoleIden::UpdatedAt, Some(Utc::now()).into()),
+            ]))
+            .and_where(Expr::col(RoleIden::Id).eq(self.id.clone()))
+            .to_string(PostgresQueryBuilder);
+
+        db.get()
+            .await
+            .unwrap_or_log()
+            .execute(&query, &[])
+            .await
+            .map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError("Failed to update role".into())
+            })?;
+
+        Ok(())
+    }
+}
@@ -4,6 +4,8 @@ use adrastos_macros::DbCommon;
 use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
 use serde::{Deserialize, Serialize};
 
+use super::user_schema::UserSchema;
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum SizeUnit {
@@ -42,6 +44,10 @@ pub struct System {
     pub github_config: Option<OAuth2Config>,
     pub twitter_config: Option<OAuth2Config>,
     pub discord_config: Option<OAuth2Config>,
+
+    pub user_schema: Option<UserSchema>,
+    pub passwordless_config: Option<PasswordlessConfig>,
+    pub verification_config: Option<VerificationConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -62,6 +68,20 @@ pub struct OAuth2Config {
     pub client_secret: String,
 }
 
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordlessConfig {
+    /// Creates an account on the first login of an unknown email
+    pub create_users: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationConfig {
+    /// Blocks users that haven't verified their email from the table and storage APIs
+    pub require_verified: bool,
+}
+
 impl System {
     pub fn get() -> String {
         Query::select()
@@ -80,11 +100,32 @@ impl System {
                 SystemIden::GithubConfig,
                 SystemIden::TwitterConfig,
                 SystemIden::DiscordConfig,
+                SystemIden::UserSchema,
+                SystemIden::PasswordlessConfig,
+                SystemIden::VerificationConfig,
             ])
             .and_where(Expr::col(SystemIden::Id).eq("system"))
             .to_string(PostgresQueryBuilder)
     }
 
+    /// Only reads the version the database was migrated to, which works before the other
+    /// columns exist
+    pub fn get_version() -> String {
+        Query::select()
+            .from(Self::table())
+            .column(SystemIden::PreviousVersion)
+            .and_where(Expr::col(SystemIden::Id).eq("system"))
+            .to_string(PostgresQueryBuilder)
+    }
+
+    pub fn set_version(version: &str) -> String {
+        Query::update()
+            .table(Self::table())
+            .value(SystemIden::PreviousVersion, version)
+            .and_where(Expr::col(SystemIden::Id).eq("system"))
+            .to_string(PostgresQueryBuilder)
+    }
+
     pub fn set(&self) -> String {
         Query::update()
             .table(System::table())
@@ -152,6 +193,27 @@ impl System {
                         .and_then(|v| serde_json::to_string(v).ok())
                         .into(),
                 ),
+                (
+                    SystemIden::UserSchema,
+                    self.user_schema
+                        .as_ref()
+                        .and_then(|v| serde_json::to_string(v).ok())
+                        .into(),
+                ),
+                (
+                    SystemIden::PasswordlessConfig,
+                    self.passwordless_config
+                        .as_ref()
+                        .and_then(|v| serde_json::to_string(v).ok())
+                        .into(),
+                ),
+                (
+                    SystemIden::VerificationConfig,
+                    self.verification_config
+                        .as_ref()
+                        .and_then(|v| serde_json::to_string(v).ok())
+                        .into(),
+                ),
             ])
             .to_string(PostgresQueryBuilder)
     }
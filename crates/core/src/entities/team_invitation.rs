@@ -0,0 +1,48 @@
+use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sea_query::enum_def;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{SystemUser, Team, TeamRole};
+
+/// An emailed invitation to join a team, accepted by the system user with the same email
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct TeamInvitation {
+    pub id: String,
+    #[adrastos(find, relation = Team)]
+    pub team_id: String,
+    pub email: String,
+    #[adrastos(json)]
+    pub role: TeamRole,
+    #[adrastos(find, unique)]
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    #[adrastos(relation = SystemUser)]
+    pub invited_by: String,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl TeamInvitation {
+    /// Generates a plaintext token, only its hash is ever stored
+    pub fn generate_token() -> String {
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect()
+    }
+
+    pub fn hash(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+
+use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use chrono::{DateTime, Utc};
+use sea_query::{enum_def, Expr, PostgresQueryBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use tracing_unwrap::ResultExt;
+
+use crate::error::Error;
+
+use super::{SystemUser, Team, Update};
+
+/// Ordered from the least to the most access, each role can do everything the ones before it can
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TeamRole {
+    Viewer,
+    Developer,
+    Admin,
+    Owner,
+}
+
+impl fmt::Display for TeamRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TeamRole::Viewer => "viewer",
+            TeamRole::Developer => "developer",
+            TeamRole::Admin => "admin",
+            TeamRole::Owner => "owner",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct TeamMember {
+    pub id: String,
+    #[adrastos(find, relation = Team)]
+    pub team_id: String,
+    #[adrastos(find, relation = SystemUser)]
+    pub user_id: String,
+    #[adrastos(json)]
+    pub role: TeamRole,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateTeamMember {
+    pub role: Option<TeamRole>,
+}
+
+impl TeamMember {
+    /// The user's membership of the team, as long as it has at least `role`
+    pub async fn require(
+        db: &deadpool_postgres::Pool,
+        team_id: &str,
+        user_id: &str,
+        role: TeamRole,
+    ) -> Result<Self, Error> {
+        let member = Self::find()
+            .by_team_id(team_id.to_string())
+            .by_user_id(user_id.to_string())
+            .one(db)
+            .await
+            .map_err(|_| Error::Forbidden("You aren't a member of this team".into()))?;
+        member.check(role)?;
+
+        Ok(member)
+    }
+
+    pub fn check(&self, role: TeamRole) -> Result<(), Error> {
+        if self.role < role {
+            return Err(Error::Forbidden(format!(
+                "This requires the {role} role or higher"
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub async fn update(
+        &self,
+        db: &deadpool_postgres::Pool,
+        update: UpdateTeamMember,
+    ) -> Result<(), Error> {
+        let query = sea_query::Query::update()
+            .table(Self::table())
+            .values(Update::create([
+                (
+                    TeamMemberIden::Role,
+                    update
+                        .role
+                        .map(|r| serde_json::to_string(&r).unwrap_or_log())
+                        .into(),
+                ),
+                (TeamMemberIden::UpdatedAt, Some(Utc::now()).into()),
+            ]))
+            .and_where(Expr::col(TeamMemberIden::Id).eq(self.id.clone()))
+            .to_string(PostgresQueryBuilder);
+
+        db.get()
+            .await
+            .unwrap_or_log()
+            .execute(&query, &[])
+            .await
+            .map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError("Failed to update team member".into())
+            })?;
+
+        Ok(())
+    }
+}
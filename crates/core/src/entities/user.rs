@@ -77,6 +77,17 @@ pub struct UpdateUser {
 }
 
 impl User {
+    /// Columns exposed when users are expanded through custom table relations
+    pub const PUBLIC_COLUMNS: [&'static str; 7] = [
+        "id",
+        "first_name",
+        "last_name",
+        "username",
+        "verified",
+        "created_at",
+        "updated_at",
+    ];
+
     pub async fn update(
         &self,
         db: &deadpool_postgres::Pool,
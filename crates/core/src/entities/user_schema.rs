@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+
+use heck::ToLowerCamelCase;
+use sea_query::{
+    Alias, Expr, ForeignKeyAction, Iden, PostgresQueryBuilder, SimpleExpr, Table, TableForeignKey,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::error;
+use validator::{ValidationError, ValidationErrors};
+
+use crate::{error::Error, util};
+
+use super::{
+    custom_table::{
+        computed,
+        fields::{Field, FieldInfo, RelationTarget},
+        schema::CustomTableSchema,
+        CustomTableSelectBuilder,
+    },
+    User,
+};
+
+const BUILTIN_COLUMNS: [&str; 12] = [
+    "id",
+    "first_name",
+    "last_name",
+    "email",
+    "username",
+    "password",
+    "verified",
+    "banned",
+    "mfa_secret",
+    "mfa_backup_codes",
+    "created_at",
+    "updated_at",
+];
+
+/// Extra profile fields a project attaches to its users table
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct UserSchema {
+    pub fields: Vec<Field>,
+}
+
+impl UserSchema {
+    fn as_table(&self) -> CustomTableSchema {
+        CustomTableSchema {
+            name: User::table().to_string(),
+            fields: self.fields.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        for field in &self.fields {
+            if BUILTIN_COLUMNS.contains(&field.name.as_str()) {
+                return Err(Error::BadRequest(format!(
+                    "'{}' is a built-in user field",
+                    field.name
+                )));
+            }
+            if matches!(
+                field.info,
+                FieldInfo::Relation {
+                    target: RelationTarget::Many,
+                    ..
+                }
+            ) {
+                return Err(Error::BadRequest(format!(
+                    "'{}' can't be a many relation",
+                    field.name
+                )));
+            }
+        }
+
+        computed::validate(&self.fields)
+    }
+
+    /// Queries adding or dropping columns to go from this schema to `updated`
+    pub fn migrate(&self, updated: &UserSchema) -> Result<Vec<String>, Error> {
+        let table = User::table();
+        let mut queries = vec![];
+
+        for field in &self.fields {
+            match updated.fields.iter().find(|f| f.name == field.name) {
+                Some(f) if serde_json::to_value(f).ok() != serde_json::to_value(field).ok() => {
+                    return Err(Error::BadRequest(format!(
+                        "'{}' can't be changed, remove it and add it back instead",
+                        field.name
+                    )));
+                }
+                Some(_) => {}
+                None => queries.push(
+                    Table::alter()
+                        .table(table.clone())
+                        .drop_column(Alias::new(&field.name))
+                        .to_string(PostgresQueryBuilder),
+                ),
+            }
+        }
+
+        for field in &updated.fields {
+            if self.fields.iter().any(|f| f.name == field.name) {
+                continue;
+            }
+
+            // Existing users have no value, required fields are enforced when users are written
+            let mut column = field.nullable_column();
+            // The select builder can't read missing booleans
+            if let FieldInfo::Boolean = field.info {
+                column.default(false);
+            }
+
+            let mut alter_query = Table::alter();
+            alter_query.table(table.clone()).add_column(&mut column);
+
+            if let FieldInfo::Relation {
+                table: relation_table,
+                cascade_delete,
+                ..
+            } = &field.info
+            {
+                let mut foreign_key = TableForeignKey::new();
+                if *cascade_delete {
+                    foreign_key.on_delete(ForeignKeyAction::Cascade);
+                }
+
+                alter_query.add_foreign_key(
+                    foreign_key
+                        .name(format!("FK_{}_{}", table.to_string(), field.name))
+                        .from_tbl(table.clone())
+                        .from_col(Alias::new(&field.name))
+                        .to_tbl(Alias::new(relation_table))
+                        .to_col(Alias::new("id"))
+                        .on_update(ForeignKeyAction::Cascade),
+                );
+            }
+
+            queries.push(alter_query.to_string(PostgresQueryBuilder));
+        }
+
+        Ok(queries)
+    }
+
+    /// Validates the camelCase values in `body`, only checking the fields present when `partial`.
+    /// Keys that aren't user fields are rejected.
+    pub fn values(
+        &self,
+        body: &HashMap<String, Value>,
+        partial: bool,
+    ) -> Result<Vec<(Alias, SimpleExpr)>, Error> {
+        let mut errors = ValidationErrors::new();
+        let mut values = vec![];
+
+        for key in body.keys() {
+            if !self
+                .fields
+                .iter()
+                .any(|field| field.name.to_lower_camel_case() == *key)
+            {
+                errors.add(
+                    util::string_to_static_str(key.clone()),
+                    ValidationError::new("unknown"),
+                );
+            }
+        }
+
+        for field in &self.fields {
+            let name = field.name.to_lower_camel_case();
+            let value = body.get(&name);
+            if partial && value.is_none() {
+                continue;
+            }
+
+            match field.validate(value) {
+                Ok(value) => values.push((Alias::new(&field.name), value)),
+                Err(validation_errors) => validation_errors.into_iter().for_each(|error| {
+                    errors.add(util::string_to_static_str(name.clone()), error);
+                }),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::ValidationErrors {
+                message: "Invalid user fields".into(),
+                errors,
+            });
+        }
+
+        Ok(values)
+    }
+
+    pub async fn save(
+        &self,
+        db: &deadpool_postgres::Pool,
+        user_id: &str,
+        values: Vec<(Alias, SimpleExpr)>,
+    ) -> Result<(), Error> {
+        if values.is_empty() {
+            return Ok(());
+        }
+
+        let query = sea_query::Query::update()
+            .table(User::table())
+            .values(values)
+            .and_where(Expr::col(Alias::new("id")).eq(user_id))
+            .to_string(PostgresQueryBuilder);
+
+        db.get()
+            .await
+            .unwrap()
+            .execute(&query, &[])
+            .await
+            .map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError("Failed to update user fields".into())
+            })?;
+
+        Ok(())
+    }
+
+    /// The user's extra field values as a camelCase JSON object
+    pub async fn find(&self, db: &deadpool_postgres::Pool, user_id: &str) -> Result<Value, Error> {
+        if self.fields.is_empty() {
+            return Ok(Value::Object(Default::default()));
+        }
+
+        let mut builder = CustomTableSelectBuilder::from(&self.as_table());
+        let mut data = builder
+            .and_where(vec![Expr::col(Alias::new("id")).eq(user_id)])
+            .join(&[], None)?
+            .finish(db)
+            .await?
+            .as_array()
+            .and_then(|rows| rows.first().cloned())
+            .ok_or(Error::NotFound)?;
+
+        if let Some(data) = data.as_object_mut() {
+            for key in ["id", "createdAt", "updatedAt"] {
+                data.remove(key);
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_fields_allow_existing_users() {
+        let updated = UserSchema {
+            fields: vec![Field {
+                name: "nickname".into(),
+                info: FieldInfo::String {
+                    min_length: None,
+                    max_length: None,
+                    pattern: None,
+                    is_required: true,
+                    is_unique: false,
+                },
+            }],
+        };
+
+        let queries = UserSchema::default().migrate(&updated).unwrap();
+        assert_eq!(queries.len(), 1);
+        assert!(queries[0].contains(r#"ADD COLUMN "nickname""#));
+        assert!(!queries[0].contains("NOT NULL"));
+
+        assert!(updated.values(&HashMap::new(), false).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let schema = UserSchema {
+            fields: vec![Field {
+                name: "nickname".into(),
+                info: FieldInfo::String {
+                    min_length: None,
+                    max_length: None,
+                    pattern: None,
+                    is_required: false,
+                    is_unique: false,
+                },
+            }],
+        };
+        let body = |key: &str| HashMap::from([(key.to_string(), Value::from("Sam"))]);
+
+        assert_eq!(schema.values(&body("nickname"), true).unwrap().len(), 1);
+        assert!(schema.values(&body("banned"), true).is_err());
+        assert!(UserSchema::default()
+            .values(&body("nickname"), true)
+            .is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sea_query::{enum_def, Expr, PostgresQueryBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use tracing_unwrap::ResultExt;
+
+use crate::error::Error;
+
+use super::Update;
+
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<String>,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateWebhook {
+    pub url: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub secret: Option<String>,
+}
+
+impl Webhook {
+    pub fn generate_secret() -> String {
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect()
+    }
+
+    pub async fn update(
+        &self,
+        db: &deadpool_postgres::Pool,
+        update: UpdateWebhook,
+    ) -> Result<(), Error> {
+        let query = sea_query::Query::update()
+            .table(Self::table())
+            .values(Update::create([
+                (WebhookIden::Url, update.url.into()),
+                (WebhookIden::Events, update.events.into()),
+                (WebhookIden::Secret, update.secret.into()),
+                (WebhookIden::UpdatedAt, Some(Utc::now()).into()),
+            ]))
+            .and_where(Expr::col(WebhookIden::Id).eq(self.id.clone()))
+            .to_string(PostgresQueryBuilder);
+
+        db.get()
+            .await
+            .unwrap_or_log()
+            .execute(&query, &[])
+            .await
+            .map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError("Failed to update webhook".into())
+            })?;
+
+        Ok(())
+    }
+}
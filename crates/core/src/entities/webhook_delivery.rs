@@ -0,0 +1,66 @@
+use adrastos_macros::{DbCommon, DbQuery, DbSelect};
+use chrono::{DateTime, Utc};
+use sea_query::{enum_def, Expr, PostgresQueryBuilder};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+use tracing_unwrap::ResultExt;
+
+use crate::error::Error;
+
+use super::{Update, Webhook};
+
+#[enum_def]
+#[derive(Debug, Serialize, Deserialize, Clone, DbSelect, DbCommon, DbQuery)]
+#[serde(rename_all(serialize = "camelCase"))]
+#[adrastos(rename = "webhook_deliveries")]
+pub struct WebhookDelivery {
+    pub id: String,
+    #[adrastos(find, relation = Webhook)]
+    pub webhook_id: String,
+    pub event: String,
+    #[adrastos(json)]
+    pub payload: serde_json::Value,
+    pub status_code: Option<i64>,
+    pub attempts: i64,
+    pub succeeded: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpdateWebhookDelivery {
+    pub status_code: Option<Option<i64>>,
+    pub attempts: Option<i64>,
+    pub succeeded: Option<bool>,
+}
+
+impl WebhookDelivery {
+    pub async fn update(
+        &self,
+        db: &deadpool_postgres::Pool,
+        update: UpdateWebhookDelivery,
+    ) -> Result<(), Error> {
+        let query = sea_query::Query::update()
+            .table(Self::table())
+            .values(Update::create([
+                (WebhookDeliveryIden::StatusCode, update.status_code.into()),
+                (WebhookDeliveryIden::Attempts, update.attempts.into()),
+                (WebhookDeliveryIden::Succeeded, update.succeeded.into()),
+                (WebhookDeliveryIden::UpdatedAt, Some(Utc::now()).into()),
+            ]))
+            .and_where(Expr::col(WebhookDeliveryIden::Id).eq(self.id.clone()))
+            .to_string(PostgresQueryBuilder);
+
+        db.get()
+            .await
+            .unwrap_or_log()
+            .execute(&query, &[])
+            .await
+            .map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError("Failed to update webhook delivery".into())
+            })?;
+
+        Ok(())
+    }
+}
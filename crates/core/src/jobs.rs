@@ -0,0 +1,344 @@
+use std::{fmt, sync::Arc, time::Instant};
+
+use chrono::{DateTime, Duration, Utc};
+use fred::{
+    clients::RedisPool,
+    interfaces::KeysInterface,
+    types::{Expiration, SetOptions},
+};
+use heck::ToSnakeCase;
+use reqwest::header;
+use sea_query::{Alias, Cond, Expr, PostgresQueryBuilder};
+use serde_json::json;
+use tokio::{task::JoinHandle, time};
+use tracing::error;
+
+use crate::{
+    config::Config,
+    db::{
+        postgres::{DatabaseType, Databases},
+        redis,
+    },
+    entities::{
+        Job, JobAction, JobRun, Project, RefreshTokenTree, RefreshTokenTreeIden, UpdateJob,
+        UploadMetadata,
+    },
+    error::Error,
+    id::Id,
+    s3::S3,
+    webhooks,
+};
+
+const LOCK_EXPIRY: i64 = 60 * 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobRunStatus {
+    Succeeded,
+    Failed,
+}
+
+impl fmt::Display for JobRunStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+pub fn start_worker(
+    databases: Arc<Databases>,
+    redis_pool: RedisPool,
+    s3: Arc<S3>,
+    config: Config,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = time::interval(time::Duration::from_secs(30));
+
+        loop {
+            interval.tick().await;
+
+            let system_db = databases.get(&DatabaseType::System, &config).await;
+            let projects = match Project::find().all(&system_db).await {
+                Ok(projects) => projects,
+                Err(e) => {
+                    error!(error = ?e, "Unable to fetch projects");
+                    continue;
+                }
+            };
+
+            for project in projects {
+                let db = databases
+                    .get(&DatabaseType::Project(project.id.clone()), &config)
+                    .await;
+                if !has_jobs_table(&db).await {
+                    continue;
+                }
+
+                let jobs = match Job::find().all(&db).await {
+                    Ok(jobs) => jobs,
+                    Err(e) => {
+                        error!(error = ?e, project.id = project.id, "Unable to fetch jobs");
+                        continue;
+                    }
+                };
+
+                for job in jobs {
+                    let Some(due_at) = job.due_at(Utc::now()) else {
+                        continue;
+                    };
+                    if !acquire_lock(&redis_pool, &config, &job, due_at).await {
+                        continue;
+                    }
+
+                    tokio::spawn(run(
+                        db.clone(),
+                        s3.clone(),
+                        project.id.clone(),
+                        job,
+                        config.allow_private_webhooks,
+                    ));
+                }
+            }
+        }
+    })
+}
+
+/// Projects created before jobs existed don't have the table until they're migrated
+async fn has_jobs_table(db: &deadpool_postgres::Pool) -> bool {
+    let Ok(conn) = db.get().await else {
+        return false;
+    };
+
+    conn.query_one(
+        "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = 'public' AND table_name = 'jobs';",
+        &[],
+    )
+    .await
+    .is_ok_and(|row| row.get::<_, i64>(0) > 0)
+}
+
+fn lock_key(job: &Job, due_at: DateTime<Utc>) -> String {
+    format!("jobs:{}:{}", job.id, due_at.timestamp())
+}
+
+/// Only one instance gets to run a given occurrence of a job
+async fn acquire_lock(
+    redis_pool: &RedisPool,
+    config: &Config,
+    job: &Job,
+    due_at: DateTime<Utc>,
+) -> bool {
+    redis_pool
+        .set::<Option<String>, _, _>(
+            redis::build_key(config, lock_key(job, due_at)),
+            Id::new().to_string(),
+            Some(Expiration::EX(LOCK_EXPIRY)),
+            Some(SetOptions::NX),
+            false,
+        )
+        .await
+        .is_ok_and(|acquired| acquired.is_some())
+}
+
+async fn run(
+    db: Arc<deadpool_postgres::Pool>,
+    s3: Arc<S3>,
+    project_id: String,
+    job: Job,
+    allow_private_webhooks: bool,
+) {
+    let started_at = Utc::now();
+    if let Err(e) = job
+        .update(
+            &db,
+            UpdateJob {
+                last_run_at: Some(started_at),
+                ..Default::default()
+            },
+        )
+        .await
+    {
+        error!(error = ?e, job.id = job.id, "Unable to update job");
+        return;
+    }
+
+    let timer = Instant::now();
+    let result = execute(&db, &s3, &project_id, &job, allow_private_webhooks).await;
+
+    let job_run = JobRun {
+        id: Id::new().to_string(),
+        job_id: job.id.clone(),
+        status: match result {
+            Ok(_) => JobRunStatus::Succeeded,
+            Err(_) => JobRunStatus::Failed,
+        }
+        .to_string(),
+        duration: timer.elapsed().as_millis() as i64,
+        error: result.err().map(|e| format!("{e:?}")),
+        created_at: started_at,
+    };
+
+    if let Err(e) = job_run.create(&db).await {
+        error!(error = ?e, job.id = job.id, "Unable to log job run");
+    }
+}
+
+/// The cutoff for rows and uploads older than `older_than` seconds
+pub fn cutoff(older_than: i64) -> Result<DateTime<Utc>, Error> {
+    Duration::try_seconds(older_than)
+        .filter(|_| older_than > 0)
+        .and_then(|duration| Utc::now().checked_sub_signed(duration))
+        .ok_or_else(|| Error::BadRequest(format!("Invalid age of {older_than} seconds")))
+}
+
+pub async fn execute(
+    db: &deadpool_postgres::Pool,
+    s3: &S3,
+    project_id: &str,
+    job: &Job,
+    allow_private_webhooks: bool,
+) -> Result<(), Error> {
+    match &job.action {
+        JobAction::DeleteRows {
+            table,
+            filter,
+            older_than,
+        } => {
+            // Without any conditions this would empty the table
+            if filter.is_empty() && older_than.is_none() {
+                return Err(Error::BadRequest(
+                    "Deleting rows needs a filter or an age".into(),
+                ));
+            }
+
+            let mut query = sea_query::Query::delete();
+            query.from_table(Alias::new(table));
+
+            filter.iter().for_each(|(field, equals)| {
+                query.and_where(Expr::col(Alias::new(field.to_snake_case())).eq(equals));
+            });
+            if let Some(older_than) = older_than {
+                query.and_where(Expr::col(Alias::new("created_at")).lt(cutoff(*older_than)?));
+            }
+
+            execute_query(db, query.to_string(PostgresQueryBuilder)).await
+        }
+        JobAction::PurgeRefreshTokens => {
+            let now = Utc::now();
+            let query = sea_query::Query::delete()
+                .from_table(RefreshTokenTree::table())
+                .cond_where(
+                    Cond::any()
+                        .add(Expr::col(RefreshTokenTreeIden::ExpiresAt).lt(now))
+                        .add(Expr::col(RefreshTokenTreeIden::InactiveAt).lt(now)),
+                )
+                .to_string(PostgresQueryBuilder);
+
+            execute_query(db, query).await
+        }
+        JobAction::PurgeUploads { older_than } => {
+            let cutoff = cutoff(*older_than)?;
+
+            for upload in UploadMetadata::find()
+                .all(db)
+                .await?
+                .into_iter()
+                .filter(|upload| upload.created_at < cutoff)
+            {
+                s3.delete(format!("{}/{}/{}", project_id, upload.user_id, upload.id))
+                    .await?;
+                upload.delete(db).await?;
+            }
+
+            Ok(())
+        }
+        JobAction::CallWebhook { url, secret } => {
+            // Hosts can start resolving to internal addresses after the job was saved
+            webhooks::validate_url(url, allow_private_webhooks).await?;
+
+            let body = json!({
+                "job": { "id": job.id, "name": job.name },
+                "ranAt": Utc::now(),
+            })
+            .to_string();
+
+            let mut request = reqwest::Client::new()
+                .post(url)
+                .timeout(std::time::Duration::from_secs(10))
+                .header(header::CONTENT_TYPE, "application/json");
+            if let Some(secret) = secret {
+                request = request.header(
+                    "X-Adrastos-Signature",
+                    format!("sha256={}", webhooks::sign(secret, &body)),
+                );
+            }
+
+            let response = request.body(body).send().await.map_err(|e| {
+                error!(error = ?e);
+                Error::InternalServerError(format!("Failed to call {url}"))
+            })?;
+            if !response.status().is_success() {
+                return Err(Error::InternalServerError(format!(
+                    "{url} responded with {}",
+                    response.status()
+                )));
+            }
+
+            Ok(())
+        }
+    }
+}
+
+async fn execute_query(db: &deadpool_postgres::Pool, query: String) -> Result<(), Error> {
+    db.get()
+        .await
+        .unwrap()
+        .execute(&query, &[])
+        .await
+        .map_err(|e| {
+            error!(error = ?e);
+            Error::InternalServerError("Failed to run job query".into())
+        })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn lock_key_is_per_occurrence() {
+        let job = Job {
+            id: "job".into(),
+            name: "purge".into(),
+            schedule: "0 0 * * * *".into(),
+            action: JobAction::PurgeRefreshTokens,
+            enabled: true,
+            last_run_at: None,
+            created_at: Utc::now(),
+            updated_at: None,
+        };
+        let due_at = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        assert_eq!(lock_key(&job, due_at), "jobs:job:1704110400");
+        assert_eq!(lock_key(&job, due_at), lock_key(&job, due_at));
+        assert_ne!(
+            lock_key(&job, due_at),
+            lock_key(&job, due_at + Duration::hours(1))
+        );
+    }
+
+    #[test]
+    fn cutoff_rejects_invalid_ages() {
+        assert!(cutoff(60).is_ok_and(|cutoff| cutoff < Utc::now()));
+        assert!(cutoff(0).is_err());
+        assert!(cutoff(-60).is_err());
+        assert!(cutoff(i64::MAX).is_err());
+    }
+}
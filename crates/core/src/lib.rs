@@ -7,7 +7,9 @@ pub mod entities;
 pub mod error;
 pub mod expiring_map;
 pub mod id;
+pub mod jobs;
 pub mod migrations;
 pub mod s3;
 pub mod url;
 pub mod util;
+pub mod webhooks;
@@ -1,13 +1,33 @@
-use sea_query::TableAlterStatement;
+use sea_query::{ColumnDef, ColumnType, Expr, PostgresQueryBuilder, Table};
 use semver::{BuildMetadata, Prerelease, Version};
 
+use crate::entities::{
+    custom_table::schema::{CustomTableSchema, CustomTableSchemaIden},
+    ApiKey, Job, JobRun, RefreshTokenTree, RefreshTokenTreeIden, Role, SigningKey, System,
+    SystemIden, SystemUserIden, TeamIden, TeamInvitation, TeamMember, TeamMemberIden, UserRole,
+    Webhook, WebhookDelivery,
+};
+
+/// Queries bringing databases created by an older version up to `version`. They're safe to
+/// run twice since fresh databases already have the tables and columns they add.
 pub struct Migration {
     pub version: Version,
-    pub queries: Vec<TableAlterStatement>,
+    /// Run against the system database
+    pub system: Vec<String>,
+    /// Run against every project database
+    pub project: Vec<String>,
 }
 
 pub struct Migrations(Vec<Migration>);
 
+/// Adds a nullable text column to the `system` table, which both kinds of databases have
+fn system_column(column: SystemIden) -> String {
+    Table::alter()
+        .table(System::table())
+        .add_column_if_not_exists(ColumnDef::new(column).string())
+        .to_string(PostgresQueryBuilder)
+}
+
 impl Default for Migrations {
     fn default() -> Self {
         Self::new()
@@ -27,13 +47,115 @@ impl Migrations {
                 build: BuildMetadata::EMPTY,
             },
             vec![],
+            vec![],
+        );
+
+        let mut system = vec![];
+        let mut project = vec![];
+
+        // Profile fields of project users
+        for queries in [&mut system, &mut project] {
+            queries.push(system_column(SystemIden::UserSchema));
+        }
+
+        // Record hooks of custom tables
+        project.push(
+            Table::alter()
+                .table(CustomTableSchema::table())
+                .add_column_if_not_exists(
+                    ColumnDef::new(CustomTableSchemaIden::Hooks)
+                        .json_binary()
+                        .not_null()
+                        .default(Expr::cust("'{}'::jsonb")),
+                )
+                .to_string(PostgresQueryBuilder),
+        );
+
+        // Outgoing webhooks
+        project.extend([Webhook::init(), WebhookDelivery::init()]);
+
+        // Scheduled jobs
+        project.extend([Job::init(), JobRun::init()]);
+
+        // Validation rules of custom tables
+        project.push(
+            Table::alter()
+                .table(CustomTableSchema::table())
+                .add_column_if_not_exists(
+                    ColumnDef::new(CustomTableSchemaIden::Rules)
+                        .array(ColumnType::String(None))
+                        .not_null()
+                        .default(vec![] as Vec<String>),
+                )
+                .to_string(PostgresQueryBuilder),
+        );
+
+        // Passwordless login settings
+        for queries in [&mut system, &mut project] {
+            queries.push(system_column(SystemIden::PasswordlessConfig));
+        }
+
+        // Project API keys
+        project.push(ApiKey::init());
+
+        // Asymmetric token signing keys
+        system.push(SigningKey::init());
+
+        // Device metadata of sessions
+        let session_columns = Table::alter()
+            .table(RefreshTokenTree::table())
+            .add_column_if_not_exists(ColumnDef::new(RefreshTokenTreeIden::UserAgent).string())
+            .add_column_if_not_exists(ColumnDef::new(RefreshTokenTreeIden::IpAddress).string())
+            .add_column_if_not_exists(
+                ColumnDef::new(RefreshTokenTreeIden::LastUsedAt).timestamp_with_time_zone(),
+            )
+            .to_string(PostgresQueryBuilder);
+        for queries in [&mut system, &mut project] {
+            queries.push(session_columns.clone());
+        }
+
+        // Email verification settings
+        for queries in [&mut system, &mut project] {
+            queries.push(system_column(SystemIden::VerificationConfig));
+        }
+
+        // Project roles
+        project.extend([Role::init(), UserRole::init()]);
+
+        // Team members and invitations, teams made before they had members are owned by the
+        // earliest system user
+        system.extend([
+            TeamMember::init(),
+            TeamInvitation::init(),
+            format!(
+                r#"INSERT INTO "{members}" ("id", "team_id", "user_id", "role") SELECT substr(md5(random()::text || "{teams}"."id"), 1, 20), "{teams}"."id", "owner"."id", '"owner"'::jsonb FROM "{teams}", (SELECT "id" FROM "{users}" ORDER BY "created_at" LIMIT 1) AS "owner" WHERE NOT EXISTS (SELECT 1 FROM "{members}" WHERE "{members}"."team_id" = "{teams}"."id")"#,
+                members = TeamMemberIden::Table,
+                teams = TeamIden::Table,
+                users = SystemUserIden::Table,
+            ),
+        ]);
+
+        migrations.add(
+            Version {
+                major: 0,
+                minor: 2,
+                patch: 0,
+                pre: Prerelease::EMPTY,
+                build: BuildMetadata::EMPTY,
+            },
+            system,
+            project,
         );
 
         migrations
     }
 
-    fn add(&mut self, version: Version, queries: Vec<TableAlterStatement>) -> &mut Self {
-        self.0.push(Migration { version, queries });
+    fn add(&mut self, version: Version, system: Vec<String>, project: Vec<String>) -> &mut Self {
+        self.0.push(Migration {
+            version,
+            system,
+            project,
+        });
         self
     }
 
@@ -50,3 +172,33 @@ impl Migrations {
         targets
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_migrations() {
+        let migrations = Migrations::all_from("0.1.0");
+
+        assert_eq!(migrations.len(), 1);
+        for queries in [&migrations[0].system, &migrations[0].project] {
+            assert!(queries
+                .iter()
+                .any(|q| q.contains(r#"ADD COLUMN IF NOT EXISTS "user_schema""#)));
+        }
+        assert!(migrations[0]
+            .project
+            .iter()
+            .any(|q| q.contains(r#"ADD COLUMN IF NOT EXISTS "hooks""#)));
+        assert!(migrations[0]
+            .system
+            .iter()
+            .any(|q| q.contains(r#"CREATE TABLE IF NOT EXISTS "team_members""#)));
+        assert!(migrations[0]
+            .system
+            .iter()
+            .any(|q| q.contains(r#"INSERT INTO "team_members""#) && q.contains(r#"FROM "users""#)));
+        assert!(Migrations::all_from("0.2.0").is_empty());
+    }
+}
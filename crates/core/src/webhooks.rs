@@ -0,0 +1,299 @@
+use std::{fmt, net::IpAddr, time::Duration};
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{header, Url};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::{net, time};
+use tracing::{error, warn};
+
+use crate::{
+    entities::{UpdateWebhookDelivery, Webhook, WebhookDelivery},
+    error::Error,
+    id::Id,
+};
+
+const MAX_ATTEMPTS: i64 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookEvent {
+    RowCreate,
+    RowUpdate,
+    RowDelete,
+    AuthRegister,
+    AuthLogin,
+    StorageUpload,
+    StorageDelete,
+}
+
+impl fmt::Display for WebhookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::RowCreate => "row.create",
+            Self::RowUpdate => "row.update",
+            Self::RowDelete => "row.delete",
+            Self::AuthRegister => "auth.register",
+            Self::AuthLogin => "auth.login",
+            Self::StorageUpload => "storage.upload",
+            Self::StorageDelete => "storage.delete",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl TryFrom<&str> for WebhookEvent {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        [
+            Self::RowCreate,
+            Self::RowUpdate,
+            Self::RowDelete,
+            Self::AuthRegister,
+            Self::AuthLogin,
+            Self::StorageUpload,
+            Self::StorageDelete,
+        ]
+        .into_iter()
+        .find(|event| event.to_string() == value)
+        .ok_or_else(|| Error::BadRequest(format!("Invalid webhook event '{value}'")))
+    }
+}
+
+/// Only allows http(s) URLs, and unless `allow_private` is set, hosts that don't resolve to
+/// loopback, link-local or private addresses.
+pub async fn validate_url(url: &str, allow_private: bool) -> Result<(), Error> {
+    let invalid = |reason: &str| Error::BadRequest(format!("Invalid webhook URL: {reason}"));
+
+    let url = Url::parse(url).map_err(|_| invalid("not a valid URL"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(invalid("only http and https are allowed"));
+    }
+
+    let host = url.host_str().ok_or_else(|| invalid("missing host"))?;
+    if allow_private {
+        return Ok(());
+    }
+
+    let addresses = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => vec![ip],
+        Err(_) => net::lookup_host((host, url.port_or_known_default().unwrap_or(80)))
+            .await
+            .map_err(|_| invalid("host couldn't be resolved"))?
+            .map(|address| address.ip())
+            .collect(),
+    };
+    if addresses.iter().any(is_internal) {
+        return Err(invalid("internal addresses aren't allowed"));
+    }
+
+    Ok(())
+}
+
+fn is_internal(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+        }
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local (fc00::/7) and link-local (fe80::/10)
+                || ip.segments()[0] & 0xfe00 == 0xfc00
+                || ip.segments()[0] & 0xffc0 == 0xfe80
+                || ip
+                    .to_ipv4_mapped()
+                    .is_some_and(|ip| is_internal(&IpAddr::V4(ip)))
+        }
+    }
+}
+
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC can take a key of any size");
+    mac.update(body.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn backoff(attempt: i64) -> Duration {
+    Duration::from_secs(2_u64.pow(attempt as u32))
+}
+
+pub async fn send(
+    url: &str,
+    secret: &str,
+    delivery_id: &str,
+    event: &WebhookEvent,
+    body: &str,
+) -> Result<u16, reqwest::Error> {
+    reqwest::Client::new()
+        .post(url)
+        .timeout(Duration::from_secs(10))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("X-Adrastos-Event", event.to_string())
+        .header("X-Adrastos-Delivery", delivery_id)
+        .header(
+            "X-Adrastos-Signature",
+            format!("sha256={}", sign(secret, body)),
+        )
+        .body(body.to_string())
+        .send()
+        .await
+        .map(|res| res.status().as_u16())
+}
+
+pub fn dispatch(db: &deadpool_postgres::Pool, event: WebhookEvent, data: Value) {
+    let db = db.clone();
+
+    tokio::spawn(async move {
+        let webhooks = match Webhook::find().all(&db).await {
+            Ok(webhooks) => webhooks,
+            Err(e) => {
+                error!(error = ?e, "Unable to fetch webhooks");
+                return;
+            }
+        };
+
+        webhooks
+            .into_iter()
+            .filter(|webhook| webhook.events.contains(&event.to_string()))
+            .for_each(|webhook| {
+                tokio::spawn(deliver(db.clone(), webhook, event.clone(), data.clone()));
+            });
+    });
+}
+
+async fn deliver(db: deadpool_postgres::Pool, webhook: Webhook, event: WebhookEvent, data: Value) {
+    let id = Id::new().to_string();
+    let delivery = WebhookDelivery {
+        id: id.clone(),
+        webhook_id: webhook.id.clone(),
+        event: event.to_string(),
+        payload: json!({
+            "id": id,
+            "event": event.to_string(),
+            "createdAt": Utc::now(),
+            "data": data,
+        }),
+        status_code: None,
+        attempts: 0,
+        succeeded: false,
+        created_at: Utc::now(),
+        updated_at: None,
+    };
+
+    if let Err(e) = delivery.create(&db).await {
+        error!(error = ?e, webhook.id = webhook.id, "Unable to log webhook delivery");
+        return;
+    }
+
+    let body = delivery.payload.to_string();
+    for attempt in 1..=MAX_ATTEMPTS {
+        let status_code = send(&webhook.url, &webhook.secret, &delivery.id, &event, &body)
+            .await
+            .ok();
+        let succeeded = status_code.is_some_and(|code| (200..300).contains(&code));
+
+        let _ = delivery
+            .update(
+                &db,
+                UpdateWebhookDelivery {
+                    status_code: Some(status_code.map(i64::from)),
+                    attempts: Some(attempt),
+                    succeeded: Some(succeeded),
+                },
+            )
+            .await;
+
+        if succeeded {
+            return;
+        }
+        if attempt < MAX_ATTEMPTS {
+            time::sleep(backoff(attempt)).await;
+        }
+    }
+
+    warn!(
+        webhook.id = webhook.id,
+        delivery.id = delivery.id,
+        "Webhook delivery failed after {MAX_ATTEMPTS} attempts"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn signature() {
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[tokio::test]
+    async fn validates_urls() {
+        assert!(validate_url("https://93.184.216.34/hook", false)
+            .await
+            .is_ok());
+        assert!(validate_url("http://127.0.0.1:8000", true).await.is_ok());
+
+        for url in [
+            "not a url",
+            "ftp://93.184.216.34",
+            "http://127.0.0.1:8000",
+            "http://10.0.0.1",
+            "http://192.168.1.1",
+            "http://169.254.169.254/latest/meta-data",
+            "http://0.0.0.0",
+            "http://[::1]",
+            "http://[fd00::1]",
+            "http://[::ffff:127.0.0.1]",
+        ] {
+            assert!(validate_url(url, false).await.is_err(), "{url}");
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_signed_payload() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut buffer = vec![0; 4096];
+            let read = stream.read(&mut buffer).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+
+            String::from_utf8_lossy(&buffer[..read]).to_lowercase()
+        });
+
+        let body = r#"{"data":null}"#;
+        let status = send(&url, "secret", "delivery", &WebhookEvent::RowCreate, body).await;
+        let request = server.await.unwrap();
+
+        assert_eq!(status.ok(), Some(204));
+        assert!(request.contains("x-adrastos-event: row.create"));
+        assert!(request.contains(&format!(
+            "x-adrastos-signature: sha256={}",
+            sign("secret", body)
+        )));
+    }
+}
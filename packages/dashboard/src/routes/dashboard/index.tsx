@@ -1,10 +1,31 @@
-import { createFileRoute, redirect } from '@tanstack/react-router';
+import { zodResolver } from '@hookform/resolvers/zod';
+import { createFileRoute, redirect, useRouter } from '@tanstack/react-router';
+import { useForm } from 'react-hook-form';
+import { z } from 'zod';
 
-import { teamsQueryOptions } from '~/hooks';
+import {
+  Button,
+  Card,
+  CardContent,
+  CardDescription,
+  CardHeader,
+  CardTitle,
+  Form,
+  FormControl,
+  FormField,
+  FormItem,
+  FormLabel,
+  FormMessage,
+  Input,
+} from '~/components';
+import { teamsQueryOptions, useCreateTeamMutation } from '~/hooks';
 
 export const Route = createFileRoute('/dashboard/')({
+  component: NoTeamsComponent,
   beforeLoad: async ({ context: { client, queryClient } }) => {
     const teams = await queryClient.ensureQueryData(teamsQueryOptions(client));
+    if (teams.length === 0) return;
+
     throw redirect({
       to: '/dashboard/teams/$teamId',
       params: { teamId: teams[0].id },
@@ -12,3 +33,67 @@ export const Route = createFileRoute('/dashboard/')({
     });
   },
 });
+
+const formSchema = z.object({
+  name: z.string().min(1, { message: 'Name is required' }),
+});
+
+// Users who left or were removed from all their teams land here
+function NoTeamsComponent() {
+  const router = useRouter();
+
+  const { mutateAsync } = useCreateTeamMutation();
+
+  const form = useForm<z.infer<typeof formSchema>>({
+    resolver: zodResolver(formSchema),
+    defaultValues: {
+      name: '',
+    },
+  });
+
+  return (
+    <div className="flex w-full flex-col items-center pt-14">
+      <Card className="w-full max-w-md">
+        <CardHeader>
+          <CardTitle>Create a team</CardTitle>
+          <CardDescription>
+            You aren't a member of any team yet. Create one to start adding
+            projects, or ask a team owner to invite you.
+          </CardDescription>
+        </CardHeader>
+        <CardContent>
+          <Form {...form}>
+            <form
+              onSubmit={(e) =>
+                void form.handleSubmit(async (values) => {
+                  await mutateAsync(values.name);
+                  await router.invalidate();
+                })(e)
+              }
+            >
+              <div className="space-y-5">
+                <FormField
+                  control={form.control}
+                  name="name"
+                  render={({ field }) => (
+                    <FormItem className="w-full">
+                      <FormLabel>Name</FormLabel>
+                      <FormControl>
+                        <Input placeholder="Name" {...field} />
+                      </FormControl>
+                      <FormMessage />
+                    </FormItem>
+                  )}
+                />
+
+                <Button type="submit" className="w-full">
+                  Create team
+                </Button>
+              </div>
+            </form>
+          </Form>
+        </CardContent>
+      </Card>
+    </div>
+  );
+}